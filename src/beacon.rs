@@ -1,5 +1,5 @@
 use crate::serde::*;
-use crate::{DevAddr, Sf};
+use crate::Sf;
 use crc_0x8810::CRC_16_LORA;
 use modular_bitfield::prelude::*;
 
@@ -195,10 +195,3 @@ pub const T_BEACON_DELAY: embedded_time::duration::Microseconds =
 
 pub const BEACON_PERIOD: embedded_time::duration::Seconds =
     embedded_time::duration::Seconds(128u32);
-
-/*
-// what is NbChannel
-fn frequency_hopping_beacon_channel(dev_addr: DevAddr, beacon_time: u32) -> u32 {
-    dev_addr.addr + (beacon_time / BEACON_PERIOD) % nb_channel
-}
-    */
@@ -0,0 +1,517 @@
+//! Class B beacon synchronization and ping slots
+//!
+//! Gateways broadcast a beacon at the start of every [`BEACON_PERIOD`] of GPS time. To find the
+//! first one the end-device needs an approximate GPS time, for example from `DeviceTimeAns`
+//! ([`EndDevice::device_time`]), which is given to [`EndDevice::start_class_b`]. Once a beacon is
+//! received the device is locked on, and the following beacons are expected a whole number of
+//! beacon periods after it. Beacon windows are widened to account for the inaccuracy of the time
+//! they were computed from and for the drift of the local clock since then
+//! ([`ClassB::clock_drift_ppm`]).
+//!
+//! When a beacon is missed, the device keeps operating in Class B from the timing of the last
+//! beacon received for up to [`BEACONLESS_OPERATION`], its windows widening as the clock drifts,
+//! and then falls back to Class A.
+//!
+//! While locked on, the device also listens in a ping slot every `2^ping_slot_periodicity`
+//! seconds ([`Parameters::ping_slot_periodicity`]), at a pseudo-random offset ([`ping_offset`])
+//! from the start of each beacon period which the Network Server computes the same way. Multicast
+//! groups with [`PingSlots`] get their own ping slots, from their McAddr.
+//!
+//! [`EndDevice::next_action`] requests beacon windows with [`Action::ReceiveBeacon`] and ping slot
+//! windows with [`Action::ReceivePingSlot`] between Class A exchanges, beacons taking precedence.
+//!
+//! [`Action::ReceiveBeacon`]: crate::state::Action::ReceiveBeacon
+//! [`Action::ReceivePingSlot`]: crate::state::Action::ReceivePingSlot
+//! [`Parameters::ping_slot_periodicity`]: crate::Parameters::ping_slot_periodicity
+
+use aes::Aes128;
+use core::time::Duration;
+use embedded_time::{Clock, Instant};
+use generic_array::GenericArray;
+
+use crate::beacon::{self, Beacon};
+use crate::parameters::{AnyBand, Band, Modulation};
+use crate::state::Event;
+use crate::{instant_add, mac, BatteryLevel, DataRate, DevAddr, DeviceClass, EndDevice};
+use crate::{Frequency, MessageRecvMeta, Sf};
+
+/// Time between the start of consecutive beacons, [`beacon::BEACON_PERIOD`]
+pub const BEACON_PERIOD: Duration = Duration::from_secs(beacon::BEACON_PERIOD.0 as u64);
+
+/// Delay between the start of a beacon period and the beacon transmission,
+/// [`beacon::T_BEACON_DELAY`]
+pub const T_BEACON_DELAY: Duration = Duration::from_micros(beacon::T_BEACON_DELAY.0 as u64);
+
+/// Time before each beacon in which no ping slots are scheduled. Uplinks are held back so that
+/// their receive windows end before it.
+pub const BEACON_GUARD: Duration = Duration::from_secs(3);
+
+/// Time after the start of a beacon period in which no ping slots are scheduled, as it is used
+/// by the beacon
+pub const BEACON_RESERVED: Duration = Duration::from_millis(2_120);
+
+/// Length of a ping slot, the unit of ping slot offsets
+pub const PING_SLOT_LEN: Duration = Duration::from_millis(30);
+
+/// Number of ping slots in a beacon period, between [`BEACON_RESERVED`] and [`BEACON_GUARD`]
+pub const PING_SLOTS: u16 = 4096;
+
+/// How long Class B operation continues without receiving beacons
+pub const BEACONLESS_OPERATION: Duration = Duration::from_secs(120 * 60);
+
+/// Preamble length of beacons, in symbols
+pub const BEACON_PREAMBLE: u16 = 10;
+
+/// Preamble length of downlinks, in symbols
+pub const DOWNLINK_PREAMBLE: u16 = 8;
+
+/// Worst case accuracy of the time in `DeviceTimeAns`
+pub const DEVICE_TIME_ACCURACY: Duration = Duration::from_millis(100);
+
+/// GPS time (time since the GPS epoch, 1980-01-06 00:00:00 UTC) at a local instant
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub struct GpsTime<C: Clock> {
+    pub local: Instant<C>,
+    /// GPS time at `local`. Only the low 32 bits of the seconds matter, as beacons don't carry
+    /// the others.
+    pub gps: Duration,
+    /// How far `gps` may be from the actual GPS time
+    pub accuracy: Duration,
+}
+
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for GpsTime<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for GpsTime<C> {}
+
+impl<C: Clock> GpsTime<C> {
+    /// Local instant at GPS time `gps`, `None` if it is before `self.gps`
+    pub fn local_at(&self, gps: Duration) -> Option<Instant<C>> {
+        instant_add(self.local, gps.checked_sub(self.gps)?)
+    }
+}
+
+/// Where the end-device is in synchronizing to beacons
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub enum ClassBState<C: Clock> {
+    /// Class B isn't in use
+    Off,
+    /// Searching for a beacon, expected at the times given by an approximate GPS time
+    Acquiring { time: GpsTime<C> },
+    /// Synchronized to the beacons, `beacon` being the time given by the last one received
+    Locked { beacon: GpsTime<C> },
+    /// The last beacon expected wasn't received, windows are still scheduled from the time given
+    /// by the last one received until [`BEACONLESS_OPERATION`] after it
+    Beaconless { beacon: GpsTime<C> },
+}
+
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for ClassBState<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for ClassBState<C> {}
+
+/// A receive window scheduled relative to the beacons
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub struct Window<C: Clock> {
+    /// Start listening at this time
+    pub start: Instant<C>,
+    /// Stop listening if no frame has started this long after `start`
+    pub timeout: Duration,
+    pub frequency: Frequency,
+    pub data_rate: DataRate,
+}
+
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for Window<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for Window<C> {}
+
+/// A Class B window requested by [`EndDevice::next_action`] that the radio hasn't reported on yet
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub enum Receiving<C: Clock> {
+    Beacon(Window<C>),
+    PingSlot(Window<C>),
+}
+
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for Receiving<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for Receiving<C> {}
+
+/// Class B state of an [`EndDevice`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct ClassB<C: Clock> {
+    pub state: ClassBState<C>,
+    pub receiving: Option<Receiving<C>>,
+    /// Start of the last window the radio reported on, windows starting earlier aren't opened
+    /// again
+    pub last_window: Option<Instant<C>>,
+    /// Worst case drift of the local clock, in parts per million, used to widen windows
+    ///
+    /// Default: 20
+    pub clock_drift_ppm: u32,
+    /// Set by `BeaconFreqReq`, used instead of the band's beacon channels
+    pub beacon_frequency: Option<Frequency>,
+    /// Set by `PingSlotChannelReq`, used instead of the band's ping slot channels
+    pub ping_slot_frequency: Option<Frequency>,
+    /// Set by `PingSlotChannelReq`, used instead of the band's ping slot data rate
+    pub ping_slot_data_rate: Option<DataRate>,
+}
+
+impl<C: Clock> Default for ClassB<C> {
+    fn default() -> Self {
+        Self {
+            state: ClassBState::Off,
+            receiving: None,
+            last_window: None,
+            clock_drift_ppm: 20,
+            beacon_frequency: None,
+            ping_slot_frequency: None,
+            ping_slot_data_rate: None,
+        }
+    }
+}
+
+impl<C: Clock> ClassB<C> {
+    /// How much windows `elapsed` after `time` must be widened on each side
+    pub fn window_widening(&self, time: &GpsTime<C>, elapsed: Duration) -> Duration {
+        let drift_ns = elapsed.as_nanos() * u128::from(self.clock_drift_ppm) / 1_000_000;
+        time.accuracy + Duration::from_nanos(drift_ns as u64)
+    }
+
+    /// Should a window from `start` to `end` be opened at `now`?
+    fn window_pending(&self, now: Instant<C>, start: Instant<C>, end: Instant<C>) -> bool {
+        end > now && self.last_window.is_none_or(|last| start > last)
+    }
+}
+
+/// Ping slot parameters of the end-device or of a [`MulticastGroup`]
+///
+/// [`MulticastGroup`]: crate::MulticastGroup
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct PingSlots {
+    /// A ping slot every `2^periodicity` seconds, see [`ping_period`]
+    pub periodicity: u8,
+    /// `None` to use the band's ping slot channels ([`Band::ping_slot_frequency`])
+    pub frequency: Option<Frequency>,
+    pub data_rate: DataRate,
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassBError {
+    /// The device hasn't joined or been activated by personalization
+    NotActivated,
+    /// No band is selected, or the band isn't implemented
+    NoBand,
+}
+
+/// pingPeriod: the number of [`PING_SLOT_LEN`] slots between ping slots, for a ping slot every
+/// `2^periodicity` seconds
+pub fn ping_period(periodicity: u8) -> u16 {
+    1 << (5 + periodicity.min(7))
+}
+
+/// pingOffset: the slot of the first ping slot of `dev_addr` in the beacon period starting at
+/// `beacon_time` (GPS seconds), when ping slots are `ping_period` slots apart
+pub fn ping_offset(beacon_time: u32, dev_addr: DevAddr, ping_period: u16) -> u16 {
+    // Rand = aes128_encrypt(16 x 0x00, BeaconTime | DevAddr | pad16)
+    let mut block = [0u8; 16];
+    block[..4].copy_from_slice(&beacon_time.to_le_bytes());
+    block[4..8].copy_from_slice(&dev_addr.addr.to_le_bytes());
+    let aes = <Aes128 as cipher::KeyInit>::new_from_slice(&[0; 16]).unwrap();
+    cipher::BlockEncrypt::encrypt_block(&aes, GenericArray::from_mut_slice(&mut block));
+    u16::from_le_bytes([block[0], block[1]]) % ping_period
+}
+
+/// Spreading factor of beacons sent with `modulation`, which determines their layout
+fn beacon_sf(modulation: &Modulation) -> Option<Sf> {
+    match *modulation {
+        Modulation::Lora { sf: 8, .. } => Some(Sf::_8),
+        Modulation::Lora { sf: 9, .. } => Some(Sf::_9),
+        Modulation::Lora { sf: 10, .. } => Some(Sf::_10),
+        Modulation::Lora { sf: 11, .. } => Some(Sf::_11),
+        Modulation::Lora { sf: 12, .. } => Some(Sf::_12),
+        Modulation::Lora { .. }
+        | Modulation::Fsk { .. }
+        | Modulation::LrFhss { .. }
+        | Modulation::Rfu => None,
+    }
+}
+
+impl<C, P, B> EndDevice<C, P, B>
+where
+    C: Clock,
+    P: mac::ProprietaryHandler,
+    B: BatteryLevel,
+{
+    /// Start searching for beacons, `time` being an approximate GPS time such as
+    /// [`EndDevice::device_time`]
+    ///
+    /// [`EndDevice::handle_event`] returns [`Event::BeaconLocked`] once a beacon is received, from
+    /// which point the device operates in Class B. Class C is stopped.
+    pub fn start_class_b(&mut self, time: GpsTime<C>) -> Result<(), ClassBError> {
+        if self.activation.is_none() {
+            return Err(ClassBError::NotActivated);
+        }
+        self.band().ok_or(ClassBError::NoBand)?;
+        self.stop_class_c();
+        self.class_b.state = ClassBState::Acquiring { time };
+        self.class_b.receiving = None;
+        self.class_b.last_window = None;
+        Ok(())
+    }
+
+    /// Stop receiving beacons, returning to Class A
+    pub fn stop_class_b(&mut self) {
+        self.class_b.state = ClassBState::Off;
+        self.class_b.receiving = None;
+        if matches!(self.class, DeviceClass::B) {
+            self.class = DeviceClass::A;
+        }
+    }
+
+    /// The first beacon window which hasn't ended at `now`, along with the time (GPS seconds) of
+    /// the beacon expected in it. `None` if Class B isn't started, or if the beacon would be
+    /// more than [`BEACONLESS_OPERATION`] after the last one received.
+    pub fn next_beacon_window(&self, now: Instant<C>) -> Option<(u32, Window<C>)> {
+        let (time, limit) = match self.class_b.state {
+            ClassBState::Off => return None,
+            ClassBState::Acquiring { time } => (time, None),
+            ClassBState::Locked { beacon } | ClassBState::Beaconless { beacon } => {
+                (beacon, Some(BEACONLESS_OPERATION))
+            }
+        };
+        let band = self.band()?;
+        let settings = band.beacon_settings();
+        let symbol_time = band
+            .data_rates()
+            .get(u8::from(settings.dr) as usize)?
+            .symbol_time()?;
+
+        let period = BEACON_PERIOD.as_secs();
+        let mut beacon_time = (time.gps.as_secs() / period + 1) * period;
+        loop {
+            let elapsed =
+                (Duration::from_secs(beacon_time) + T_BEACON_DELAY).checked_sub(time.gps)?;
+            if limit.is_some_and(|limit| elapsed > limit) {
+                return None;
+            }
+            let widening = self.class_b.window_widening(&time, elapsed);
+            // a window that would open before the time it is computed from is skipped
+            if let Some(before) = elapsed.checked_sub(widening) {
+                let start = instant_add(time.local, before)?;
+                let timeout = 2 * widening + u32::from(BEACON_PREAMBLE) * symbol_time;
+                if self
+                    .class_b
+                    .window_pending(now, start, instant_add(start, timeout)?)
+                {
+                    return Some((
+                        beacon_time as u32,
+                        Window {
+                            start,
+                            timeout,
+                            frequency: self
+                                .class_b
+                                .beacon_frequency
+                                .unwrap_or_else(|| band.beacon_frequency(beacon_time as u32)),
+                            data_rate: settings.dr,
+                        },
+                    ));
+                }
+            }
+            beacon_time += period;
+        }
+    }
+
+    /// The first ping slot window, of the device or of one of its multicast groups, which hasn't
+    /// ended at `now`. `None` unless operating in Class B, or if the ping slot would be more than
+    /// [`BEACONLESS_OPERATION`] after the last beacon received.
+    ///
+    /// Ping slots are widened for the clock drift since the last beacon received.
+    pub fn next_ping_slot_window(&self, now: Instant<C>) -> Option<Window<C>> {
+        let beacon = match self.class_b.state {
+            ClassBState::Locked { beacon } | ClassBState::Beaconless { beacon } => beacon,
+            ClassBState::Off | ClassBState::Acquiring { .. } => return None,
+        };
+        let dev_addr = self.activation?.dev_addr;
+        let band = self.band()?;
+        let unicast = PingSlots {
+            periodicity: self.parameters.ping_slot_periodicity,
+            frequency: self.class_b.ping_slot_frequency,
+            data_rate: self
+                .class_b
+                .ping_slot_data_rate
+                .unwrap_or(band.ping_slot_settings().dr),
+        };
+        let multicast = self
+            .multicast_groups
+            .iter()
+            .flatten()
+            .filter_map(|group| Some((group.address, group.class_b?)));
+
+        [(dev_addr, unicast)]
+            .into_iter()
+            .chain(multicast)
+            .filter_map(|(addr, slots)| self.ping_slot_window(now, &band, &beacon, addr, &slots))
+            .min_by_key(|window| window.start)
+    }
+
+    /// The first ping slot window of `dev_addr` with the ping slot parameters `slots` which
+    /// hasn't ended at `now`, `beacon` being the time given by the last beacon received
+    fn ping_slot_window(
+        &self,
+        now: Instant<C>,
+        band: &AnyBand,
+        beacon: &GpsTime<C>,
+        dev_addr: DevAddr,
+        slots: &PingSlots,
+    ) -> Option<Window<C>> {
+        let beacon = *beacon;
+        let symbol_time = band
+            .data_rates()
+            .get(u8::from(slots.data_rate) as usize)?
+            .symbol_time()?;
+        let ping_period = ping_period(slots.periodicity);
+
+        let period = BEACON_PERIOD.as_secs();
+        // starting with the beacon period of the last beacon
+        let mut beacon_time = beacon.gps.as_secs() / period * period;
+        loop {
+            let offset = ping_offset(beacon_time as u32, dev_addr, ping_period);
+            for slot in (offset..PING_SLOTS).step_by(ping_period.into()) {
+                let slot_start = Duration::from_secs(beacon_time)
+                    + BEACON_RESERVED
+                    + u32::from(slot) * PING_SLOT_LEN;
+                let Some(elapsed) = slot_start.checked_sub(beacon.gps) else {
+                    continue;
+                };
+                if elapsed > BEACONLESS_OPERATION {
+                    return None;
+                }
+                let widening = self.class_b.window_widening(&beacon, elapsed);
+                let Some(before) = elapsed.checked_sub(widening) else {
+                    continue;
+                };
+                let start = instant_add(beacon.local, before)?;
+                let timeout = 2 * widening + u32::from(DOWNLINK_PREAMBLE) * symbol_time;
+                if self
+                    .class_b
+                    .window_pending(now, start, instant_add(start, timeout)?)
+                {
+                    return Some(Window {
+                        start,
+                        timeout,
+                        frequency: slots.frequency.unwrap_or_else(|| {
+                            band.ping_slot_frequency(dev_addr, beacon_time as u32)
+                        }),
+                        data_rate: slots.data_rate,
+                    });
+                }
+            }
+            beacon_time += period;
+        }
+    }
+
+    /// Would the uplink in [`EndDevice::uplink`], if transmitted at `now`, still be in its
+    /// receive windows [`BEACON_GUARD`] before `beacon_start`?
+    pub(crate) fn uplink_overlaps_beacon(&self, now: Instant<C>, beacon_start: Instant<C>) -> bool {
+        let details = self.uplink.details;
+        let time_on_air = self
+            .band()
+            .and_then(|band| band.uplink_time_on_air(details.data_rate, details.len))
+            .unwrap_or_default();
+        instant_add(now, time_on_air + self.receive_delay2() + BEACON_GUARD)
+            .is_none_or(|end| end > beacon_start)
+    }
+
+    /// Process a frame received in a beacon window, `meta.time` being the time its reception
+    /// ended. A frame which isn't a valid beacon counts as a missed beacon.
+    pub(crate) fn beacon_received(
+        &mut self,
+        meta: MessageRecvMeta<C>,
+        bytes: &[u8],
+    ) -> Option<Event<'static>> {
+        let Some((beacon, time)) = self.decode_beacon(meta, bytes) else {
+            return self.beacon_missed(meta.time);
+        };
+        let event = match self.class_b.state {
+            ClassBState::Off => return None,
+            ClassBState::Acquiring { .. } => Event::BeaconLocked(beacon),
+            ClassBState::Locked { .. } => Event::Beacon(beacon),
+            ClassBState::Beaconless { .. } => Event::BeaconReacquired(beacon),
+        };
+        self.class_b.state = ClassBState::Locked { beacon: time };
+        self.class = DeviceClass::B;
+        Some(event)
+    }
+
+    /// No beacon was received in the beacon window which closed at `now`
+    pub(crate) fn beacon_missed(&mut self, now: Instant<C>) -> Option<Event<'static>> {
+        let beacon = match self.class_b.state {
+            // keep searching
+            ClassBState::Off | ClassBState::Acquiring { .. } => return None,
+            ClassBState::Locked { beacon } | ClassBState::Beaconless { beacon } => beacon,
+        };
+        if self.next_beacon_window(now).is_none() {
+            self.stop_class_b();
+            return Some(Event::ClassBTimedOut);
+        }
+        match self.class_b.state {
+            ClassBState::Locked { .. } => {
+                self.class_b.state = ClassBState::Beaconless { beacon };
+                Some(Event::BeaconLost)
+            }
+            ClassBState::Off | ClassBState::Acquiring { .. } | ClassBState::Beaconless { .. } => {
+                None
+            }
+        }
+    }
+
+    /// Decode a beacon, and the GPS time it gives, received in a beacon window
+    fn decode_beacon(
+        &self,
+        meta: MessageRecvMeta<C>,
+        bytes: &[u8],
+    ) -> Option<(Beacon, GpsTime<C>)> {
+        let band = self.band()?;
+        let settings = band.beacon_settings();
+        let modulation = band.data_rates().get(u8::from(settings.dr) as usize)?;
+        let beacon = Beacon::parse_beacon(beacon_sf(modulation)?, bytes).ok()?;
+        // beacons are sent at the start of each beacon period
+        if u64::from(beacon.time) % BEACON_PERIOD.as_secs() != 0 {
+            return None;
+        }
+        // beacons use an implicit header and no CRC
+        let time_on_air =
+            modulation.time_on_air(bytes.len(), BEACON_PREAMBLE, settings.cr, false, false)?;
+        let time = GpsTime {
+            local: meta.time,
+            gps: Duration::from_secs(beacon.time.into()) + T_BEACON_DELAY + time_on_air,
+            accuracy: Duration::ZERO,
+        };
+        Some((beacon, time))
+    }
+}
@@ -0,0 +1,179 @@
+//! Class C continuous reception
+//!
+//! Once [`EndDevice::start_class_c`] is called, the end-device listens with the RX2 parameters
+//! whenever it isn't transmitting or listening in RX1: [`EndDevice::next_action`] requests
+//! [`Action::ReceiveContinuous`] instead of sleeping, including between the end of an uplink and
+//! RX1, and between RX1 and RX2.
+//!
+//! The Network Server waits [`Parameters::class_c_resp_timeout`] for an uplink acknowledging a
+//! confirmed downlink received this way. If the application doesn't send one first, an empty
+//! uplink carrying the acknowledgement is queued as soon as the device is idle.
+//!
+//! The Class C [`Session`] of a multicast group ([`MulticastGroup::class_c`]) makes the device
+//! listen continuously with the session's frequency and data rate for its duration, whatever its
+//! class.
+//!
+//! [`Action::ReceiveContinuous`]: crate::state::Action::ReceiveContinuous
+//! [`MulticastGroup::class_c`]: crate::MulticastGroup::class_c
+//! [`Parameters::class_c_resp_timeout`]: crate::Parameters::class_c_resp_timeout
+
+use embedded_time::{Clock, Instant};
+
+use crate::class_b::ClassBState;
+use crate::state::Event;
+use crate::{instant_add, mac, BatteryLevel, DataRate, DeviceClass, EndDevice, Frequency};
+use crate::{MessageRecvMeta, RxWindow};
+
+/// A multicast Class C session, during which the end-device listens continuously with its own
+/// parameters
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub struct Session<C: Clock> {
+    pub start: Instant<C>,
+    /// The session is over at this time
+    pub end: Instant<C>,
+    pub frequency: Frequency,
+    pub data_rate: DataRate,
+}
+
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for Session<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for Session<C> {}
+
+/// Class C state of an [`EndDevice`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct ClassC<C: Clock> {
+    /// [`EndDevice::next_action`] requested a continuous window the radio hasn't reported on yet
+    pub receiving: bool,
+    /// A confirmed downlink was received in a continuous window, and must be acknowledged by an
+    /// uplink before this time
+    pub ack_deadline: Option<Instant<C>>,
+}
+
+impl<C: Clock> Default for ClassC<C> {
+    fn default() -> Self {
+        Self {
+            receiving: false,
+            ack_deadline: None,
+        }
+    }
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassCError {
+    /// The device hasn't joined or been activated by personalization
+    NotActivated,
+    /// The RX2 parameters aren't known, as no band is selected or the band isn't implemented
+    NoBand,
+}
+
+impl<C, P, B> EndDevice<C, P, B>
+where
+    C: Clock,
+    P: mac::ProprietaryHandler,
+    B: BatteryLevel,
+{
+    /// Operate in Class C, stopping Class B if it was in use
+    ///
+    /// The Network Server must be told of the switch, for example with
+    /// [`EndDevice::request_device_mode`].
+    pub fn start_class_c(&mut self) -> Result<(), ClassCError> {
+        if self.activation.is_none() {
+            return Err(ClassCError::NotActivated);
+        }
+        self.rx2_window_details().ok_or(ClassCError::NoBand)?;
+        if !matches!(self.class_b.state, ClassBState::Off) {
+            self.stop_class_b();
+        }
+        self.class = DeviceClass::C;
+        Ok(())
+    }
+
+    /// Stop listening continuously, returning to Class A. Multicast sessions still in progress
+    /// continue.
+    pub fn stop_class_c(&mut self) {
+        if matches!(self.class, DeviceClass::C) {
+            self.class = DeviceClass::A;
+        }
+        self.class_c.ack_deadline = None;
+    }
+
+    /// The window to listen in continuously from `now`: the first multicast session's if one is
+    /// in progress, otherwise RX2's in Class C. `None` if the device shouldn't listen.
+    pub fn continuous_window(&self, now: Instant<C>) -> Option<RxWindow<C>> {
+        if let Some(session) = self
+            .class_c_sessions()
+            .find(|session| session.start <= now && now < session.end)
+        {
+            return Some(RxWindow {
+                start: now,
+                frequency: session.frequency,
+                data_rate: session.data_rate,
+            });
+        }
+        if !matches!(self.class, DeviceClass::C) {
+            return None;
+        }
+        let (frequency, data_rate) = self.rx2_window_details()?;
+        Some(RxWindow {
+            start: now,
+            frequency,
+            data_rate,
+        })
+    }
+
+    /// When a multicast session starts or ends after `now`, changing the continuous window
+    pub(crate) fn continuous_window_change(&self, now: Instant<C>) -> Option<Instant<C>> {
+        self.class_c_sessions()
+            .filter_map(|session| {
+                if now < session.start {
+                    Some(session.start)
+                } else if now < session.end {
+                    Some(session.end)
+                } else {
+                    None
+                }
+            })
+            .min()
+    }
+
+    fn class_c_sessions(&self) -> impl Iterator<Item = Session<C>> + '_ {
+        self.multicast_groups
+            .iter()
+            .flatten()
+            .filter_map(|group| group.class_c)
+    }
+
+    /// Process a frame received in a continuous window
+    pub(crate) fn class_c_downlink_received<'a>(
+        &mut self,
+        meta: MessageRecvMeta<C>,
+        bytes: &'a mut [u8],
+    ) -> Option<Event<'a>> {
+        let event = self.process_downlink_in(meta, bytes, false).ok()?;
+        if matches!(event, Event::Downlink { .. }) && self.ack_pending {
+            self.class_c.ack_deadline =
+                instant_add(meta.time, self.parameters.class_c_resp_timeout);
+        }
+        Some(event)
+    }
+
+    /// Queue an empty uplink acknowledging a confirmed Class C downlink, unless an uplink already
+    /// acknowledged it or it is too late to
+    pub(crate) fn queue_class_c_ack(&mut self, now: Instant<C>) {
+        let Some(deadline) = self.class_c.ack_deadline.take() else {
+            return;
+        };
+        if self.ack_pending && now < deadline {
+            // if it can't be sent, the next uplink carries the acknowledgement
+            let _ = self.send_uplink(false, None, &[]);
+        }
+    }
+}
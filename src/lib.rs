@@ -19,11 +19,15 @@ pub mod parameters;
 // XXX: consider using the embedded_time Duration instead of core::time
 use core::{marker::PhantomData, time::Duration};
 use embedded_time::{Clock, Instant};
+use get_move::Get;
 
 pub use parameters::*;
 
 pub mod state;
 
+pub mod class_b;
+pub mod class_c;
+
 pub mod mac;
 pub mod mac_frame;
 mod serde;
@@ -165,31 +169,75 @@ pub enum CodingRate {
     Cr1_3,
     Cr2_3,
     Cr4_5,
+    Cr4_6,
+    Cr4_7,
+    Cr4_8,
 }
 
+/// Settings for the next uplink, as determined by [`data_rate_backoff`]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct BackoffDetails {
     pub data_rate: DataRate,
+
+    /// Set the ADRACKReq bit in FCtrl, asking the Network Server for a downlink
     pub adr_ack_req: bool,
-    pub tx_power: (),
-    pub nb_trans: u8,
+
+    /// TXPower index, 0 being the maximum power (see [`parameters::Band::tx_power_eirp_offset`])
+    pub tx_power: u8,
+
+    /// The band's default uplink channels must be re-enabled
     pub channel_mask_reset: bool,
 }
 
 // "4.3.1.1 Adaptive data-rate control in frame header (ADR, ADRACKReq in FCtrl)"
-pub fn data_rate_backoff(params: &Parameters, adr_ack_cnt: u8) -> BackoffDetails {
-    if adr_ack_cnt < params.adr_ack_limit {
-        return BackoffDetails {
-            data_rate: DataRate::_0,
-            adr_ack_req: false,
-            tx_power: (), // Max -9dBm
-            nb_trans: 3,
-            channel_mask_reset: false,
-        };
+/// ADR backoff for an uplink sent after `adr_ack_cnt` uplinks without a downlink, currently using
+/// `data_rate` and `tx_power`.
+///
+/// Once `adr_ack_limit` is reached ADRACKReq is set. Each `adr_ack_delay` uplinks after that one
+/// step is taken to regain connectivity: first TX power is set to the maximum, then the data rate
+/// is lowered one step at a time, and finally the default channels are re-enabled. ADRACKReq is no
+/// longer set once there is nothing left to try.
+pub fn data_rate_backoff(
+    params: &Parameters,
+    band: &impl Band,
+    adr_ack_cnt: u32,
+    data_rate: DataRate,
+    tx_power: u8,
+    default_channels_enabled: bool,
+) -> BackoffDetails {
+    let mut details = BackoffDetails {
+        data_rate,
+        adr_ack_req: false,
+        tx_power,
+        channel_mask_reset: false,
+    };
+    let limit = u32::from(params.adr_ack_limit);
+    if adr_ack_cnt < limit {
+        return details;
     }
 
-    panic!()
+    // the backoff table maps the lowest data rates to themselves
+    let lower_data_rate = |dr: DataRate| {
+        band.backoff_data_rate(dr)
+            .filter(|&lower| u8::from(lower) < u8::from(dr))
+    };
+
+    let since_limit = adr_ack_cnt - limit;
+    if since_limit != 0 && since_limit.is_multiple_of(u32::from(params.adr_ack_delay.max(1))) {
+        if tx_power != 0 {
+            details.tx_power = 0;
+        } else if let Some(lower) = lower_data_rate(data_rate) {
+            details.data_rate = lower;
+        } else if !default_channels_enabled {
+            details.channel_mask_reset = true;
+        }
+    }
+
+    details.adr_ack_req = details.tx_power != 0
+        || lower_data_rate(details.data_rate).is_some()
+        || !(default_channels_enabled || details.channel_mask_reset);
+    details
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
@@ -208,6 +256,21 @@ impl Frequency {
     pub const fn from_khz(khz: u32) -> Self {
         Self { khz }
     }
+
+    /// Convert from the 100Hz units used by MAC commands. Returns `None` if the frequency isn't a
+    /// whole number of kHz.
+    pub const fn from_100hz(v: u32) -> Option<Self> {
+        if !v.is_multiple_of(10) {
+            return None;
+        }
+
+        Some(Self { khz: v / 10 })
+    }
+
+    /// Is this frequency within `range` (inclusive), as returned by `Band::frequency_range()`
+    pub const fn within(&self, range: (Frequency, Frequency)) -> bool {
+        range.0.khz <= self.khz && self.khz <= range.1.khz
+    }
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
@@ -216,6 +279,85 @@ pub struct DevAddr {
     pub addr: u32,
 }
 
+/// The operating class of an end-device. See the crate level documentation for a description of
+/// each.
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeviceClass {
+    #[default]
+    A,
+    B,
+    C,
+}
+
+/// Only Class A and Class C are switched between with `DeviceModeInd`, Class B is started with
+/// [`EndDevice::start_class_b`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedDeviceMode;
+
+/// Battery level as reported to the Network Server in `DevStatusAns`
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Battery {
+    /// Connected to an external power source
+    External,
+    /// 1 (minimum) to 254 (maximum). Values outside this range are clamped.
+    Level(u8),
+    /// The end-device was not able to measure the battery level
+    Unknown,
+}
+
+impl From<Battery> for u8 {
+    fn from(v: Battery) -> Self {
+        match v {
+            Battery::External => 0,
+            Battery::Level(level) => level.clamp(1, 254),
+            Battery::Unknown => 255,
+        }
+    }
+}
+
+/// Application provided source of the battery level
+pub trait BatteryLevel {
+    fn battery_level(&self) -> Battery;
+}
+
+/// Always reports that the battery level is unknown
+impl BatteryLevel for () {
+    fn battery_level(&self) -> Battery {
+        Battery::Unknown
+    }
+}
+
+/// A Rejoin-Request schedule requested by the Network Server via `ForceRejoinReq`
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct ForceRejoin<C: Clock> {
+    /// Type of Rejoin-Request to send, 0 or 2
+    pub rejoin_type: u8,
+
+    /// Data rate to send the Rejoin-Requests at
+    pub data_rate: DataRate,
+
+    /// Number of Rejoin-Requests remaining to be sent, including the next one
+    pub remaining: u8,
+
+    /// Exponent for the delay between Rejoin-Requests (`32s * 2^period`)
+    pub period: u8,
+
+    /// When the next Rejoin-Request should be sent
+    pub next: Instant<C>,
+}
+
+impl<C: Clock> ForceRejoin<C> {
+    /// Minimum delay between Rejoin-Requests. The specification requires an additional random
+    /// delay of 0 to 32 seconds be added to this.
+    pub fn retransmit_delay(&self) -> Duration {
+        Duration::from_secs(32) * (1u32 << self.period)
+    }
+}
+
 ///
 /// `Clock` must be a monotonic clock of with variance of XXX and accuracy of XXX
 ///
@@ -233,7 +375,7 @@ pub struct DevAddr {
 ///
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone)]
-pub struct EndDevice<C: Clock> {
+pub struct EndDevice<C: Clock, P: mac::ProprietaryHandler = (), B: BatteryLevel = ()> {
     /// The current band in use
     // NOTE: using the enum allows us to avoid having either dyn pointers & box or having to make
     // ggthis generic over regions (preventing region transitions unless box/dyn is used)
@@ -246,12 +388,57 @@ pub struct EndDevice<C: Clock> {
     ///   Must be persisted for lifetime of device.
     pub frame_count_uplink: u32,
 
-    /// XXX
+    /// FCntDown: the frame counter expected for the next downlink from the Network Server (one
+    /// more than the last one received). Reset to 0 when a JoinAccept is succesfully processed.
+    pub frame_count_downlink: u32,
+
+    /// Session established by joining, `None` if the device hasn't been activated
+    pub activation: Option<EndDeviceStorageActivation>,
+
+    /// Join-Requests sent since the last successful join, used to select the channel and data rate
+    /// of the next one (see [`parameters::Band::join_request_channel`])
+    pub join_attempts: u32,
+
+    /// The Join-Request awaiting a Join-Accept
+    pub pending_join: Option<PendingJoin>,
+
+    /// Progress of the current uplink, see [`EndDevice::next_action`]
+    pub class_a: state::ClassAState<C>,
+
+    /// The current (or last) uplink sent by the Class A state machine
+    pub uplink: state::Uplink,
+
+    /// Application uplink to send once the uplink carrying MAC command answers in its
+    /// `FRMPayload` completes, see [`EndDevice::send_uplink_unconfirmed`]
+    pub queued_uplink: Option<state::QueuedUplink>,
+
+    /// A confirmed downlink was received, so the next uplink must set the ACK bit
+    pub ack_pending: bool,
+
+    /// Set the ADR bit in uplinks, allowing the Network Server to control the data rate, TX power
+    /// and NbTrans with `LinkADRReq`
+    pub adr: bool,
+
+    /// ADR_ACK_CNT: uplinks sent with the ADR bit set since the last downlink, drives the ADR
+    /// backoff (see [`data_rate_backoff`])
     pub adr_ack_cnt: u32,
 
     /// ClassB only
     pub class_b_resp_timeout: Duration,
 
+    /// Beacon synchronization, see [`class_b`]
+    pub class_b: class_b::ClassB<C>,
+
+    /// GPS time given by the last `DeviceTimeAns`, which refers to the end of the uplink that
+    /// carried `DeviceTimeReq`
+    pub device_time: Option<class_b::GpsTime<C>>,
+
+    /// Continuous reception, see [`class_c`]
+    pub class_c: class_c::ClassC<C>,
+
+    /// Multicast groups, indexed by McGroupID, see [`EndDevice::set_multicast_group`]
+    pub multicast_groups: [Option<MulticastGroup<C>>; MULTICAST_GROUPS_MAX],
+
     /// delays since a previous uplink where the EndDevice will have it's reciever enabled and be
     /// able to recieve downlink from the Network Server.
     ///
@@ -264,10 +451,14 @@ pub struct EndDevice<C: Clock> {
     /// Determines when we're going to open the class A recv windows (based on those 2 delays).
     pub previous_transmit_time: Option<Instant<C>>,
 
+    /// NbTrans: how many times each uplink is transmitted, set by `LinkADRReq`. Unconfirmed
+    /// uplinks stop being repeated when a downlink is received, confirmed uplinks when they are
+    /// acknowledged.
     pub num_transmits: u8,
 
-    ///
-    pub maximum_tx_power: Option<u8>,
+    /// TXPower index set by `LinkADRReq`, see [`parameters::Band::tx_power_eirp_offset`]. 0 is
+    /// the maximum power. Use [`EndDevice::tx_power_dbm`] for the actual power.
+    pub tx_power_index: u8,
 
     /// bitmask indicating if a uplink channel may be used (bit is set if channel is usable). By
     /// default, all channels are considered usable.
@@ -277,38 +468,143 @@ pub struct EndDevice<C: Clock> {
     /// slightly be using `(u64, u16)` or similar if desirable.
     pub uplink_channel_mask: u128,
 
-    /// Set by the `DutyCycleReq` mac command from the Network Server.
-    ///
-    /// FIXME: needs units.
+    /// Data rate used for uplinks, set by `LinkADRReq`
+    pub data_rate: DataRate,
+
+    /// Channels defined by the Network Server, only used by bands with dynamic channel plans
+    pub channels: DynamicChannels,
+
+    /// RX2 frequency and data rate set by `RXParamSetupReq`, overriding the band's default
+    pub rx2_window: Option<(Frequency, DataRate)>,
+
+    /// Uplink dwell time limit set by `TxParamSetupReq` (0 if unlimited), `None` if the band's
+    /// default applies. Use [`EndDevice::uplink_dwell_time_limit`] for the limit in effect.
+    pub uplink_dwell_time: Option<Duration>,
+
+    /// MaxEIRP (in dBm) set by `TxParamSetupReq`, `None` if the band's default applies
+    pub max_eirp_dbm: Option<i8>,
+
+    /// MaxDCycle set by the `DutyCycleReq` mac command from the Network Server. The aggregated
+    /// duty cycle of all uplinks is limited to `1 / 2^max_duty_cycle`, 0 meaning no limit beyond
+    /// the regional one.
     pub max_duty_cycle: u8,
+
+    /// Earliest time the aggregated duty cycle (`max_duty_cycle`) allows another uplink, updated
+    /// by [`EndDevice::uplink_transmitted`]
+    pub duty_cycle_available: Option<Instant<C>>,
+
+    /// Regulatory duty cycle of the band's sub-bands, updated by [`EndDevice::uplink_transmitted`]
+    pub sub_band_duty_cycle: SubBandDutyCycle<C>,
+
+    /// Parameters that the Network Server may adjust, initialized to the recommended defaults
+    pub parameters: Parameters,
+
+    /// The class the end-device is currently operating in
+    pub class: DeviceClass,
+
+    /// Answers to MAC commands that have not yet been sent
+    pub pending_mac_answers: mac::PendingAnswers,
+
+    /// Answers which are sent in every uplink until a Class A downlink is received. See
+    /// [`mac::AnsFromEndDevice::is_sticky`].
+    ///
+    /// These must be persisted (see [`EndDevice::persist`]) so they aren't lost on reset.
+    pub sticky_mac_answers: mac::PendingAnswers,
+
+    /// LoRaWAN 1.1: `ResetInd` is sent in every uplink until a `ResetConf` is received
+    pub reset_ind_pending: bool,
+
+    /// LoRaWAN 1.1: `RekeyInd` is sent in every uplink until a `RekeyConf` is received
+    pub rekey_ind_pending: bool,
+
+    /// LoRaWAN 1.1: class requested by `DeviceModeInd`, sent in every uplink until a
+    /// `DeviceModeConf` is received
+    pub device_mode_ind_pending: Option<DeviceClass>,
+
+    /// LoRaWAN 1.1: Rejoin-Requests scheduled by `ForceRejoinReq`
+    pub force_rejoin: Option<ForceRejoin<C>>,
+
+    /// LoRaWAN 1.1: periodic Type 0 Rejoin-Request limits set by `RejoinParamSetupReq`
+    pub rejoin_param_setup: Option<mac::RejoinParamSetupReq>,
+
+    /// Application handler for proprietary MAC commands
+    pub proprietary: P,
+
+    /// Application provided battery level, reported in `DevStatusAns`
+    pub battery: B,
 }
 
-impl<C: Clock> Default for EndDevice<C> {
+impl<C, P, B> Default for EndDevice<C, P, B>
+where
+    C: Clock,
+    P: mac::ProprietaryHandler + Default,
+    B: BatteryLevel + Default,
+{
     fn default() -> Self {
+        Self::new(P::default(), B::default())
+    }
+}
+
+impl<C, P, B> EndDevice<C, P, B>
+where
+    C: Clock,
+    P: mac::ProprietaryHandler,
+    B: BatteryLevel,
+{
+    /// Create an `EndDevice` which uses `proprietary` to handle proprietary MAC commands and
+    /// `battery` to report the battery level to the Network Server
+    pub fn new(proprietary: P, battery: B) -> Self {
         Self {
             band_id: None,
             frame_count_uplink: 0,
+            frame_count_downlink: 0,
+            activation: None,
+            join_attempts: 0,
+            pending_join: None,
+            class_a: state::ClassAState::Idle,
+            uplink: state::Uplink::default(),
+            queued_uplink: None,
+            ack_pending: false,
+            adr: true,
             adr_ack_cnt: 0,
             // FIXME: not sure this is the right default,
             class_b_resp_timeout: Duration::from_secs(1),
+            class_b: class_b::ClassB::default(),
+            device_time: None,
+            class_c: class_c::ClassC::default(),
+            multicast_groups: [None; MULTICAST_GROUPS_MAX],
 
             receive_delay1: Duration::from_secs(1),
             previous_transmit_time: None,
 
-            // FIXME: arbitrary. this should be from the spec instead
-            num_transmits: 0,
+            num_transmits: 1,
 
-            maximum_tx_power: None,
+            tx_power_index: 0,
             uplink_channel_mask: u128::MAX,
+            data_rate: DataRate::_0,
+            channels: DynamicChannels::default(),
+            rx2_window: None,
+            uplink_dwell_time: None,
+            max_eirp_dbm: None,
             max_duty_cycle: 0,
+            duty_cycle_available: None,
+            sub_band_duty_cycle: SubBandDutyCycle::default(),
+
+            parameters: Parameters::default(),
+            class: DeviceClass::A,
+            pending_mac_answers: mac::PendingAnswers::default(),
+            sticky_mac_answers: mac::PendingAnswers::default(),
+            reset_ind_pending: false,
+            rekey_ind_pending: false,
+            device_mode_ind_pending: None,
+            force_rejoin: None,
+            rejoin_param_setup: None,
+
+            proprietary,
+            battery,
         }
     }
-}
 
-impl<C> EndDevice<C>
-where
-    C: Clock,
-{
     // NOTE: this function exists to allow us to change receive_delay1 to be a band/region
     // defaulted parameter (which it technically is in the specification). All regions at the
     // moment define it to be the same value (1s) though.
@@ -320,11 +616,32 @@ where
         Duration::from_secs(1) + self.receive_delay1()
     }
 
-    pub fn band(&self) -> Option<impl parameters::Band> {
-        Some(match self.band_id {
-            Some(parameters::BandId::US915) => parameters::Us915,
-            _ => return None,
-        })
+    /// Maximum EIRP (in dBm), either the band default or as set by the Network Server
+    pub fn effective_max_eirp_dbm(&self) -> Option<i8> {
+        self.max_eirp_dbm
+            .or_else(|| self.band().map(|b| b.max_eirp_default_dbm()))
+    }
+
+    /// EIRP (in dBm) that the radio should transmit uplinks at
+    ///
+    /// This is the maximum EIRP reduced by the offset selected by the Network Server's TXPower
+    /// index. Radio drivers need to account for antenna gain when converting this to a conducted
+    /// power.
+    pub fn tx_power_dbm(&self) -> Option<i8> {
+        let band = self.band()?;
+        let offset = band.tx_power_eirp_offset(self.tx_power_index)?;
+        Some(self.effective_max_eirp_dbm()? + offset)
+    }
+
+    /// Frequency and data rate of the RX2 window, either the band default or as set by the Network
+    /// Server
+    pub fn rx2_window_details(&self) -> Option<(Frequency, DataRate)> {
+        self.rx2_window
+            .or_else(|| self.band().map(|b| b.rx2_window_details()))
+    }
+
+    pub fn band(&self) -> Option<parameters::AnyBand> {
+        self.band_id.and_then(parameters::AnyBand::from_id)
     }
 
     /// Change the band_id for this instance
@@ -337,16 +654,412 @@ where
         // _probably_ no longer Joined) and as a result a need to re-schedule uplinks and downlinks
         // & reconfigure the radio hardware to support that.
         self.band_id = band_id;
+
+        // channels defined by the Network Server are specific to the band
+        self.channels = DynamicChannels::default();
+        self.rx2_window = None;
+        self.tx_power_index = 0;
+        self.max_eirp_dbm = None;
+        self.uplink_dwell_time = None;
+        self.sub_band_duty_cycle.clear();
+        self.class_b.beacon_frequency = None;
+        self.class_b.ping_slot_frequency = None;
+        self.class_b.ping_slot_data_rate = None;
     }
 
-    pub fn send_uplink_unconfirmed(&mut self, payload: &[u8]) -> Result<(), ()> {
-        // TODO: schedule a transmition as soon as permitted
-        // TODO: construct packet around payload
-        todo!()
+    /// LoRaWAN 1.1: start sending `ResetInd` in each uplink until the Network Server confirms it
+    ///
+    /// ABP devices do this after every reset.
+    pub fn begin_reset_ind(&mut self) {
+        self.reset_ind_pending = true;
     }
 
-    pub fn send_uplink_confirmed(&mut self, payload: &[u8]) -> Result<(), ()> {
-        todo!()
+    /// LoRaWAN 1.1: start sending `RekeyInd` in each uplink until the Network Server confirms it
+    ///
+    /// OTAA devices do this after every successful join.
+    pub fn begin_rekey_ind(&mut self) {
+        self.rekey_ind_pending = true;
+    }
+
+    /// LoRaWAN 1.1: request that the Network Server switch this device to a different class
+    ///
+    /// The class in use is only changed once the Network Server confirms the switch. Only
+    /// switching between Class A and Class C is done this way.
+    pub fn request_device_mode(&mut self, class: DeviceClass) -> Result<(), UnsupportedDeviceMode> {
+        if mac::DeviceModeInd::for_class(class).is_none() {
+            return Err(UnsupportedDeviceMode);
+        }
+
+        self.device_mode_ind_pending = Some(class);
+        Ok(())
+    }
+
+    /// Join the multicast group `id` (McGroupID), replacing the group previously set up with this
+    /// ID, or leave it if `group` is `None`
+    ///
+    /// [`EndDevice::handle_event`] reports the group's downlinks with
+    /// [`state::Event::MulticastDownlink`].
+    pub fn set_multicast_group(
+        &mut self,
+        id: u8,
+        group: Option<MulticastGroup<C>>,
+    ) -> Result<(), InvalidMulticastGroupId> {
+        let slot = self
+            .multicast_groups
+            .get_mut(usize::from(id))
+            .ok_or(InvalidMulticastGroupId)?;
+        *slot = group;
+        Ok(())
+    }
+
+    /// Indications that must be repeated in every uplink until they are confirmed by the Network
+    /// Server
+    pub fn pending_indications(&self) -> impl Iterator<Item = mac::AnsFromEndDevice> {
+        let reset = self
+            .reset_ind_pending
+            .then(|| mac::AnsFromEndDevice::Reset(mac::ResetInd::new().with_minor(1)));
+        let rekey = self
+            .rekey_ind_pending
+            .then(|| mac::AnsFromEndDevice::Rekey(mac::RekeyInd::new().with_minor(1)));
+        let device_mode = self
+            .device_mode_ind_pending
+            .and_then(mac::DeviceModeInd::for_class)
+            .map(mac::AnsFromEndDevice::DeviceMode);
+
+        reset.into_iter().chain(rekey).chain(device_mode)
+    }
+
+    /// Record that a Rejoin-Request scheduled by `ForceRejoinReq` was sent at `time`
+    ///
+    /// `jitter` is the random delay (0 to 32 seconds) that the specification requires be added to
+    /// the spacing between Rejoin-Requests.
+    pub fn force_rejoin_sent(&mut self, time: Instant<C>, jitter: Duration) {
+        let Some(force_rejoin) = &mut self.force_rejoin else {
+            return;
+        };
+
+        force_rejoin.remaining = force_rejoin.remaining.saturating_sub(1);
+        let next = instant_add(time, force_rejoin.retransmit_delay() + jitter);
+        match next {
+            Some(next) if force_rejoin.remaining > 0 => force_rejoin.next = next,
+            _ => self.force_rejoin = None,
+        }
+    }
+
+    /// Record an uplink transmission on `frequency` which ended at `end` and lasted `time_on_air`
+    ///
+    /// The device must then stay silent for `time_on_air * (2^max_duty_cycle - 1)` to respect the
+    /// aggregated duty cycle, and the frequency's sub-band follows its own regulatory duty cycle.
+    /// [`EndDevice::handle_event`] and [`EndDevice::join_request_sent`] call this, it only needs to
+    /// be called for uplinks sent by other means.
+    pub fn uplink_transmitted(
+        &mut self,
+        frequency: Frequency,
+        end: Instant<C>,
+        time_on_air: Duration,
+    ) {
+        if let Some(band) = self.band() {
+            self.sub_band_duty_cycle
+                .transmitted(&band, frequency, end, time_on_air);
+        }
+
+        let off_factor = (1u32 << self.max_duty_cycle) - 1;
+        // on overflow, refuse to transmit until a later `uplink_transmitted` or `DutyCycleReq`
+        // rather than exceed the limit
+        self.duty_cycle_available = Some(
+            time_on_air
+                .checked_mul(off_factor)
+                .and_then(|off| instant_add(end, off))
+                .unwrap_or(end),
+        );
+    }
+
+    /// Check if an uplink on `frequency` may start at `now`, returning the earliest allowed time
+    /// if not
+    pub fn check_uplink_allowed(
+        &self,
+        now: Instant<C>,
+        frequency: Frequency,
+    ) -> Result<(), UplinkDelayed<C>> {
+        let available = match self.band() {
+            Some(band) => self.uplink_available_at(&band, frequency),
+            None => self.duty_cycle_available,
+        };
+        match available {
+            Some(earliest) if now < earliest => Err(UplinkDelayed { earliest }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Earliest time the duty cycle limits allow an uplink on `frequency`, `None` if at any time
+    fn uplink_available_at(
+        &self,
+        band: &impl parameters::Band,
+        frequency: Frequency,
+    ) -> Option<Instant<C>> {
+        let sub_band = self.sub_band_duty_cycle.available_at(band, frequency);
+        match (self.duty_cycle_available, sub_band) {
+            (Some(a), Some(b)) => Some(if a < b { b } else { a }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Build the next Join-Request in `buf`, returning how the radio should transmit it
+    ///
+    /// The DevNonce in `storage` is incremented and must be persisted before the Join-Request is
+    /// transmitted. If no Join-Accept is received in either of the windows returned by
+    /// [`EndDevice::join_request_sent`], call this again to retry on a different channel and data
+    /// rate.
+    pub fn join_request(
+        &mut self,
+        now: Instant<C>,
+        storage: &mut EndDeviceStorage,
+        credentials: &OtaaCredentials,
+        buf: &mut [u8],
+    ) -> Result<TxDetails, JoinError<C>> {
+        let band = self.band().ok_or(JoinError::NoBand)?;
+        let (channel, data_rate) = band.join_request_channel(self.join_attempts);
+        let frequency = band
+            .upstream_channels()
+            .get_move(channel as usize)
+            .ok_or(JoinError::NoBand)?
+            .frequency;
+        self.check_uplink_allowed(now, frequency)
+            .map_err(JoinError::Delayed)?;
+        let buf = buf
+            .get_mut(..mac_frame::JOIN_REQUEST_LEN)
+            .ok_or(JoinError::BufferTooSmall)?;
+
+        // a DevNonce must never be reused with the same JoinEUI
+        let dev_nonce = storage.dev_nonce;
+        storage.dev_nonce = dev_nonce
+            .checked_add(1)
+            .ok_or(JoinError::DevNonceExhausted)?;
+
+        self.join_attempts = self.join_attempts.wrapping_add(1);
+
+        buf.copy_from_slice(
+            &mac_frame::JoinRequestBuf {
+                join_eui: credentials.join_eui,
+                dev_eui: credentials.dev_eui,
+                dev_nonce,
+            }
+            .to_bytes(&credentials.app_key),
+        );
+        self.pending_join = Some(PendingJoin {
+            dev_nonce,
+            channel,
+            data_rate,
+        });
+
+        Ok(TxDetails {
+            channel,
+            frequency,
+            data_rate,
+            tx_power_dbm: band.max_eirp_default_dbm(),
+            len: buf.len(),
+        })
+    }
+
+    /// The Join-Request from [`EndDevice::join_request`] finished transmitting at `tx_end`.
+    /// Returns the RX1 and RX2 windows in which the radio must listen for a Join-Accept.
+    pub fn join_request_sent(
+        &mut self,
+        tx_end: Instant<C>,
+    ) -> Result<[RxWindow<C>; 2], JoinError<C>> {
+        let band = self.band().ok_or(JoinError::NoBand)?;
+        let pending_join = self.pending_join.ok_or(JoinError::NotJoining)?;
+        self.previous_transmit_time = Some(tx_end);
+        let frequency = band
+            .upstream_channels()
+            .get_move(pending_join.channel as usize)
+            .map(|channel| channel.frequency);
+        let time_on_air =
+            band.uplink_time_on_air(pending_join.data_rate, mac_frame::JOIN_REQUEST_LEN);
+        if let (Some(frequency), Some(time_on_air)) = (frequency, time_on_air) {
+            self.uplink_transmitted(frequency, tx_end, time_on_air);
+        }
+
+        // the Join-Accept uses the default RX1 data rate offset and RX2 settings
+        let (rx1_frequency, rx1_data_rate) = self
+            .rx1_window_details(&band, pending_join.channel, pending_join.data_rate, 0)
+            .ok_or(JoinError::NoBand)?;
+        let (rx2_frequency, rx2_data_rate) = band.rx2_window_details();
+
+        let at = |delay| instant_add(tx_end, delay).ok_or(JoinError::TimeOverflow);
+        Ok([
+            RxWindow {
+                start: at(self.parameters.join_accept_delay1)?,
+                frequency: rx1_frequency,
+                data_rate: rx1_data_rate,
+            },
+            RxWindow {
+                start: at(self.parameters.join_accept_delay2)?,
+                frequency: rx2_frequency,
+                data_rate: rx2_data_rate,
+            },
+        ])
+    }
+
+    /// Process a frame received in one of the windows from [`EndDevice::join_request_sent`]
+    ///
+    /// `bytes` is decrypted in place. On success the device is activated with new session keys,
+    /// frame counters are reset and the settings in the Join-Accept are applied. The JoinNonce is
+    /// recorded in `storage`, which must be persisted so that replayed Join-Accepts are rejected.
+    pub fn join_accept_received(
+        &mut self,
+        storage: &mut EndDeviceStorage,
+        credentials: &OtaaCredentials,
+        bytes: &mut [u8],
+    ) -> Result<(), JoinError<C>> {
+        let band = self.band().ok_or(JoinError::NoBand)?;
+        let pending_join = self.pending_join.ok_or(JoinError::NotJoining)?;
+
+        let phy = mac_frame::PhyPayload::from_bytes(bytes)
+            .map_err(JoinError::Decode)?
+            .decrypt_join_accept(&credentials.app_key)
+            .map_err(JoinError::Decrypt)?;
+        let join_accept = phy.join_accept().map_err(JoinError::Parse)?;
+
+        // the Join Server increments JoinNonce for every Join-Accept
+        let join_nonce = join_accept.join_nonce();
+        if storage.join_nonce.is_some_and(|last| join_nonce <= last) {
+            return Err(JoinError::JoinNonceReplayed);
+        }
+        storage.join_nonce = Some(join_nonce);
+
+        self.activation = Some(EndDeviceStorageActivation {
+            kind: ActivationKind::Otaa,
+            dev_addr: DevAddr {
+                addr: join_accept.dev_addr(),
+            },
+            network_session_key: join_accept
+                .calculate_network_session_key(&credentials.app_key, pending_join.dev_nonce),
+            application_session_key: join_accept
+                .calculate_app_session_key(&credentials.app_key, pending_join.dev_nonce),
+        });
+        self.reset_session();
+        self.frame_count_uplink = 0;
+        self.frame_count_downlink = 0;
+
+        let dl_settings = join_accept.dl_settings();
+        self.parameters.rx1_dr_offset = dl_settings.rx1_dr_offset() as usize;
+        if let Ok(data_rate) = DataRate::try_from(dl_settings.rx2_data_rate()) {
+            self.rx2_window = Some((band.rx2_window_details().0, data_rate));
+        }
+
+        // RxDelay: 0 is treated as 1s
+        self.receive_delay1 = Duration::from_secs((join_accept.rx_delay() & 0xF).max(1) as u64);
+
+        if let Some(cf_list) = join_accept.cf_list() {
+            self.apply_cf_list(&band, cf_list.try_into().unwrap());
+        }
+
+        Ok(())
+    }
+
+    /// Activate by personalization (ABP), using a session provisioned on the device instead of
+    /// joining
+    ///
+    /// ABP frame counters are never reset, so they must be restored from persistent storage (see
+    /// [`EndDeviceStorage::frame_count_uplink`]) and saved again with [`EndDevice::persist`] after
+    /// every uplink and downlink.
+    pub fn activate_abp(
+        &mut self,
+        dev_addr: DevAddr,
+        nwk_skey: [u8; 16],
+        app_skey: [u8; 16],
+        fcnt_up: u32,
+        fcnt_down: u32,
+    ) {
+        self.activation = Some(EndDeviceStorageActivation {
+            kind: ActivationKind::Abp,
+            dev_addr,
+            network_session_key: nwk_skey,
+            application_session_key: app_skey,
+        });
+        self.reset_session();
+        self.frame_count_uplink = fcnt_up;
+        self.frame_count_downlink = fcnt_down;
+    }
+
+    /// Forget the MAC state of any previous session, returning the settings the Network Server may
+    /// change to the band's and [`Parameters::default`]'s values
+    fn reset_session(&mut self) {
+        let defaults = Parameters::default();
+
+        self.pending_join = None;
+        self.join_attempts = 0;
+        self.class_a = state::ClassAState::Idle;
+        self.queued_uplink = None;
+        self.ack_pending = false;
+        self.adr_ack_cnt = 0;
+
+        self.pending_mac_answers.clear();
+        self.sticky_mac_answers.clear();
+        self.channels = DynamicChannels::default();
+        self.uplink_channel_mask = u128::MAX;
+        self.data_rate = DataRate::_0;
+        self.tx_power_index = 0;
+        self.num_transmits = 1;
+        self.max_duty_cycle = 0;
+        self.duty_cycle_available = None;
+        self.max_eirp_dbm = None;
+        self.uplink_dwell_time = None;
+        self.rx2_window = None;
+        self.receive_delay1 = defaults.receive_delay1;
+        self.parameters.rx1_dr_offset = defaults.rx1_dr_offset;
+        self.parameters.adr_ack_limit = defaults.adr_ack_limit;
+        self.parameters.adr_ack_delay = defaults.adr_ack_delay;
+        self.parameters.downlink_dwell_time = defaults.downlink_dwell_time;
+        self.class_b.beacon_frequency = None;
+        self.class_b.ping_slot_frequency = None;
+        self.class_b.ping_slot_data_rate = None;
+    }
+
+    /// Was the current session established by joining (OTAA)?
+    pub fn is_otaa(&self) -> bool {
+        matches!(
+            self.activation,
+            Some(EndDeviceStorageActivation {
+                kind: ActivationKind::Otaa,
+                ..
+            })
+        )
+    }
+
+    /// Apply the CFList of a Join-Accept, ignored if its type doesn't match the band
+    fn apply_cf_list(&mut self, band: &parameters::AnyBand, cf_list: &[u8; 16]) {
+        match (band.cflist_type(), cf_list[15]) {
+            (CflistType::Specific, 0) => {
+                // frequencies of the channels following the default channels
+                let first = band.upstream_channels().len();
+                for (i, f) in cf_list[..15].chunks_exact(3).enumerate() {
+                    let frequency =
+                        Frequency::from_100hz(serde::u24_from_le_bytes(f.try_into().unwrap()))
+                            .filter(|f| f.within(band.frequency_range()));
+                    let index = first + i;
+                    if let (Some(frequency), true) = (frequency, index < DYNAMIC_CHANNELS_MAX) {
+                        self.channels.channels[index] = DynamicChannel {
+                            uplink: Some(new_uplink_channel(band, frequency, None)),
+                            downlink_frequency: None,
+                        };
+                        self.uplink_channel_mask |= 1 << index;
+                    }
+                }
+            }
+            (CflistType::Mask, 1) => {
+                // ChMask0..ChMask4, covering channels 0 to 79
+                let mask = cf_list[..10]
+                    .chunks_exact(2)
+                    .enumerate()
+                    .fold(0u128, |mask, (i, m)| {
+                        mask | (u16::from_le_bytes(m.try_into().unwrap()) as u128) << (16 * i)
+                    });
+                self.uplink_channel_mask = mask & self.defined_uplink_channels(band);
+            }
+            _ => {}
+        }
     }
 
     pub fn process_mac_request(
@@ -357,44 +1070,14 @@ where
     ) -> Result<(), ()> {
         match mac_message {
             mac::ReqFromNetworkServer::LinkAdr(link_adr) => {
-                if link_adr.tx_power() != 0xf {
-                    // FIXME: map this to some meaningful units
-                    self.maximum_tx_power = Some(link_adr.tx_power());
-                }
-
-                let channel_mask_ack = if let Some(band) = self.band() {
-                    // TODO: consider what should be done if we lack a band/region
-
-                    // TODO: must check if all channels would be disabled
-                    // TODO: must check if the channel mask is incompatible with the resulting data
-                    // rate or TX power
-                    // TODO: must check if the channel mask enables a yet undefined channel
-                    if let Ok(uplink_channel_mask) = band.channel_mask_apply(
-                        link_adr.channel_mask_ctrl(),
-                        link_adr.ch_mask(),
-                        self.uplink_channel_mask,
-                    ) {
-                        self.uplink_channel_mask = uplink_channel_mask;
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                };
-
-                // TODO: link_adr.data_rate
-                // TODO: link_adr.redudancy
-
-                self.send_mac_answer(mac::AnsFromEndDevice::LinkAdr(
-                    mac::LinkAdrAns::new()
-                        .with_channel_mask_ack(channel_mask_ack)
-                        .with_power_ack(true)
-                        .with_data_rate_ack(true),
-                ))
+                self.process_link_adr_block(&[link_adr]).map_err(|_| ())
             }
             mac::ReqFromNetworkServer::DevStatus => {
-                todo!();
+                self.send_mac_answer(mac::AnsFromEndDevice::DevStatus(
+                    mac::DevStatusAns::new()
+                        .with_battery(self.battery.battery_level().into())
+                        .with_margin(message_recv_meta.snr_db),
+                ))
             }
             mac::ReqFromNetworkServer::DutyCycle(duty_cycle) => {
                 self.max_duty_cycle = duty_cycle.max_duty_cycle();
@@ -402,37 +1085,855 @@ where
                 self.send_mac_answer(mac::AnsFromEndDevice::DutyCycle)
             }
             mac::ReqFromNetworkServer::DlChannel(dl_channel) => {
-                todo!();
+                let Some(band) = self.band().filter(|b| b.has_dynamic_channels()) else {
+                    // fixed channel plans don't support DlChannelReq
+                    return Err(());
+                };
+
+                let index = dl_channel.channel_index() as usize;
+                let frequency = Frequency::from_100hz(dl_channel.frequency())
+                    .filter(|f| f.within(band.frequency_range()));
+                let uplink_frequency_exists = self.uplink_channel(&band, index).is_some();
+
+                if let (Some(frequency), true) = (frequency, uplink_frequency_exists) {
+                    self.channels.channels[index].downlink_frequency = Some(frequency);
+                }
+
+                self.send_mac_answer(mac::AnsFromEndDevice::DlChannel(
+                    mac::DlChannelAns::new()
+                        .with_channel_frequency_ok(frequency.is_some())
+                        .with_uplink_frequency_exists(uplink_frequency_exists),
+                ))
             }
             mac::ReqFromNetworkServer::NewChannel(new_channel_req) => {
-                todo!();
+                let Some(band) = self.band().filter(|b| b.has_dynamic_channels()) else {
+                    // fixed channel plans don't support NewChannelReq
+                    return Err(());
+                };
+
+                let index = new_channel_req.channel_index() as usize;
+                let default_channels = band.upstream_channels().len();
+                // default channels may not be modified
+                let index_ok = (default_channels..DYNAMIC_CHANNELS_MAX).contains(&index);
+
+                let (channel_frequency_ok, data_rate_range_ok) = if new_channel_req.frequency() == 0
+                {
+                    // removes the channel
+                    if index_ok {
+                        self.channels.channels[index] = DynamicChannel::default();
+                        self.uplink_channel_mask &= !(1 << index);
+                    }
+
+                    (index_ok, index_ok)
+                } else {
+                    let frequency = Frequency::from_100hz(new_channel_req.frequency())
+                        .filter(|f| f.within(band.frequency_range()));
+
+                    let data_rate_known = |dr: u8| {
+                        !matches!(
+                            band.data_rates().get(dr as usize),
+                            None | Some(Modulation::Rfu)
+                        )
+                    };
+                    let min = new_channel_req.min_data_rate();
+                    let max = new_channel_req.max_data_rate();
+                    let data_rate_range_ok =
+                        min <= max && data_rate_known(min) && data_rate_known(max);
+
+                    if let (true, Some(frequency), true) = (index_ok, frequency, data_rate_range_ok)
+                    {
+                        let data_rates = (min.try_into().unwrap(), max.try_into().unwrap());
+                        self.channels.channels[index] = DynamicChannel {
+                            uplink: Some(new_uplink_channel(&band, frequency, Some(data_rates))),
+                            downlink_frequency: None,
+                        };
+                        self.uplink_channel_mask |= 1 << index;
+                    }
+
+                    (
+                        index_ok && frequency.is_some(),
+                        index_ok && data_rate_range_ok,
+                    )
+                };
+
+                self.send_mac_answer(mac::AnsFromEndDevice::NewChannel(
+                    mac::NewChannelAns::new()
+                        .with_channel_frequency_ok(channel_frequency_ok)
+                        .with_data_rate_range_ok(data_rate_range_ok),
+                ))
             }
             mac::ReqFromNetworkServer::RxParamSetup(rx_param_setup_req) => {
-                todo!();
+                let (rx1_data_rate_offset_ack, rx2_data_rate_ack, channel_ack) =
+                    if let Some(band) = self.band() {
+                        let rx1_dr_offset = rx_param_setup_req.rx1_data_rate_offset();
+                        let rx2_data_rate = rx_param_setup_req.rx2_data_rate();
+                        let frequency = Frequency::from_100hz(rx_param_setup_req.frequency())
+                            .filter(|f| f.within(band.frequency_range()));
+
+                        (
+                            band.rx1_window_data_rate(self.data_rate, rx1_dr_offset)
+                                .is_some(),
+                            !matches!(
+                                band.data_rates().get(rx2_data_rate as usize),
+                                None | Some(Modulation::Rfu)
+                            ),
+                            frequency,
+                        )
+                    } else {
+                        (false, false, None)
+                    };
+
+                // all or nothing
+                if let (true, true, Some(frequency)) =
+                    (rx1_data_rate_offset_ack, rx2_data_rate_ack, channel_ack)
+                {
+                    self.parameters.rx1_dr_offset =
+                        rx_param_setup_req.rx1_data_rate_offset() as usize;
+                    self.rx2_window = Some((
+                        frequency,
+                        rx_param_setup_req.rx2_data_rate().try_into().unwrap(),
+                    ));
+                }
+
+                self.send_mac_answer(mac::AnsFromEndDevice::RxParamSetup(
+                    mac::RxParamSetupAns::new()
+                        .with_rx1_data_rate_offset_ack(rx1_data_rate_offset_ack)
+                        .with_rx2_data_rate_ack(rx2_data_rate_ack)
+                        .with_channel_ack(channel_ack.is_some()),
+                ))
             }
             mac::ReqFromNetworkServer::TxParamSetup(tx_param_setup_req) => {
-                todo!();
+                if !self.band().is_some_and(|b| b.supports_tx_param_setup()) {
+                    // regions without dwell time limits ignore TxParamSetupReq
+                    return Err(());
+                }
+
+                let dwell_time = |limited: bool| {
+                    if limited {
+                        DWELL_TIME_LIMIT
+                    } else {
+                        Duration::from_secs(0)
+                    }
+                };
+                self.uplink_dwell_time = Some(dwell_time(tx_param_setup_req.uplink_dwell_time()));
+                self.parameters.downlink_dwell_time =
+                    dwell_time(tx_param_setup_req.downlink_dwell_time());
+                self.max_eirp_dbm = Some(tx_param_setup_req.max_eirp_dbm());
+
+                self.send_mac_answer(mac::AnsFromEndDevice::TxParamSetup)
             }
             mac::ReqFromNetworkServer::RxTimingSetup(rx_timing_setup_req) => {
-                todo!();
+                // 0 and 1 both mean 1 second
+                self.receive_delay1 =
+                    Duration::from_secs(rx_timing_setup_req.delay_seconds().max(1).into());
+
+                self.send_mac_answer(mac::AnsFromEndDevice::RxTimingSetup)
+            }
+            mac::ReqFromNetworkServer::Reset(_reset_conf) => {
+                self.reset_ind_pending = false;
+                Ok(())
+            }
+            mac::ReqFromNetworkServer::Rekey(_rekey_conf) => {
+                self.rekey_ind_pending = false;
+                Ok(())
+            }
+            mac::ReqFromNetworkServer::AdrParamSetup(adr_param_setup) => {
+                self.parameters.adr_ack_limit = 1 << adr_param_setup.limit_exp();
+                self.parameters.adr_ack_delay = 1 << adr_param_setup.delay_exp();
+
+                self.send_mac_answer(mac::AnsFromEndDevice::AdrParamSetup)
+            }
+            mac::ReqFromNetworkServer::ForceRejoin(force_rejoin) => {
+                // Rejoin-Requests are only meaningful for devices which joined
+                if !self.is_otaa() {
+                    return Err(());
+                }
+
+                let rejoin_type = match force_rejoin.rejoin_type() {
+                    0 | 1 => 0,
+                    2 => 2,
+                    // RFU, ignore the request
+                    _ => return Err(()),
+                };
+
+                self.force_rejoin = Some(ForceRejoin {
+                    rejoin_type,
+                    data_rate: force_rejoin.data_rate().try_into().unwrap(),
+                    remaining: force_rejoin.max_retries() + 1,
+                    period: force_rejoin.period(),
+                    // the first Rejoin-Request is sent as soon as possible
+                    next: message_recv_meta.time,
+                });
+
+                // ForceRejoinReq has no answer
+                Ok(())
+            }
+            mac::ReqFromNetworkServer::RejoinParamSetup(rejoin_param_setup) => {
+                if !self.is_otaa() {
+                    return Err(());
+                }
+
+                self.rejoin_param_setup = Some(rejoin_param_setup);
+
+                // time based rejoins are always supported as we're required to have a `Clock`
+                self.send_mac_answer(mac::AnsFromEndDevice::RejoinParamSetup(
+                    mac::RejoinParamSetupAns::new().with_time_ok(true),
+                ))
+            }
+            mac::ReqFromNetworkServer::DeviceMode(device_mode_conf) => {
+                // the Network Server may refuse the switch by confirming our current class
+                if let Some(class) = device_mode_conf.device_class() {
+                    self.class = class;
+                }
+                self.device_mode_ind_pending = None;
+                Ok(())
+            }
+            mac::ReqFromNetworkServer::PingSlotChannel(ping_slot_channel_req) => {
+                let (frequency, data_rate) = match self.band() {
+                    Some(band) => (
+                        match ping_slot_channel_req.frequency() {
+                            // back to the band's ping slot channels
+                            0 => Some(None),
+                            f => Frequency::from_100hz(f)
+                                .filter(|f| f.within(band.frequency_range()))
+                                .map(Some),
+                        },
+                        DataRate::try_from(ping_slot_channel_req.data_rate())
+                            .ok()
+                            .filter(|dr| {
+                                !matches!(
+                                    band.data_rates().get(u8::from(*dr) as usize),
+                                    None | Some(Modulation::Rfu)
+                                )
+                            }),
+                    ),
+                    None => (None, None),
+                };
+
+                // all or nothing
+                if let (Some(frequency), Some(data_rate)) = (frequency, data_rate) {
+                    self.class_b.ping_slot_frequency = frequency;
+                    self.class_b.ping_slot_data_rate = Some(data_rate);
+                }
+
+                self.send_mac_answer(mac::AnsFromEndDevice::PingSlotChannel(
+                    mac::PingSlotChannelAns::new()
+                        .with_channel_frequency_ok(frequency.is_some())
+                        .with_data_rate_ok(data_rate.is_some()),
+                ))
+            }
+            mac::ReqFromNetworkServer::BeaconFreq(beacon_freq_req) => {
+                let frequency = match beacon_freq_req.frequency() {
+                    // back to the band's beacon channels
+                    0 => Some(None),
+                    f => self.band().and_then(|band| {
+                        Frequency::from_100hz(f)
+                            .filter(|f| f.within(band.frequency_range()))
+                            .map(Some)
+                    }),
+                };
+                if let Some(frequency) = frequency {
+                    self.class_b.beacon_frequency = frequency;
+                }
+
+                self.send_mac_answer(mac::AnsFromEndDevice::BeaconFreq(
+                    mac::BeaconFreqAns::new().with_beacon_frequency_ok(frequency.is_some()),
+                ))
+            }
+            mac::ReqFromNetworkServer::Proprietary(proprietary) => {
+                match self.proprietary.handle_request(&proprietary) {
+                    Some(answer) => {
+                        self.send_mac_answer(mac::AnsFromEndDevice::Proprietary(answer))
+                    }
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Process a contiguous block of `LinkADRReq` commands
+    ///
+    /// The channel mask changes from every request in the block are applied in order, but the data
+    /// rate, TX power, and NbTrans are only taken from the last request. If any part of the block
+    /// is rejected, none of it is applied. One `LinkADRAns` is sent per request, all with the same
+    /// status.
+    pub fn process_link_adr_block(
+        &mut self,
+        block: &[mac::LinkAdrReq],
+    ) -> Result<(), mac::PendingAnswersFull> {
+        let Some(last) = block.last() else {
+            return Ok(());
+        };
+
+        // TODO: consider what should be done if we lack a band/region
+        let (channel_mask_ack, data_rate_ack, power_ack) = if let Some(band) = self.band() {
+            let defined = self.defined_uplink_channels(&band);
+
+            let mut channel_mask_ack = true;
+            let mut uplink_channel_mask = self.uplink_channel_mask;
+            for link_adr in block {
+                let ctrl = link_adr.channel_mask_ctrl();
+
+                // channels enabled by `ChMask` itself, rather than implicitly by `ChMaskCntl`
+                let explicit = band.channel_mask_apply(ctrl, link_adr.ch_mask(), 0);
+                let implicit = band.channel_mask_apply(ctrl, 0, 0);
+                let applied =
+                    band.channel_mask_apply(ctrl, link_adr.ch_mask(), uplink_channel_mask);
+
+                match (explicit, implicit, applied) {
+                    (Ok(explicit), Ok(implicit), Ok(applied)) => {
+                        if explicit & !implicit & !defined != 0 {
+                            // enables a yet undefined channel
+                            channel_mask_ack = false;
+                        }
+                        uplink_channel_mask = applied & defined;
+                    }
+                    _ => channel_mask_ack = false,
+                }
+            }
+
+            if uplink_channel_mask == 0 {
+                channel_mask_ack = false;
+            }
+
+            let data_rate = match last.data_rate() {
+                0xF => Some(self.data_rate),
+                dr => dr.try_into().ok(),
+            };
+
+            let data_rate_ack = match data_rate {
+                Some(data_rate) => {
+                    let known = !matches!(
+                        band.data_rates().get(u8::from(data_rate) as usize),
+                        None | Some(Modulation::Rfu)
+                    );
+                    let usable =
+                        self.channels_support_data_rate(&band, uplink_channel_mask, data_rate);
+                    if !usable {
+                        // the channel mask is incompatible with the resulting data rate
+                        channel_mask_ack = false;
+                    }
+                    // not even an empty frame fits in the dwell time
+                    let fits = self.maximum_payload_size(&band, data_rate).is_some();
+                    known && usable && fits
+                }
+                None => false,
+            };
+
+            let power_ack = match last.tx_power() {
+                0xF => true,
+                tx_power => band.tx_power_eirp_offset(tx_power).is_some(),
+            };
+
+            if channel_mask_ack && data_rate_ack && power_ack {
+                self.uplink_channel_mask = uplink_channel_mask;
+                if let Some(data_rate) = data_rate {
+                    self.data_rate = data_rate;
+                }
+                if last.tx_power() != 0xF {
+                    self.tx_power_index = last.tx_power();
+                }
+                if last.nb_trans() != 0 {
+                    self.num_transmits = last.nb_trans();
+                }
+            }
+
+            (channel_mask_ack, data_rate_ack, power_ack)
+        } else {
+            (false, false, false)
+        };
+
+        let answer = mac::LinkAdrAns::new()
+            .with_channel_mask_ack(channel_mask_ack)
+            .with_power_ack(power_ack)
+            .with_data_rate_ack(data_rate_ack);
+        for _ in block {
+            self.send_mac_answer(mac::AnsFromEndDevice::LinkAdr(answer))
+                .map_err(|_| mac::PendingAnswersFull)?;
+        }
+
+        Ok(())
+    }
+
+    /// Uplink dwell time limit in effect, `None` if unlimited
+    pub fn uplink_dwell_time_limit(&self, band: &impl parameters::Band) -> Option<Duration> {
+        let dwell_time = self
+            .uplink_dwell_time
+            .unwrap_or(band.uplink_dwell_time_default());
+        (!dwell_time.is_zero()).then_some(dwell_time)
+    }
+
+    /// Maximum MACPayload size (M) at `data_rate`, limited by the dwell time if there is one.
+    /// `None` if the data rate can't be used, such as when even a frame without FOpts or
+    /// FRMPayload would exceed the dwell time.
+    pub fn maximum_payload_size(
+        &self,
+        band: &impl parameters::Band,
+        data_rate: DataRate,
+    ) -> Option<u8> {
+        let max = band.maximum_payload_size(data_rate.into())?;
+        let Some(dwell_time) = self.uplink_dwell_time_limit(band) else {
+            return Some(max);
+        };
+        // the MACPayload is at least a 7 byte FHDR, and is wrapped by the MHDR and MIC (5 bytes)
+        (7..=max).rev().find(|&m| {
+            band.uplink_time_on_air(data_rate, usize::from(m) + 5)
+                .is_some_and(|time_on_air| time_on_air <= dwell_time)
+        })
+    }
+
+    /// Length of the MAC command answers for the next uplink if they fit in its `FOpts`, given the
+    /// maximum MACPayload size `max_payload`. `None` if they must be sent in the `FRMPayload` of
+    /// an uplink of their own instead.
+    pub(crate) fn fopts_mac_answers_len(&self, max_payload: u8) -> Option<usize> {
+        let len: usize = self.uplink_mac_answers().map(|a| a.encoded_len()).sum();
+        // FHDR without FOpts, and FPort
+        (len <= mac::FOPTS_MAX_LEN && 8 + len <= usize::from(max_payload)).then_some(len)
+    }
+
+    /// Longest application payload the next uplink may carry, given the current data rate, dwell
+    /// time limit, and the MAC command answers which will be sent in `FOpts`. Answers which don't
+    /// fit in `FOpts` are sent in an uplink of their own, so they don't limit the payload. `None`
+    /// if no band is selected or the data rate can't be used.
+    ///
+    /// The ADR backoff may lower the data rate when the uplink is sent, see [`data_rate_backoff`].
+    pub fn max_app_payload_len(&self) -> Option<usize> {
+        let band = self.band()?;
+        let max_payload = self.maximum_payload_size(&band, self.data_rate)?;
+        let fopts_len = self.fopts_mac_answers_len(max_payload).unwrap_or(0);
+        // FHDR without FOpts, and FPort
+        usize::from(max_payload).checked_sub(8 + fopts_len)
+    }
+
+    /// ADR backoff for the next uplink, `None` if ADR is disabled. The default channels are
+    /// re-enabled right away if the backoff requires it, the rest is up to the caller.
+    pub(crate) fn adr_backoff(&mut self, band: &impl parameters::Band) -> Option<BackoffDetails> {
+        if !self.adr {
+            return None;
+        }
+
+        let default_mask = u128::MAX
+            .checked_shr(128 - band.upstream_channels().len().min(128) as u32)
+            .unwrap_or(0);
+        let mut backoff = data_rate_backoff(
+            &self.parameters,
+            band,
+            self.adr_ack_cnt,
+            self.data_rate,
+            self.tx_power_index,
+            self.uplink_channel_mask & default_mask == default_mask,
+        );
+        if backoff.channel_mask_reset {
+            self.uplink_channel_mask |= default_mask;
+        }
+        // the dwell time may forbid lower data rates
+        if self.maximum_payload_size(band, backoff.data_rate).is_none() {
+            backoff.data_rate = self.data_rate;
+        }
+        Some(backoff)
+    }
+
+    /// Number of uplink channel indexes that may be in use in `band`
+    fn uplink_channel_count(&self, band: &impl parameters::Band) -> usize {
+        if band.has_dynamic_channels() {
+            DYNAMIC_CHANNELS_MAX
+        } else {
+            band.upstream_channels().len()
+        }
+    }
+
+    /// Details of the uplink channel `index`, either one of the band's default channels or one
+    /// defined by the Network Server. `None` if the channel isn't defined.
+    pub fn uplink_channel(
+        &self,
+        band: &impl parameters::Band,
+        index: usize,
+    ) -> Option<ChannelDetails> {
+        let channels = band.upstream_channels();
+        if index < channels.len() {
+            channels.get_move(index)
+        } else if band.has_dynamic_channels() {
+            self.channels.channels.get(index)?.uplink
+        } else {
+            None
+        }
+    }
+
+    /// Pick an uplink channel for `data_rate` at random among the enabled channels which support
+    /// it and may be used at `now`. `exclude` is only picked if no other channel is usable, so
+    /// that retransmissions change channel.
+    ///
+    /// If no channel may be used at `now`, the error holds the earliest time one may be.
+    pub fn select_uplink_channel(
+        &self,
+        band: &impl parameters::Band,
+        now: Instant<C>,
+        data_rate: DataRate,
+        exclude: Option<u8>,
+        rng: &mut impl Rng,
+    ) -> Result<u8, NoChannelAvailable<C>> {
+        let dr = u8::from(data_rate);
+        let mut usable: u128 = 0;
+        let mut earliest: Option<Instant<C>> = None;
+        for i in 0..self.uplink_channel_count(band).min(128) {
+            if self.uplink_channel_mask & (1 << i) == 0 {
+                continue;
+            }
+            let Some(channel) = self.uplink_channel(band, i) else {
+                continue;
+            };
+            if !(u8::from(channel.data_rate_min)..=u8::from(channel.data_rate_max)).contains(&dr) {
+                continue;
+            }
+            match self.uplink_available_at(band, channel.frequency) {
+                Some(available) if now < available => {
+                    if earliest.is_none_or(|e| available < e) {
+                        earliest = Some(available);
+                    }
+                }
+                _ => usable |= 1 << i,
+            }
+        }
+
+        let others = usable & !exclude.map_or(0, |c| 1u128.checked_shl(c.into()).unwrap_or(0));
+        if others != 0 {
+            usable = others;
+        }
+        if usable == 0 {
+            return Err(NoChannelAvailable { earliest });
+        }
+
+        // uniformly pick one of the set bits
+        let pick = (u64::from(rng.next_u32()) * u64::from(usable.count_ones())) >> 32;
+        for _ in 0..pick {
+            usable &= usable - 1;
+        }
+        Ok(usable.trailing_zeros() as u8)
+    }
+
+    /// Frequency and data rate of the RX1 window following an uplink on `channel` at `data_rate`
+    pub fn rx1_window_details(
+        &self,
+        band: &impl parameters::Band,
+        channel: u8,
+        data_rate: DataRate,
+        rx1_dr_offset: u8,
+    ) -> Option<(Frequency, DataRate)> {
+        let frequency = if band.has_dynamic_channels() {
+            let index = channel as usize;
+            self.channels
+                .channels
+                .get(index)
+                .and_then(|c| c.downlink_frequency)
+                .or_else(|| self.uplink_channel(band, index).map(|c| c.frequency))?
+        } else {
+            band.downstream_channels()
+                .get_move(band.rx1_recv_channel(channel) as usize)?
+                .frequency
+        };
+
+        Some((
+            frequency,
+            band.rx1_window_data_rate(data_rate, rx1_dr_offset)?,
+        ))
+    }
+
+    /// Bitmask of the uplink channels which are defined
+    fn defined_uplink_channels(&self, band: &impl parameters::Band) -> u128 {
+        (0..self.uplink_channel_count(band).min(128))
+            .filter(|&i| self.uplink_channel(band, i).is_some())
+            .fold(0, |mask, i| mask | (1 << i))
+    }
+
+    /// Is there at least one channel in `channel_mask` which can be used with `data_rate`?
+    pub(crate) fn channels_support_data_rate(
+        &self,
+        band: &impl parameters::Band,
+        channel_mask: u128,
+        data_rate: DataRate,
+    ) -> bool {
+        let dr = u8::from(data_rate);
+        (0..self.uplink_channel_count(band).min(128))
+            .filter(|&i| channel_mask & (1 << i) != 0)
+            .filter_map(|i| self.uplink_channel(band, i))
+            .any(|ch| u8::from(ch.data_rate_min) <= dr && dr <= u8::from(ch.data_rate_max))
+    }
+
+    /// Decode and process all the MAC commands in `bytes` (either `FOpts` or a `FRMPayload` with
+    /// `FPort` = 0)
+    ///
+    /// Commands before a command that can't be decoded are still processed.
+    pub fn process_mac_commands(
+        &mut self,
+        message_recv_meta: MessageRecvMeta<C>,
+        bytes: &[u8],
+    ) -> Result<(), mac::MacCommandParseError> {
+        let mut rem = bytes;
+        while !rem.is_empty() {
+            let (cmd, r) = mac::parse_downlink_command(rem, &self.proprietary)?;
+            rem = r;
+
+            match cmd {
+                mac::FromNetworkServer::Req(mac::ReqFromNetworkServer::LinkAdr(first)) => {
+                    // contiguous LinkADRReqs are processed together as a single block
+                    let mut block = [first; mac::PENDING_ANSWERS_MAX];
+                    let mut len = 1;
+                    while let Ok((
+                        mac::FromNetworkServer::Req(mac::ReqFromNetworkServer::LinkAdr(next)),
+                        r,
+                    )) = mac::parse_downlink_command(rem, &self.proprietary)
+                    {
+                        rem = r;
+                        if let Some(slot) = block.get_mut(len) {
+                            *slot = next;
+                        }
+                        len += 1;
+                    }
+
+                    match block.get(..len) {
+                        Some(block) => {
+                            let _ = self.process_link_adr_block(block);
+                        }
+                        None => {
+                            // too many to process, reject the whole block. Only as many answers
+                            // as fit are queued.
+                            for _ in 0..len {
+                                let _ = self.send_mac_answer(mac::AnsFromEndDevice::LinkAdr(
+                                    mac::LinkAdrAns::new(),
+                                ));
+                            }
+                        }
+                    }
+                }
+                mac::FromNetworkServer::Req(req) => {
+                    // a failure to handle one command doesn't prevent handling the remaining ones
+                    let _ = self.process_mac_request(message_recv_meta, req);
+                }
+                mac::FromNetworkServer::Ans(mac::AnsFromNetworkServer::DeviceTime(ans)) => {
+                    if let Some(local) = self.previous_transmit_time {
+                        // the fraction is in 1/256 s
+                        let gps = Duration::from_secs(ans.seconds_since_epoch().into())
+                            + Duration::from_nanos(u64::from(ans.fraction_seconds()) * 3_906_250);
+                        self.device_time = Some(class_b::GpsTime {
+                            local,
+                            gps,
+                            accuracy: class_b::DEVICE_TIME_ACCURACY,
+                        });
+                    }
+                }
+                mac::FromNetworkServer::Ans(mac::AnsFromNetworkServer::LinkCheck(_)) => {
+                    // TODO: surface LinkCheckAns to the application
+                }
+                mac::FromNetworkServer::Ans(
+                    mac::AnsFromNetworkServer::PingSlotInfo
+                    | mac::AnsFromNetworkServer::BeaconTiming(_),
+                ) => {
+                    // PingSlotInfoReq and BeaconTimingReq are never sent
+                }
             }
         }
+
+        Ok(())
     }
 
+    /// Queue a MAC answer to be sent in the next uplink
     pub fn send_mac_answer(&mut self, mac_answer: mac::AnsFromEndDevice) -> Result<(), ()> {
-        todo!()
+        if mac_answer.is_sticky() {
+            self.sticky_mac_answers.push(mac_answer)
+        } else {
+            self.pending_mac_answers.push(mac_answer)
+        }
+        .map_err(|_| ())
+    }
+
+    /// All MAC answers and indications that should be placed in the next uplink
+    pub fn uplink_mac_answers(&self) -> impl Iterator<Item = mac::AnsFromEndDevice> + '_ {
+        self.sticky_mac_answers
+            .iter()
+            .chain(self.pending_mac_answers.iter())
+            .copied()
+            .chain(self.pending_indications())
+    }
+
+    /// Record that the answers from `uplink_mac_answers()` were sent in an uplink
+    ///
+    /// Sticky answers and indications are retained until the Network Server acknowledges them.
+    pub fn uplink_mac_answers_sent(&mut self) {
+        self.pending_mac_answers.clear();
+    }
+
+    /// Record that a valid Class A downlink was received, which stops the repetition of sticky
+    /// answers
+    ///
+    /// This must be called before processing any MAC commands in the downlink, as those commands
+    /// may generate new sticky answers.
+    pub fn class_a_downlink_received(&mut self) {
+        self.sticky_mac_answers.clear();
+    }
+
+    /// Copy state which must survive a reset into `storage`
+    pub fn persist(&self, storage: &mut EndDeviceStorage) {
+        storage.activation = self.activation;
+        storage.frame_count_uplink = self.frame_count_uplink;
+        storage.frame_count_downlink = self.frame_count_downlink;
+        storage.sticky_mac_answers = self.sticky_mac_answers;
+    }
+
+    /// Restore state previously saved by [`EndDevice::persist`]
+    pub fn restore(&mut self, storage: &EndDeviceStorage) {
+        self.activation = storage.activation;
+        self.frame_count_uplink = storage.frame_count_uplink;
+        self.frame_count_downlink = storage.frame_count_downlink;
+        self.sticky_mac_answers = storage.sticky_mac_answers;
+    }
+}
+
+/// A channel defined by the Network Server. New channels share the modulation parameters of the
+/// band's first default channel, and also its data rates if `data_rates` is `None`.
+fn new_uplink_channel(
+    band: &impl parameters::Band,
+    frequency: Frequency,
+    data_rates: Option<(DataRate, DataRate)>,
+) -> ChannelDetails {
+    let default = band.upstream_channels().get_move(0);
+    let (data_rate_min, data_rate_max) = data_rates
+        .or(default.map(|c| (c.data_rate_min, c.data_rate_max)))
+        .unwrap_or((DataRate::_0, DataRate::_0));
+    ChannelDetails {
+        frequency,
+        bandwidth: default
+            .map(|c| c.bandwidth)
+            .unwrap_or(Frequency::from_khz(125)),
+        data_rate_min,
+        data_rate_max,
+        coding_rate: default.and_then(|c| c.coding_rate),
+    }
+}
+
+/// Source of random numbers, used to randomize channel selection and retransmission timing
+pub trait Rng {
+    fn next_u32(&mut self) -> u32;
+}
+
+/// Keys and identifiers used to join a network by Over-The-Air Activation (OTAA)
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct OtaaCredentials {
+    pub join_eui: u64,
+    pub dev_eui: u64,
+    pub app_key: [u8; 16],
+}
+
+/// A Join-Request which has been sent, see [`EndDevice::join_request`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct PendingJoin {
+    pub dev_nonce: u16,
+    /// Uplink channel index the Join-Request was sent on
+    pub channel: u8,
+    pub data_rate: DataRate,
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub enum JoinError<C: Clock> {
+    /// No band is selected, or the band isn't implemented
+    NoBand,
+    /// Duty cycle limits don't allow transmitting yet
+    Delayed(UplinkDelayed<C>),
+    BufferTooSmall,
+    /// All DevNonce values have been used with this JoinEUI
+    DevNonceExhausted,
+    /// No Join-Request is awaiting a Join-Accept
+    NotJoining,
+    /// The JoinNonce isn't greater than that of the last accepted Join-Accept
+    JoinNonceReplayed,
+    TimeOverflow,
+    Decode(mac_frame::PhyPayloadDecodeError),
+    Decrypt(mac_frame::JoinAcceptDecryptError),
+    Parse(mac_frame::JoinAcceptParseError),
+}
+
+/// How the radio should transmit an uplink
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct TxDetails {
+    /// Uplink channel index
+    pub channel: u8,
+    pub frequency: Frequency,
+    pub data_rate: DataRate,
+    /// EIRP, see [`EndDevice::tx_power_dbm`]
+    pub tx_power_dbm: i8,
+    /// Length of the frame to transmit
+    pub len: usize,
+}
+
+/// A receive window the radio must open
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub struct RxWindow<C: Clock> {
+    pub start: Instant<C>,
+    pub frequency: Frequency,
+    pub data_rate: DataRate,
+}
+
+impl<C: Clock> Clone for RxWindow<C> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
+impl<C: Clock> Copy for RxWindow<C> {}
+
+/// Add a `Duration` to an `Instant`, returning `None` on overflow
+pub(crate) fn instant_add<C: Clock>(instant: Instant<C>, duration: Duration) -> Option<Instant<C>> {
+    use embedded_time::duration::{Microseconds, Milliseconds};
+
+    // prefer microsecond precision, but fall back to milliseconds for long durations which would
+    // overflow a u32 count of microseconds (~71 minutes)
+    if let Ok(us) = Microseconds::<u32>::try_from(duration) {
+        instant.checked_add(us)
+    } else {
+        instant.checked_add(Milliseconds::<u32>::try_from(duration).ok()?)
+    }
+}
+
+/// An uplink can't be transmitted yet due to duty cycle limits
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct UplinkDelayed<C: Clock> {
+    /// Earliest time the uplink may be transmitted
+    pub earliest: Instant<C>,
+}
+
+/// No uplink channel can be used, see [`EndDevice::select_uplink_channel`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct NoChannelAvailable<C: Clock> {
+    /// Earliest time a channel may be used, `None` if no enabled channel supports the data rate
+    pub earliest: Option<Instant<C>>,
+}
+
 /// Meta radio reciever provides about a recieved message
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct MessageRecvMeta<C: Clock> {
     pub power_db: u32,
+    /// Signal to noise ratio of the received message, in dB
+    pub snr_db: i8,
     pub time: Instant<C>,
     // TODO: consider if in some cases we need to record modulation information here
 }
 
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for MessageRecvMeta<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for MessageRecvMeta<C> {}
+
 /// Representation of the `Network Server` view of a device
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
@@ -447,18 +1948,44 @@ pub struct Network {
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct EndDeviceStorageActivation {
+    pub kind: ActivationKind,
     pub dev_addr: DevAddr,
-    pub network_session_key: (),
-    pub application_session_key: (),
+    /// NwkSKey
+    pub network_session_key: [u8; 16],
+    /// AppSKey
+    pub application_session_key: [u8; 16],
 }
 
+/// How a session was established
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationKind {
+    /// Over-The-Air Activation, by joining
+    Otaa,
+    /// Activation By Personalization
+    Abp,
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct EndDeviceStorage {
-    pub activation: EndDeviceStorageActivation,
+    /// `None` until the device has joined
+    pub activation: Option<EndDeviceStorageActivation>,
 
     /// Used for 6.2.5 Join-Request frame.
     pub dev_nonce: u16,
+
+    /// JoinNonce of the last accepted Join-Accept, `None` if the device never joined
+    pub join_nonce: Option<u32>,
+
+    /// FCntUp of the session in `activation`
+    pub frame_count_uplink: u32,
+
+    /// FCntDown of the session in `activation`
+    pub frame_count_downlink: u32,
+
+    /// Answers that must be repeated until a downlink is received
+    pub sticky_mac_answers: mac::PendingAnswers,
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
@@ -470,25 +1997,68 @@ pub struct JoinServer {
 }
 
 /// Calculate the NwkSKey on the end-device
-pub fn end_device_network_skey(join_nonce: [u8; 3], net_id: [u8; 3], dev_nonce: [u8; 3]) -> () {
+pub fn end_device_network_skey(
+    app_key: &[u8; 16],
+    join_nonce: [u8; 3],
+    net_id: [u8; 3],
+    dev_nonce: u16,
+) -> [u8; 16] {
     // NwkSKey = aes128_encrypt(AppKey, 0x01 | JoinNonce | NetID | DevNonce | pad_16)
-    todo!()
+    mac_frame::session_key(0x01, app_key, join_nonce, net_id, dev_nonce)
 }
 
-pub fn end_device_app_skey(join_nonce: [u8; 3], net_id: [u8; 3], dev_nonce: [u8; 3]) -> () {
+pub fn end_device_app_skey(
+    app_key: &[u8; 16],
+    join_nonce: [u8; 3],
+    net_id: [u8; 3],
+    dev_nonce: u16,
+) -> [u8; 16] {
     // AppSKey = aes128_encrypt(AppKey, 0x02 | JoinNonce | NetID | DevNonce | pad_16)
-    todo!()
+    mac_frame::session_key(0x02, app_key, join_nonce, net_id, dev_nonce)
 }
 
+/// Number of multicast groups an end-device can be in, McGroupID being in
+/// `0..MULTICAST_GROUPS_MAX`
+pub const MULTICAST_GROUPS_MAX: usize = 4;
+
+/// A multicast group the end-device receives downlinks of, see
+/// [`EndDevice::set_multicast_group`]
+///
+/// Multicast downlinks are only received in Class B ping slots and Class C windows, they are never
+/// confirmed and don't carry MAC commands.
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
-#[derive(Debug, Clone, Copy)]
-pub struct MulticastGroup {
-    pub network_address: [u8; 4],
-    // FIXME: not sure of the type here
-    pub session_keys: (),
+#[derive(Debug)]
+pub struct MulticastGroup<C: Clock> {
+    /// McAddr
+    pub address: DevAddr,
+    /// McNwkSKey
+    pub network_session_key: [u8; 16],
+    /// McAppSKey
+    pub application_session_key: [u8; 16],
+    /// Lowest frame counter accepted for the next downlink, starting at minMcFCnt
     pub downlink_frame_counter: u32,
+    /// maxMcFCnt: downlinks with a higher frame counter are rejected
+    pub max_frame_count: u32,
+    /// Ping slots of the group, opened while the device operates in Class B
+    pub class_b: Option<class_b::PingSlots>,
+    /// Class C session of the group, during which the device listens continuously
+    pub class_c: Option<class_c::Session<C>>,
 }
 
+/// McGroupID isn't below [`MULTICAST_GROUPS_MAX`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidMulticastGroupId;
+
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for MulticastGroup<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for MulticastGroup<C> {}
+
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct NetworkServer<C> {
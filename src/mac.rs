@@ -2,6 +2,11 @@
 
 use modular_bitfield::prelude::*;
 
+// NOTE: `#[bitfield]` fields are listed from the least significant bit, the reverse of the
+// specification's diagrams
+
+use crate::DeviceClass;
+
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub enum ReqFromNetworkServer {
@@ -13,6 +18,27 @@ pub enum ReqFromNetworkServer {
     RxTimingSetup(RxTimingSetupReq),
     TxParamSetup(TxParamSetupReq),
     DlChannel(DlChannelReq),
+
+    /// LoRaWAN 1.1: confirmation of a [`AnsFromEndDevice::Reset`] indication
+    Reset(ResetConf),
+    /// LoRaWAN 1.1: confirmation of a [`AnsFromEndDevice::Rekey`] indication
+    Rekey(RekeyConf),
+    /// LoRaWAN 1.1
+    AdrParamSetup(AdrParamSetupReq),
+    /// LoRaWAN 1.1. Has no answer, the end-device responds with Rejoin-Request frames instead.
+    ForceRejoin(ForceRejoinReq),
+    /// LoRaWAN 1.1
+    RejoinParamSetup(RejoinParamSetupReq),
+    /// LoRaWAN 1.1: confirmation of a [`AnsFromEndDevice::DeviceMode`] indication
+    DeviceMode(DeviceModeConf),
+
+    /// Class B
+    PingSlotChannel(PingSlotChannelReq),
+    /// Class B
+    BeaconFreq(BeaconFreqReq),
+
+    /// CIDs 0x80..=0xFF, decoded using a [`ProprietaryHandler`]
+    Proprietary(ProprietaryCommand),
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
@@ -27,6 +53,11 @@ pub enum ReqFromEndDevice {
 pub enum AnsFromNetworkServer {
     /// Related: [`MacCommandCid::LinkCheck`], [`Req::LinkCheck`], [`LinkCheck`]
     LinkCheck(LinkCheckAns),
+    DeviceTime(DeviceTimeAns),
+    /// Class B: acknowledges a `PingSlotInfoReq`
+    PingSlotInfo,
+    /// Class B, deprecated in favour of [`AnsFromNetworkServer::DeviceTime`]
+    BeaconTiming(BeaconTimingAns),
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
@@ -39,6 +70,22 @@ pub enum AnsFromEndDevice {
     NewChannel(NewChannelAns),
     DlChannel(DlChannelAns),
     RxTimingSetup,
+    TxParamSetup,
+
+    /// LoRaWAN 1.1: sent by ABP devices until a [`ReqFromNetworkServer::Reset`] is received
+    Reset(ResetInd),
+    /// LoRaWAN 1.1: sent by OTAA devices after joining until a [`ReqFromNetworkServer::Rekey`]
+    /// is received
+    Rekey(RekeyInd),
+    AdrParamSetup,
+    RejoinParamSetup(RejoinParamSetupAns),
+    /// LoRaWAN 1.1: sent until a [`ReqFromNetworkServer::DeviceMode`] is received
+    DeviceMode(DeviceModeInd),
+    PingSlotChannel(PingSlotChannelAns),
+    BeaconFreq(BeaconFreqAns),
+
+    /// Answer produced by a [`ProprietaryHandler`]
+    Proprietary(ProprietaryCommand),
 }
 
 /// Either sent as a FRMPayload with FPort = 0 or piggybacked in the FOpts field.
@@ -46,6 +93,8 @@ pub enum AnsFromEndDevice {
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub enum MacCommandCid {
+    /// ResetInd, ResetConf (LoRaWAN 1.1)
+    Reset = 0x01,
     /// LinkCheckReq, LinkCheckAns
     LinkCheck = 0x02,
     /// LinkADRReq, LinkADRAns
@@ -58,10 +107,28 @@ pub enum MacCommandCid {
     RxTimingSetup = 0x08,
     TxParamSetup = 0x09,
     DlChannel = 0x0A,
+    /// RekeyInd, RekeyConf (LoRaWAN 1.1)
+    Rekey = 0x0B,
+    /// ADRParamSetupReq, ADRParamSetupAns (LoRaWAN 1.1)
+    AdrParamSetup = 0x0C,
     DeviceTime = 0x0D,
-    // TODO: 0x10..=0x1F: Class B Commands
-    // TODO: 0x20..=0x2F: Class C commands
-    // TODO: 0x80..=0xFF: Proprietary network command extensions
+    /// ForceRejoinReq (LoRaWAN 1.1)
+    ForceRejoin = 0x0E,
+    /// RejoinParamSetupReq, RejoinParamSetupAns (LoRaWAN 1.1)
+    RejoinParamSetup = 0x0F,
+    /// PingSlotInfoReq, PingSlotInfoAns (Class B)
+    PingSlotInfo = 0x10,
+    /// PingSlotChannelReq, PingSlotChannelAns (Class B)
+    PingSlotChannel = 0x11,
+    /// BeaconTimingReq, BeaconTimingAns (Class B, deprecated)
+    BeaconTiming = 0x12,
+    /// BeaconFreqReq, BeaconFreqAns (Class B)
+    BeaconFreq = 0x13,
+    // TODO: 0x14..=0x1F: Class B Commands
+    /// DeviceModeInd, DeviceModeConf (LoRaWAN 1.1)
+    DeviceMode = 0x20,
+    // TODO: 0x21..=0x2F: Class C commands
+    // NOTE: 0x80..=0xFF: Proprietary network command extensions, see `ProprietaryHandler`
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
@@ -75,49 +142,51 @@ pub struct LinkCheckAns {
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct LinkAdrReq {
-    pub data_rate: B4,
     pub tx_power: B4,
+    pub data_rate: B4,
     pub ch_mask: u16,
-    pub rfu: bool,
-    pub channel_mask_ctrl: B3,
     pub nb_trans: B4,
+    pub channel_mask_ctrl: B3,
+    pub rfu: bool,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct LinkAdrAns {
-    pub rfu: B5,
-    pub power_ack: bool,
-    pub data_rate_ack: bool,
     pub channel_mask_ack: bool,
+    pub data_rate_ack: bool,
+    pub power_ack: bool,
+    pub rfu: B5,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct DutyCycleReq {
-    pub rfu: B4,
     pub max_duty_cycle: B4,
+    pub rfu: B4,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct RxParamSetupReq {
-    pub rfu: bool,
-    pub rx1_data_rate_offset: B3,
     pub rx2_data_rate: B4,
+    pub rx1_data_rate_offset: B3,
+    pub rfu: bool,
+    /// RX2 frequency, in units of 100Hz
+    pub frequency: B24,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct RxParamSetupAns {
-    pub rfu: B5,
-    pub rx1_data_rate_offset_ack: bool,
-    pub rx2_data_rate_ack: bool,
     pub channel_ack: bool,
+    pub rx2_data_rate_ack: bool,
+    pub rx1_data_rate_offset_ack: bool,
+    pub rfu: B5,
 }
 
 #[bitfield]
@@ -125,8 +194,22 @@ pub struct RxParamSetupAns {
 #[derive(Debug, Clone, Copy)]
 pub struct DevStatusAns {
     pub battery: u8,
-    pub rfu: B3,
-    pub snr: B5,
+    /// 6 bit signed SNR margin, use [`DevStatusAns::margin`] and [`DevStatusAns::with_margin`]
+    pub snr: B6,
+    pub rfu: B2,
+}
+
+impl DevStatusAns {
+    /// SNR of the last received `DevStatusReq`, in dB
+    pub fn margin(&self) -> i8 {
+        // sign extend from 6 bits
+        ((self.snr() << 2) as i8) >> 2
+    }
+
+    /// Set the margin (in dB), clamped to the range -32..=31
+    pub fn with_margin(self, snr_db: i8) -> Self {
+        self.with_snr(snr_db.clamp(-32, 31) as u8 & 0x3F)
+    }
 }
 
 #[bitfield]
@@ -135,17 +218,17 @@ pub struct DevStatusAns {
 pub struct NewChannelReq {
     pub channel_index: u8,
     pub frequency: B24,
-    pub max_data_rate: B4,
     pub min_data_rate: B4,
+    pub max_data_rate: B4,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct NewChannelAns {
-    pub rfu: B6,
-    pub data_rate_range_ok: bool,
     pub channel_frequency_ok: bool,
+    pub data_rate_range_ok: bool,
+    pub rfu: B6,
 }
 
 #[bitfield]
@@ -160,27 +243,38 @@ pub struct DlChannelReq {
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct DlChannelAns {
-    pub rfu: B6,
-    pub uplink_frequency_exists: bool,
     pub channel_frequency_ok: bool,
+    pub uplink_frequency_exists: bool,
+    pub rfu: B6,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct RxTimingSetupReq {
-    pub rfu: B5,
-    pub delay_seconds: B3,
+    pub delay_seconds: B4,
+    pub rfu: B4,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct TxParamSetupReq {
-    pub rfu: B2,
-    pub downlink_dwell_time: bool,
-    pub uplink_dwell_time: bool,
     pub max_eirp: B4,
+    pub uplink_dwell_time: bool,
+    pub downlink_dwell_time: bool,
+    pub rfu: B2,
+}
+
+/// MaxEIRP (in dBm) for each `TxParamSetupReq::max_eirp` index
+pub const MAX_EIRP_DBM: [i8; 16] = [
+    8, 10, 12, 13, 14, 16, 18, 20, 21, 24, 26, 27, 29, 30, 33, 36,
+];
+
+impl TxParamSetupReq {
+    pub fn max_eirp_dbm(&self) -> i8 {
+        MAX_EIRP_DBM[self.max_eirp() as usize]
+    }
 }
 
 #[bitfield]
@@ -190,3 +284,536 @@ pub struct DeviceTimeAns {
     pub seconds_since_epoch: u32,
     pub fraction_seconds: u8,
 }
+
+/// LoRaWAN minor version supported by the end-device. `minor` is `1` for LoRaWAN 1.1
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct ResetInd {
+    pub minor: B4,
+    pub rfu: B4,
+}
+
+/// LoRaWAN minor version of the Network Server, echoing the one from [`ResetInd`]
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct ResetConf {
+    pub minor: B4,
+    pub rfu: B4,
+}
+
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct RekeyInd {
+    pub minor: B4,
+    pub rfu: B4,
+}
+
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct RekeyConf {
+    pub minor: B4,
+    pub rfu: B4,
+}
+
+/// `ADR_ACK_LIMIT = 2^limit_exp`, `ADR_ACK_DELAY = 2^delay_exp`
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct AdrParamSetupReq {
+    pub delay_exp: B4,
+    pub limit_exp: B4,
+}
+
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct ForceRejoinReq {
+    pub data_rate: B4,
+    /// 0 or 1: a Type 0 Rejoin-Request, 2: a Type 2 Rejoin-Request, 3..=7: RFU
+    pub rejoin_type: B3,
+    pub rfu1: bool,
+
+    /// Number of retransmissions after the first Rejoin-Request. 0 means it is sent once.
+    pub max_retries: B3,
+    /// Delay between retransmissions is `32s * 2^period + rand(0..32s)`
+    pub period: B3,
+    pub rfu2: B2,
+}
+
+/// Type 0 Rejoin-Requests are sent every `2^(max_count_n + 4)` uplinks or every
+/// `2^(max_time_n + 10)` seconds, whichever comes first.
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct RejoinParamSetupReq {
+    pub max_count_n: B4,
+    pub max_time_n: B4,
+}
+
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct RejoinParamSetupAns {
+    /// Set if the end-device accepted the time based limit (`max_time_n`)
+    pub time_ok: bool,
+    pub rfu: B7,
+}
+
+/// Class the end-device requests to operate in. Use [`DeviceModeInd::for_class`] to construct.
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceModeInd {
+    pub class: u8,
+}
+
+impl DeviceModeInd {
+    /// Returns `None` for [`DeviceClass::B`], which is not switched to via `DeviceModeInd`
+    pub fn for_class(class: DeviceClass) -> Option<Self> {
+        device_mode_class_value(class).map(|v| Self::new().with_class(v))
+    }
+}
+
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceModeConf {
+    pub class: u8,
+}
+
+impl DeviceModeConf {
+    /// Returns `None` if the class value is RFU
+    pub fn device_class(&self) -> Option<DeviceClass> {
+        match self.class() {
+            0x00 => Some(DeviceClass::A),
+            0x02 => Some(DeviceClass::C),
+            _ => None,
+        }
+    }
+}
+
+/// Frequency and data rate of ping slots. A frequency of 0 selects the band's default ping slot
+/// channels.
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct PingSlotChannelReq {
+    /// In units of 100Hz
+    pub frequency: B24,
+    pub data_rate: B4,
+    pub rfu: B4,
+}
+
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct PingSlotChannelAns {
+    pub channel_frequency_ok: bool,
+    pub data_rate_ok: bool,
+    pub rfu: B6,
+}
+
+/// Time until the next beacon, and the channel it is sent on
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct BeaconTimingAns {
+    /// In units of 30ms, from the end of the uplink containing `BeaconTimingReq`
+    pub delay: u16,
+    pub channel: u8,
+}
+
+/// Frequency of beacons. A frequency of 0 selects the band's default beacon channels.
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct BeaconFreqReq {
+    /// In units of 100Hz
+    pub frequency: B24,
+}
+
+#[bitfield]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct BeaconFreqAns {
+    pub beacon_frequency_ok: bool,
+    pub rfu: B7,
+}
+
+fn device_mode_class_value(class: DeviceClass) -> Option<u8> {
+    match class {
+        DeviceClass::A => Some(0x00),
+        DeviceClass::B => None,
+        DeviceClass::C => Some(0x02),
+    }
+}
+
+/// Maximum number of [`AnsFromEndDevice`] which may be waiting for an uplink at once
+pub const PENDING_ANSWERS_MAX: usize = 16;
+
+/// There was no space left to queue another [`AnsFromEndDevice`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct PendingAnswersFull;
+
+/// MAC answers (and indications) waiting to be placed into the next uplink
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PendingAnswers {
+    answers: [Option<AnsFromEndDevice>; PENDING_ANSWERS_MAX],
+    len: usize,
+}
+
+impl PendingAnswers {
+    /// Queue an answer. If there is no space remaining, the answer is returned back.
+    pub fn push(&mut self, answer: AnsFromEndDevice) -> Result<(), AnsFromEndDevice> {
+        match self.answers.get_mut(self.len) {
+            Some(slot) => {
+                *slot = Some(answer);
+                self.len += 1;
+                Ok(())
+            }
+            None => Err(answer),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AnsFromEndDevice> {
+        self.answers[..self.len].iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Discard all answers, normally done once they have been placed into an uplink
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Maximum length of the payload of a [`ProprietaryCommand`], matching the maximum size of
+/// `FOpts`
+pub const PROPRIETARY_PAYLOAD_MAX: usize = 15;
+
+/// A MAC command with a CID in the proprietary range (0x80..=0xFF)
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct ProprietaryCommand {
+    pub cid: u8,
+    len: u8,
+    payload: [u8; PROPRIETARY_PAYLOAD_MAX],
+}
+
+impl ProprietaryCommand {
+    /// Returns `None` if `cid` is not in the proprietary range or `payload` is longer than
+    /// [`PROPRIETARY_PAYLOAD_MAX`]
+    pub fn new(cid: u8, payload: &[u8]) -> Option<Self> {
+        if cid < 0x80 || payload.len() > PROPRIETARY_PAYLOAD_MAX {
+            return None;
+        }
+
+        let mut p = [0u8; PROPRIETARY_PAYLOAD_MAX];
+        p[..payload.len()].copy_from_slice(payload);
+        Some(Self {
+            cid,
+            len: payload.len() as u8,
+            payload: p,
+        })
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+}
+
+/// Application provided handling of proprietary MAC commands (CIDs 0x80..=0xFF)
+///
+/// MAC commands are not self-delimiting, so without knowing the length of a proprietary command
+/// the remaining commands in the same frame can not be decoded.
+pub trait ProprietaryHandler {
+    /// Length of the payload (excluding the CID) of the proprietary command `cid` sent by the
+    /// Network Server, or `None` if `cid` is not known
+    fn request_len(&self, cid: u8) -> Option<usize>;
+
+    /// Handle a proprietary command sent by the Network Server. A returned answer is sent in the
+    /// next uplink.
+    fn handle_request(&mut self, request: &ProprietaryCommand) -> Option<ProprietaryCommand>;
+}
+
+/// No proprietary commands are supported
+impl ProprietaryHandler for () {
+    fn request_len(&self, _cid: u8) -> Option<usize> {
+        None
+    }
+
+    fn handle_request(&mut self, _request: &ProprietaryCommand) -> Option<ProprietaryCommand> {
+        None
+    }
+}
+
+/// A MAC command sent by the Network Server, found in downlink `FOpts` or `FRMPayload` (with
+/// `FPort` = 0)
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub enum FromNetworkServer {
+    Req(ReqFromNetworkServer),
+    Ans(AnsFromNetworkServer),
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacCommandParseError {
+    /// The CID is not known, so the length of it (and the position of any following commands) is
+    /// unknown
+    UnknownCid { cid: u8 },
+    /// The command was cut off
+    Truncated { cid: u8, have: usize, need: usize },
+}
+
+/// Decode the first MAC command in `bytes`, returning it and the remaining bytes
+pub fn parse_downlink_command<'a, P: ProprietaryHandler + ?Sized>(
+    bytes: &'a [u8],
+    proprietary: &P,
+) -> Result<(FromNetworkServer, &'a [u8]), MacCommandParseError> {
+    use FromNetworkServer::{Ans, Req};
+
+    let (&cid, rem) = bytes.split_first().ok_or(MacCommandParseError::Truncated {
+        cid: 0,
+        have: 0,
+        need: 1,
+    })?;
+
+    let need = match cid {
+        0x01 | 0x04 | 0x08 | 0x09 | 0x0B | 0x0C | 0x0F | 0x20 => 1,
+        0x02 | 0x0E => 2,
+        0x12 | 0x13 => 3,
+        0x03 | 0x05 | 0x0A | 0x11 => 4,
+        0x06 | 0x10 => 0,
+        0x07 | 0x0D => 5,
+        0x80..=0xFF => match proprietary.request_len(cid) {
+            Some(len) if len <= PROPRIETARY_PAYLOAD_MAX => len,
+            _ => return Err(MacCommandParseError::UnknownCid { cid }),
+        },
+        _ => return Err(MacCommandParseError::UnknownCid { cid }),
+    };
+
+    if rem.len() < need {
+        return Err(MacCommandParseError::Truncated {
+            cid,
+            have: rem.len(),
+            need,
+        });
+    }
+
+    let (p, rem) = rem.split_at(need);
+    let cmd = match cid {
+        0x01 => Req(ReqFromNetworkServer::Reset(ResetConf::from_bytes([p[0]]))),
+        0x02 => Ans(AnsFromNetworkServer::LinkCheck(LinkCheckAns {
+            margin: p[0],
+            gw_count: p[1],
+        })),
+        0x03 => Req(ReqFromNetworkServer::LinkAdr(LinkAdrReq::from_bytes(
+            p.try_into().unwrap(),
+        ))),
+        0x04 => Req(ReqFromNetworkServer::DutyCycle(DutyCycleReq::from_bytes([
+            p[0],
+        ]))),
+        0x05 => Req(ReqFromNetworkServer::RxParamSetup(
+            RxParamSetupReq::from_bytes(p.try_into().unwrap()),
+        )),
+        0x06 => Req(ReqFromNetworkServer::DevStatus),
+        0x07 => Req(ReqFromNetworkServer::NewChannel(NewChannelReq::from_bytes(
+            p.try_into().unwrap(),
+        ))),
+        0x08 => Req(ReqFromNetworkServer::RxTimingSetup(
+            RxTimingSetupReq::from_bytes([p[0]]),
+        )),
+        0x09 => Req(ReqFromNetworkServer::TxParamSetup(
+            TxParamSetupReq::from_bytes([p[0]]),
+        )),
+        0x0A => Req(ReqFromNetworkServer::DlChannel(DlChannelReq::from_bytes(
+            p.try_into().unwrap(),
+        ))),
+        0x0B => Req(ReqFromNetworkServer::Rekey(RekeyConf::from_bytes([p[0]]))),
+        0x0C => Req(ReqFromNetworkServer::AdrParamSetup(
+            AdrParamSetupReq::from_bytes([p[0]]),
+        )),
+        0x0D => Ans(AnsFromNetworkServer::DeviceTime(DeviceTimeAns::from_bytes(
+            p.try_into().unwrap(),
+        ))),
+        0x0E => Req(ReqFromNetworkServer::ForceRejoin(
+            ForceRejoinReq::from_bytes(p.try_into().unwrap()),
+        )),
+        0x0F => Req(ReqFromNetworkServer::RejoinParamSetup(
+            RejoinParamSetupReq::from_bytes([p[0]]),
+        )),
+        0x10 => Ans(AnsFromNetworkServer::PingSlotInfo),
+        0x11 => Req(ReqFromNetworkServer::PingSlotChannel(
+            PingSlotChannelReq::from_bytes(p.try_into().unwrap()),
+        )),
+        0x12 => Ans(AnsFromNetworkServer::BeaconTiming(
+            BeaconTimingAns::from_bytes(p.try_into().unwrap()),
+        )),
+        0x13 => Req(ReqFromNetworkServer::BeaconFreq(BeaconFreqReq::from_bytes(
+            p.try_into().unwrap(),
+        ))),
+        0x20 => Req(ReqFromNetworkServer::DeviceMode(
+            DeviceModeConf::from_bytes([p[0]]),
+        )),
+        // checked above
+        _ => Req(ReqFromNetworkServer::Proprietary(
+            ProprietaryCommand::new(cid, p).unwrap(),
+        )),
+    };
+
+    Ok((cmd, rem))
+}
+
+/// Iterator over the MAC commands sent by the Network Server in `FOpts` or a `FRMPayload`
+///
+/// Iteration ends after the first error, as the position of any following commands is unknown.
+pub struct DownlinkCommands<'a, P: ?Sized> {
+    bytes: &'a [u8],
+    proprietary: &'a P,
+}
+
+impl<'a, P: ProprietaryHandler + ?Sized> DownlinkCommands<'a, P> {
+    pub fn new(bytes: &'a [u8], proprietary: &'a P) -> Self {
+        Self { bytes, proprietary }
+    }
+}
+
+impl<'a, P: ProprietaryHandler + ?Sized> Iterator for DownlinkCommands<'a, P> {
+    type Item = Result<FromNetworkServer, MacCommandParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        match parse_downlink_command(self.bytes, self.proprietary) {
+            Ok((cmd, rem)) => {
+                self.bytes = rem;
+                Some(Ok(cmd))
+            }
+            Err(e) => {
+                self.bytes = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferTooSmall;
+
+impl AnsFromEndDevice {
+    /// "Sticky" answers must be sent in every uplink until a Class A downlink is received, as the
+    /// Network Server has no other way to know the end-device received the request
+    pub fn is_sticky(&self) -> bool {
+        matches!(
+            self,
+            AnsFromEndDevice::RxParamSetup(_)
+                | AnsFromEndDevice::RxTimingSetup
+                | AnsFromEndDevice::DlChannel(_)
+        )
+    }
+
+    pub fn cid(&self) -> u8 {
+        match self {
+            AnsFromEndDevice::Reset(_) => MacCommandCid::Reset as u8,
+            AnsFromEndDevice::LinkAdr(_) => MacCommandCid::LinkAdr as u8,
+            AnsFromEndDevice::DutyCycle => MacCommandCid::DutyCycle as u8,
+            AnsFromEndDevice::RxParamSetup(_) => MacCommandCid::RxParamSetup as u8,
+            AnsFromEndDevice::DevStatus(_) => MacCommandCid::DevStatus as u8,
+            AnsFromEndDevice::NewChannel(_) => MacCommandCid::NewChannel as u8,
+            AnsFromEndDevice::RxTimingSetup => MacCommandCid::RxTimingSetup as u8,
+            AnsFromEndDevice::TxParamSetup => MacCommandCid::TxParamSetup as u8,
+            AnsFromEndDevice::DlChannel(_) => MacCommandCid::DlChannel as u8,
+            AnsFromEndDevice::Rekey(_) => MacCommandCid::Rekey as u8,
+            AnsFromEndDevice::AdrParamSetup => MacCommandCid::AdrParamSetup as u8,
+            AnsFromEndDevice::RejoinParamSetup(_) => MacCommandCid::RejoinParamSetup as u8,
+            AnsFromEndDevice::DeviceMode(_) => MacCommandCid::DeviceMode as u8,
+            AnsFromEndDevice::PingSlotChannel(_) => MacCommandCid::PingSlotChannel as u8,
+            AnsFromEndDevice::BeaconFreq(_) => MacCommandCid::BeaconFreq as u8,
+            AnsFromEndDevice::Proprietary(p) => p.cid,
+        }
+    }
+
+    /// Number of bytes used by this answer when encoded, including the CID
+    pub fn encoded_len(&self) -> usize {
+        1 + match self {
+            AnsFromEndDevice::DutyCycle
+            | AnsFromEndDevice::RxTimingSetup
+            | AnsFromEndDevice::TxParamSetup
+            | AnsFromEndDevice::AdrParamSetup => 0,
+            AnsFromEndDevice::Reset(_)
+            | AnsFromEndDevice::LinkAdr(_)
+            | AnsFromEndDevice::RxParamSetup(_)
+            | AnsFromEndDevice::NewChannel(_)
+            | AnsFromEndDevice::DlChannel(_)
+            | AnsFromEndDevice::Rekey(_)
+            | AnsFromEndDevice::RejoinParamSetup(_)
+            | AnsFromEndDevice::DeviceMode(_)
+            | AnsFromEndDevice::PingSlotChannel(_)
+            | AnsFromEndDevice::BeaconFreq(_) => 1,
+            AnsFromEndDevice::DevStatus(_) => 2,
+            AnsFromEndDevice::Proprietary(p) => p.payload().len(),
+        }
+    }
+
+    /// Encode this answer (CID followed by payload) into the start of `buf`, returning the number
+    /// of bytes used
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, BufferTooSmall> {
+        let len = self.encoded_len();
+        let buf = buf.get_mut(..len).ok_or(BufferTooSmall)?;
+        buf[0] = self.cid();
+
+        let p = &mut buf[1..];
+        match self {
+            AnsFromEndDevice::DutyCycle
+            | AnsFromEndDevice::RxTimingSetup
+            | AnsFromEndDevice::TxParamSetup
+            | AnsFromEndDevice::AdrParamSetup => {}
+            AnsFromEndDevice::Reset(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::LinkAdr(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::RxParamSetup(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::NewChannel(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::DlChannel(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::Rekey(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::RejoinParamSetup(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::DeviceMode(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::PingSlotChannel(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::BeaconFreq(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::DevStatus(v) => p.copy_from_slice(&v.into_bytes()),
+            AnsFromEndDevice::Proprietary(v) => p.copy_from_slice(v.payload()),
+        }
+
+        Ok(len)
+    }
+}
+
+/// Maximum length of the MAC commands piggybacked in `FOpts`
+pub const FOPTS_MAX_LEN: usize = 15;
+
+/// Encode a sequence of answers back to back into `buf`, returning the number of bytes used
+pub fn encode_answers<'a>(
+    answers: impl IntoIterator<Item = &'a AnsFromEndDevice>,
+    buf: &mut [u8],
+) -> Result<usize, BufferTooSmall> {
+    let mut len = 0;
+    for answer in answers {
+        len += answer.encode(&mut buf[len..])?;
+    }
+
+    Ok(len)
+}
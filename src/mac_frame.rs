@@ -78,6 +78,13 @@ pub enum PayloadParseError {
     UnknownMajor { major: u8 },
     JoinRequestParseError(JoinRequestParseError),
     JoinAcceptParseError(JoinAcceptParseError),
+    FrameHeaderParseError(FrameHeaderParseError),
+}
+
+impl From<FrameHeaderParseError> for PayloadParseError {
+    fn from(other: FrameHeaderParseError) -> Self {
+        PayloadParseError::FrameHeaderParseError(other)
+    }
 }
 
 impl From<JoinRequestParseError> for PayloadParseError {
@@ -111,6 +118,33 @@ impl<T: AsRef<[u8]>, S> PhyPayload<T, S> {
         let start = self.bytes().len() - 4;
         self.bytes()[start..].try_into().unwrap()
     }
+
+    /// `aes128_cmac(key, MHDR | payload)[0..3]`, the MIC for Join-Request and Join-Accept frames
+    fn cmac_mic(&self, key: &[u8]) -> [u8; 4] {
+        let mut mac = Cmac::<Aes128>::new_from_slice(key).unwrap();
+        let end = self.bytes().len() - 4;
+        mac.update(&self.bytes()[..end]);
+        mac.finalize().into_bytes().as_slice()[..4]
+            .try_into()
+            .unwrap()
+    }
+}
+
+/// Join-Accept payloads are encrypted with `aes128_decrypt(AppKey, ...)`, so the end-device
+/// decrypts them with `aes128_encrypt(AppKey, ...)` (ECB, in 16 byte blocks)
+fn join_accept_decrypt_in_place(bytes: &mut [u8], app_key: &[u8]) {
+    let aes = <Aes128 as cipher::KeyInit>::new_from_slice(app_key).unwrap();
+    for block in bytes.chunks_exact_mut(16) {
+        cipher::BlockEncrypt::encrypt_block(&aes, GenericArray::from_mut_slice(block));
+    }
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinAcceptDecryptError {
+    NotJoinAccept { ftype: FrameType },
+    SizeMismatch { have: usize },
+    MicMismatch,
 }
 
 impl<T: AsMut<[u8]> + AsRef<[u8]>> PhyPayload<T, decode_state::Encrypted> {
@@ -137,13 +171,12 @@ impl<T: AsMut<[u8]> + AsRef<[u8]>> PhyPayload<T, decode_state::Encrypted> {
 
             FrameType::JoinAccept => {
                 // Encyption: aes128_decrypt(AppKey, JoinNonce | NetID | DevAddr | DLSettings | RXDelay | CFList | MIC)
-                let aes = <Aes128 as cipher::KeyInit>::new_from_slice(app_key).unwrap();
-                //aes.encrypt_block_inout(inout::InOutBuf::from_mut(self.bytes_mut().try_into().unwrap());
-                cipher::BlockEncrypt::encrypt_block_inout(
-                    &aes,
-                    GenericArray::from_mut_slice(self.bytes_mut()).into(),
-                );
-                todo!()
+                join_accept_decrypt_in_place(self.payload_and_mic_bytes_mut(), app_key);
+                return if self.cmac_mic(app_key) == self.mic() {
+                    Ok(())
+                } else {
+                    Err(())
+                };
             }
             FrameType::UnconfirmedDataUplink => {
                 todo!()
@@ -167,6 +200,32 @@ impl<T: AsMut<[u8]> + AsRef<[u8]>> PhyPayload<T, decode_state::Encrypted> {
 
         todo!("check the mic");
     }
+
+    /// Decrypt a Join-Accept received by the end-device and verify its MIC
+    pub fn decrypt_join_accept(
+        mut self,
+        app_key: &[u8; 16],
+    ) -> Result<PhyPayload<T, decode_state::Decrypted>, JoinAcceptDecryptError> {
+        let ftype = self.mac_header().ftype();
+        if ftype != FrameType::JoinAccept {
+            return Err(JoinAcceptDecryptError::NotJoinAccept { ftype });
+        }
+
+        let have = self.payload_and_mic_bytes_mut().len();
+        if have != 16 && have != 32 {
+            return Err(JoinAcceptDecryptError::SizeMismatch { have });
+        }
+
+        join_accept_decrypt_in_place(self.payload_and_mic_bytes_mut(), app_key);
+        if self.cmac_mic(app_key) != self.mic() {
+            return Err(JoinAcceptDecryptError::MicMismatch);
+        }
+
+        Ok(PhyPayload {
+            _type_state: PhantomData,
+            bytes: self.bytes,
+        })
+    }
 }
 
 impl<T: AsRef<[u8]>> PhyPayload<T, decode_state::Decrypted> {
@@ -180,31 +239,32 @@ impl<T: AsRef<[u8]>> PhyPayload<T, decode_state::Decrypted> {
                 //   CMAC = aes128_cmac(AppKey, MHDR | JoinEUI | DevEUI | DevNonce)
                 //   MIC = CMAC[0..3]
                 //
-                let mut mac = Cmac::<Aes128>::new_from_slice(app_key).unwrap();
-                let end = self.bytes().len() - 4;
-                mac.update(&self.bytes()[..end]);
-                mac.finalize().into_bytes().as_slice()[..4]
-                    .try_into()
-                    .unwrap()
+                self.cmac_mic(app_key)
             }
             FrameType::JoinAccept => {
                 // CMAC = aes128_cmac(AppKey, MHDR | JoinNonce | NetID | DevAddr | DLSettings | RXDelay | CFList)
                 // MIC = CMAC[0..3]
-                //
-                // FIXME: this needs a decrypted frame!
-                let mut mac = Cmac::<Aes128>::new_from_slice(app_key).unwrap();
-                let end = self.bytes().len() - 4;
-                mac.update(&self.bytes()[..end]);
-                mac.finalize().into_bytes().as_slice()[..4]
-                    .try_into()
-                    .unwrap()
+                self.cmac_mic(app_key)
             }
             _ => todo!(),
         }
     }
+
+    pub fn join_accept(&self) -> Result<JoinAccept<'_>, JoinAcceptParseError> {
+        JoinAccept::from_bytes(self.payload_bytes())
+    }
 }
 
 impl<T: AsRef<[u8]>> PhyPayload<T, decode_state::Encrypted> {
+    /// Join-Requests are never encrypted, so their MIC can be checked without decrypting. `None`
+    /// for any other frame type, which must be decrypted first.
+    pub fn mic_expected(&self, app_key: &[u8]) -> Option<[u8; 4]> {
+        match self.mac_header().ftype() {
+            FrameType::JoinRequest => Some(self.cmac_mic(app_key)),
+            _ => None,
+        }
+    }
+
     pub fn payload(&self) -> Result<Payload<'_>, PayloadParseError> {
         let mh = self.mac_header();
         if mh.major() != 0 {
@@ -216,7 +276,7 @@ impl<T: AsRef<[u8]>> PhyPayload<T, decode_state::Encrypted> {
         Ok(match mh.ftype() {
             FrameType::JoinRequest => Payload::JoinRequest(JoinRequest::from_bytes(bytes)?),
             FrameType::JoinAccept => Payload::JoinAccept(JoinAccept::from_bytes(bytes)?),
-            _ => Payload::MacPayload(MacPayload { bytes }),
+            _ => Payload::MacPayload(MacPayload::from_bytes(bytes)?),
         })
     }
 
@@ -238,14 +298,16 @@ impl<T: AsRef<[u8]>> PhyPayload<T, decode_state::Encrypted> {
 }
 
 /// MHDR
+///
+/// NOTE: `#[bitfield]` fields are listed from the least significant bit
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MacHeader {
+    pub major: B2,
+    pub rfu: B3,
     #[bits = 3]
     pub ftype: FrameType,
-    pub rfu: B3,
-    pub major: B2,
 }
 
 /// FType, 3 bits
@@ -266,7 +328,7 @@ pub enum FrameType {
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameHeaderParseError {
     TooSmall { have: usize },
 }
@@ -293,44 +355,51 @@ impl<'a> FrameHeader<'a> {
 #[derive(Debug, Clone, Copy)]
 pub struct FrameHeaderBuf {
     /// DevAddr
-    pub dev_addr: [u8; 4],
+    pub dev_addr: DevAddr,
 
-    /// FCtrl
+    /// FCtrl, the low 4 bits (`FOptsLen`) are the number of bytes of `fopts` used
     pub fctrl: u8,
-    /// FCnt
-    pub frame_count: u8,
+    /// FCnt, only the 16 least significant bits are transmitted but all 32 are used for the MIC
+    /// and encryption
+    pub frame_count: u32,
 
-    // FIXME: 0 to 15 bytes
     /// FOpts
     pub fopts: [u8; 15],
 }
 
+impl FrameHeaderBuf {
+    pub fn fopts(&self) -> &[u8] {
+        &self.fopts[..(self.fctrl & 0xF) as usize]
+    }
+}
+
+// NOTE: `#[bitfield]` fields are listed from the least significant bit
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct DownlinkFrameControl {
-    pub adr: bool,
-    pub rfu: bool,
-    pub ack: bool,
-    pub frame_pending: bool,
     /// `FOptsLen`
     pub frame_opts_len: B4,
+    pub frame_pending: bool,
+    pub ack: bool,
+    pub rfu: bool,
+    pub adr: bool,
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct UplinkFrameControl {
-    pub adr: bool,
-    pub adr_ack_req: bool,
-    pub ack: bool,
+    /// `FOptsLen`: actual length of the frame options field (`FOpts`) included in the frame.
+    pub frame_opts_len: B4,
 
     /// Set true by the end-device to indicate to the Network Server that the end-device has
     /// enabled class B and is now ready to receive scheduled downlink pings.
     pub class_b: bool,
 
-    /// `FOptsLen`: actual length of the frame options field (`FOpts`) included in the frame.
-    pub frame_opts_len: B4,
+    pub ack: bool,
+    pub adr_ack_req: bool,
+    pub adr: bool,
 }
 
 #[derive(Clone, Copy)]
@@ -390,20 +459,169 @@ pub struct MacPayload<'a> {
 }
 
 impl<'a> MacPayload<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, FrameHeaderParseError> {
+        let have = bytes.len();
+        if have < 7 || have < 7 + (bytes[4] & 0xF) as usize {
+            return Err(FrameHeaderParseError::TooSmall { have });
+        }
+
+        Ok(Self { bytes })
+    }
+
     pub fn fhdr_bytes(&self) -> &'a [u8] {
-        // examine fhdr and determine length
-        todo!()
+        &self.bytes[..7 + self.fopts_len()]
+    }
+
+    fn fopts_len(&self) -> usize {
+        (self.fctrl() & 0xF) as usize
+    }
+
+    pub fn dev_addr(&self) -> DevAddr {
+        DevAddr {
+            addr: u32::from_le_bytes(self.bytes[0..4].try_into().unwrap()),
+        }
+    }
+
+    /// FCtrl, see [`DownlinkFrameControl`] and [`UplinkFrameControl`]
+    pub fn fctrl(&self) -> u8 {
+        self.bytes[4]
+    }
+
+    /// The 16 least significant bits of the frame counter
+    pub fn frame_count(&self) -> u16 {
+        u16::from_le_bytes(self.bytes[5..7].try_into().unwrap())
+    }
+
+    pub fn fopts(&self) -> &'a [u8] {
+        &self.fhdr_bytes()[7..]
     }
 
     pub fn fport(&self) -> Option<u8> {
-        todo!()
+        self.bytes.get(self.fhdr_bytes().len()).copied()
     }
 
     pub fn frm_paylod_bytes(&self) -> &'a [u8] {
-        todo!()
+        self.bytes.get(self.fhdr_bytes().len() + 1..).unwrap_or(&[])
     }
 }
 
+/// `Dir` used in the encryption and MIC of data frames
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Uplink = 0,
+    Downlink = 1,
+}
+
+// `A_i` and `B_0` blocks: first | 4 * 0x00 | Dir | DevAddr | FCnt | 0x00 | last
+fn data_block(
+    first: u8,
+    dir: Direction,
+    dev_addr: DevAddr,
+    frame_count: u32,
+    last: u8,
+) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[0] = first;
+    block[5] = dir as u8;
+    block[6..10].copy_from_slice(&dev_addr.addr.to_le_bytes());
+    block[10..14].copy_from_slice(&frame_count.to_le_bytes());
+    block[15] = last;
+    block
+}
+
+/// Encrypt or decrypt (they are the same operation) a `FRMPayload` in place. See [`MacPayload`].
+pub fn frm_payload_crypt(
+    key: &[u8; 16],
+    dir: Direction,
+    dev_addr: DevAddr,
+    frame_count: u32,
+    payload: &mut [u8],
+) {
+    let aes = <Aes128 as cipher::KeyInit>::new_from_slice(key).unwrap();
+    for (i, chunk) in payload.chunks_mut(16).enumerate() {
+        let mut s = data_block(0x01, dir, dev_addr, frame_count, i as u8 + 1);
+        cipher::BlockEncrypt::encrypt_block(&aes, GenericArray::from_mut_slice(&mut s));
+        for (b, s) in chunk.iter_mut().zip(s) {
+            *b ^= s;
+        }
+    }
+}
+
+/// MIC of a data frame, where `msg` is `MHDR | FHDR | FPort | FRMPayload`. See [`MacPayload`].
+pub fn data_frame_mic(
+    nwk_skey: &[u8; 16],
+    dir: Direction,
+    dev_addr: DevAddr,
+    frame_count: u32,
+    msg: &[u8],
+) -> [u8; 4] {
+    let mut mac = Cmac::<Aes128>::new_from_slice(nwk_skey).unwrap();
+    mac.update(&data_block(
+        0x49,
+        dir,
+        dev_addr,
+        frame_count,
+        msg.len() as u8,
+    ));
+    mac.update(msg);
+    mac.finalize().into_bytes().as_slice()[..4]
+        .try_into()
+        .unwrap()
+}
+
+/// Encode a data frame (`MHDR | FHDR | FPort | FRMPayload | MIC`) into `buf`, encrypting
+/// `frm_payload` with the key selected by `fport` (see [`frame_port_key`]). Returns the length of
+/// the frame.
+pub fn encode_data_frame(
+    buf: &mut [u8],
+    ftype: FrameType,
+    fhdr: &FrameHeaderBuf,
+    fport: Option<u8>,
+    frm_payload: &[u8],
+    nwk_skey: &[u8; 16],
+    app_skey: &[u8; 16],
+) -> Result<usize, crate::mac::BufferTooSmall> {
+    let dir = match ftype {
+        FrameType::UnconfirmedDataDownlink | FrameType::ConfirmedDataDownlink => {
+            Direction::Downlink
+        }
+        FrameType::JoinRequest
+        | FrameType::JoinAccept
+        | FrameType::UnconfirmedDataUplink
+        | FrameType::ConfirmedDataUplink
+        | FrameType::Rfu
+        | FrameType::Proprietary => Direction::Uplink,
+    };
+    let fopts = fhdr.fopts();
+    let payload_len = fport.map(|_| 1 + frm_payload.len()).unwrap_or(0);
+    let len = 1 + 7 + fopts.len() + payload_len + 4;
+    let buf = buf.get_mut(..len).ok_or(crate::mac::BufferTooSmall)?;
+
+    buf[0] = MacHeader::new().with_ftype(ftype).into_bytes()[0];
+    buf[1..5].copy_from_slice(&fhdr.dev_addr.addr.to_le_bytes());
+    buf[5] = fhdr.fctrl;
+    buf[6..8].copy_from_slice(&(fhdr.frame_count as u16).to_le_bytes());
+    let mut i = 8 + fopts.len();
+    buf[8..i].copy_from_slice(fopts);
+    if let Some(fport) = fport {
+        buf[i] = fport;
+        i += 1;
+        let payload = &mut buf[i..i + frm_payload.len()];
+        payload.copy_from_slice(frm_payload);
+        let key = match frame_port_key(fport) {
+            Key::NwkSKey => nwk_skey,
+            Key::AppSKey => app_skey,
+        };
+        frm_payload_crypt(key, dir, fhdr.dev_addr, fhdr.frame_count, payload);
+        i += frm_payload.len();
+    }
+
+    let mic = data_frame_mic(nwk_skey, dir, fhdr.dev_addr, fhdr.frame_count, &buf[..i]);
+    buf[i..].copy_from_slice(&mic);
+    Ok(len)
+}
+
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub enum Key {
@@ -419,6 +637,9 @@ pub fn frame_port_key(frame_port: u8) -> Key {
     }
 }
 
+/// Length of a Join-Request PHYPayload
+pub const JOIN_REQUEST_LEN: usize = 23;
+
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct JoinRequestBuf {
@@ -440,6 +661,27 @@ pub enum JoinRequestParseError {
     SizeMismatch { have: usize, need: usize },
 }
 
+impl JoinRequestBuf {
+    /// Encode as a PHYPayload (`MHDR | JoinEUI | DevEUI | DevNonce | MIC`)
+    pub fn to_bytes(&self, app_key: &[u8; 16]) -> [u8; JOIN_REQUEST_LEN] {
+        let mut bytes = [0u8; JOIN_REQUEST_LEN];
+        bytes[0] = MacHeader::new()
+            .with_ftype(FrameType::JoinRequest)
+            .into_bytes()[0];
+        bytes[1..9].copy_from_slice(&self.join_eui.to_le_bytes());
+        bytes[9..17].copy_from_slice(&self.dev_eui.to_le_bytes());
+        bytes[17..19].copy_from_slice(&self.dev_nonce.to_le_bytes());
+
+        let mic = PhyPayload::<_, decode_state::Decrypted> {
+            _type_state: PhantomData,
+            bytes: &bytes[..],
+        }
+        .mic_expected(app_key);
+        bytes[19..].copy_from_slice(&mic);
+        bytes
+    }
+}
+
 impl<'a> JoinRequest<'a> {
     pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, JoinRequestParseError> {
         let need = 8 + 8 + 2;
@@ -541,29 +783,60 @@ impl<'a> JoinAccept<'a> {
 
     pub fn cf_list(&self) -> Option<&[u8]> {
         let need = 3 + 3 + 4 + 1 + 1;
-        if self.bytes.len() != need {
+        if self.bytes.len() == need {
             None
         } else {
             Some(&self.bytes[need..])
         }
     }
 
-    /// NwkSKey
-    pub fn calculate_network_session_key(&self) -> [u8; 16] {
-        todo!()
+    /// NwkSKey, `dev_nonce` is from the Join-Request this is accepting
+    pub fn calculate_network_session_key(&self, app_key: &[u8; 16], dev_nonce: u16) -> [u8; 16] {
+        self.session_key(0x01, app_key, dev_nonce)
     }
 
-    /// AppSKey
-    pub fn calculate_app_session_key(&self) -> [u8; 16] {
-        todo!()
+    /// AppSKey, `dev_nonce` is from the Join-Request this is accepting
+    pub fn calculate_app_session_key(&self, app_key: &[u8; 16], dev_nonce: u16) -> [u8; 16] {
+        self.session_key(0x02, app_key, dev_nonce)
     }
+
+    fn session_key(&self, kind: u8, app_key: &[u8; 16], dev_nonce: u16) -> [u8; 16] {
+        session_key(
+            kind,
+            app_key,
+            self.bytes[0..3].try_into().unwrap(),
+            self.bytes[3..6].try_into().unwrap(),
+            dev_nonce,
+        )
+    }
+}
+
+/// `aes128_encrypt(AppKey, kind | JoinNonce | NetID | DevNonce | pad_16)`, where `kind` is 0x01
+/// for the NwkSKey and 0x02 for the AppSKey
+pub(crate) fn session_key(
+    kind: u8,
+    app_key: &[u8; 16],
+    join_nonce: [u8; 3],
+    net_id: [u8; 3],
+    dev_nonce: u16,
+) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[0] = kind;
+    key[1..4].copy_from_slice(&join_nonce);
+    key[4..7].copy_from_slice(&net_id);
+    key[7..9].copy_from_slice(&dev_nonce.to_le_bytes());
+
+    let aes = <Aes128 as cipher::KeyInit>::new_from_slice(app_key).unwrap();
+    cipher::BlockEncrypt::encrypt_block(&aes, GenericArray::from_mut_slice(&mut key));
+    key
 }
 
 #[bitfield]
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct DlSettings {
-    pub rfu: bool,
-    pub rx1_dr_offset: B3,
+    // NOTE: listed from the least significant bit
     pub rx2_data_rate: B4,
+    pub rx1_dr_offset: B3,
+    pub rfu: bool,
 }
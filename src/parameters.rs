@@ -11,6 +11,9 @@ pub use eu863::Eu868;
 mod us915;
 pub use us915::Us915;
 
+mod as923;
+pub use as923::As923;
+
 /// "Default Settings" which are recommended values for all regions
 ///
 /// Defined in RP002-1.0.3, 2.3, line 394.
@@ -19,8 +22,8 @@ pub mod recommended {
     pub const JOIN_ACCEPT_DELAY1: Duration = Duration::from_secs(5);
     pub const JOIN_ACCEPT_DELAY2: Duration = Duration::from_secs(6);
     pub const MAX_FCNT_GAP: u16 = 16384;
-    pub const ADR_ACK_LIMIT: u8 = 64;
-    pub const ADR_ACK_DELAY: u8 = 32;
+    pub const ADR_ACK_LIMIT: u16 = 64;
+    pub const ADR_ACK_DELAY: u16 = 32;
     pub const RETRANSMIT_TIMEOUT_FIXED: Duration = Duration::from_secs(1);
     pub const RETRANSMIT_TIMEOUT_RANDOM: Duration = Duration::from_secs(2);
     pub const PING_SLOT_PERIODICITY: u8 = 7;
@@ -28,6 +31,9 @@ pub mod recommended {
     pub const CLASS_C_RESP_TIMEOUT: Duration = Duration::from_secs(8);
 }
 
+/// Maximum time on air of a single transmission in regions/countries that limit dwell time
+pub const DWELL_TIME_LIMIT: Duration = Duration::from_millis(400);
+
 /// Parameters with recommended values consistent across all regions
 ///
 /// If these parameters differ from the recommendations, those parameters shall be communicated to
@@ -53,10 +59,14 @@ pub struct Parameters {
     pub max_fcnt_gap: u16,
 
     /// Default: 64
-    pub adr_ack_limit: u8,
+    ///
+    /// May be changed by `ADRParamSetupReq` (LoRaWAN 1.1) to any power of 2 up to 2^15
+    pub adr_ack_limit: u16,
 
     /// Default: 32
-    pub adr_ack_delay: u8,
+    ///
+    /// May be changed by `ADRParamSetupReq` (LoRaWAN 1.1) to any power of 2 up to 2^15
+    pub adr_ack_delay: u16,
 
     /// Default: 1s
     pub retransmit_timeout_fixed: Duration,
@@ -170,19 +180,451 @@ pub trait Band {
     /// OPTIONAL CFlist that can be appened to the JoinAccept message is of this type if present
     fn cflist_type(&self) -> CflistType;
 
-    fn maximum_payload_size(data_rate: u8) -> Option<u8>;
+    fn maximum_payload_size(&self, data_rate: u8) -> Option<u8>;
 
     /// Maximum payload size if the end-device will never operate under a repeater
-    fn maximum_payload_size_absent_repeaters(data_rate: u8) -> Option<u8>;
+    fn maximum_payload_size_absent_repeaters(&self, data_rate: u8) -> Option<u8>;
 
     /// Provide defaults for beacons
     fn beacon_settings(&self) -> &BeaconSettings;
 
-    fn rx1_recv_channel(transmit_channel: u8) -> u8;
+    /// Provide defaults for Class B ping slots
+    fn ping_slot_settings(&self) -> &PingSlotSettings;
 
-    fn rx1_window_data_rate(upstream_datarate: DataRate, rx1_dr_offset: u8) -> Option<DataRate>;
+    /// Frequency of the beacon starting the beacon period at `beacon_time` (GPS seconds)
+    fn beacon_frequency(&self, beacon_time: u32) -> Frequency {
+        self.beacon_settings()
+            .channels
+            .frequency(|count| channel_for_beacon(beacon_time, beacon::BEACON_PERIOD.0, count))
+    }
+
+    /// Default frequency of the ping slots of `dev_addr` in the beacon period starting at
+    /// `beacon_time` (GPS seconds)
+    fn ping_slot_frequency(&self, dev_addr: DevAddr, beacon_time: u32) -> Frequency {
+        self.ping_slot_settings().channels.frequency(|count| {
+            channel_for_ping_slot(dev_addr, beacon_time, beacon::BEACON_PERIOD.0, count)
+        })
+    }
+
+    fn rx1_recv_channel(&self, transmit_channel: u8) -> u8;
+
+    fn rx1_window_data_rate(
+        &self,
+        upstream_datarate: DataRate,
+        rx1_dr_offset: u8,
+    ) -> Option<DataRate>;
 
     fn rx2_window_details(&self) -> (Frequency, DataRate);
+
+    /// Lowest and highest (inclusive) frequencies which channels in this band may use
+    fn frequency_range(&self) -> (Frequency, Frequency);
+
+    /// Default maximum EIRP (in dBm) for this band
+    fn max_eirp_default_dbm(&self) -> i8;
+
+    /// Offset (in dB, never positive) from the maximum EIRP for a `LinkADRReq` TXPower index.
+    /// `None` if the index isn't defined for this band.
+    fn tx_power_eirp_offset(&self, tx_power: u8) -> Option<i8>;
+
+    /// Uplink channel index and data rate to use for the `attempt`th (counting from 0)
+    /// Join-Request since the last successful join. Consecutive attempts use different channels.
+    fn join_request_channel(&self, attempt: u32) -> (u8, DataRate);
+
+    /// Does this band implement `TxParamSetupReq`? Only bands with dwell time limits do.
+    fn supports_tx_param_setup(&self) -> bool {
+        false
+    }
+
+    /// Uplink dwell time limit which applies until changed by `TxParamSetupReq`, 0 if unlimited
+    fn uplink_dwell_time_default(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Regulatory sub-bands, each with its own duty cycle limit (at most [`SUB_BANDS_MAX`]).
+    /// Empty if the band has no duty cycle limits.
+    fn sub_bands(&self) -> &[SubBand] {
+        &[]
+    }
+
+    /// Does this band allow the Network Server to define channels (`NewChannelReq`), as opposed to
+    /// using a fixed channel plan?
+    fn has_dynamic_channels(&self) -> bool {
+        matches!(self.cflist_type(), CflistType::Specific)
+    }
+
+    /// Time on air of an uplink PHY payload of `len` bytes at `data_rate`
+    fn uplink_time_on_air(&self, data_rate: DataRate, len: usize) -> Option<Duration> {
+        self.data_rates()
+            .get(u8::from(data_rate) as usize)?
+            .uplink_time_on_air(len)
+    }
+}
+
+/// One of the bands implemented by this crate, allowing the band in use to be selected at runtime
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub enum AnyBand {
+    Eu868(Eu868),
+    Us915(Us915),
+    As923(As923),
+}
+
+impl AnyBand {
+    /// Returns `None` if the band is not implemented
+    pub fn from_id(band_id: BandId) -> Option<Self> {
+        match band_id {
+            BandId::Eu868 => Some(AnyBand::Eu868(Eu868)),
+            BandId::US915 => Some(AnyBand::Us915(Us915)),
+            BandId::AS923 => Some(AnyBand::As923(As923)),
+            BandId::CN779
+            | BandId::EU433
+            | BandId::AU915
+            | BandId::CN470
+            | BandId::AS923_2
+            | BandId::AS923_3
+            | BandId::KR920
+            | BandId::IN865
+            | BandId::RU864
+            | BandId::AS923_4 => None,
+        }
+    }
+}
+
+/// Channels of one of the bands in [`AnyBand`]
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub enum AnyChannels {
+    List(&'static [ChannelDetails]),
+    Plan(&'static ChannelPlan),
+    Plans(&'static Chain<ChannelPlan, ChannelPlan>),
+}
+
+impl Get for AnyChannels {
+    type Output = ChannelDetails;
+
+    fn get_move(&self, index: usize) -> Option<Self::Output> {
+        match self {
+            AnyChannels::List(l) => l.get_move(index),
+            AnyChannels::Plan(p) => p.get_move(index),
+            AnyChannels::Plans(p) => p.get_move(index),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            AnyChannels::List(l) => l.len(),
+            AnyChannels::Plan(p) => p.len(),
+            AnyChannels::Plans(p) => p.len(),
+        }
+    }
+}
+
+static EU868_CHANNELS_ANY: AnyChannels = AnyChannels::List(&eu863::EU863_CHANNELS);
+static US915_UPSTREAM_ANY: AnyChannels = AnyChannels::Plans(&us915::US915_CHANNELS.upstream);
+static US915_DOWNSTREAM_ANY: AnyChannels = AnyChannels::Plan(&us915::US915_CHANNELS.downstream);
+static AS923_CHANNELS_ANY: AnyChannels = AnyChannels::List(&as923::AS923_CHANNELS);
+
+impl Band for AnyBand {
+    fn channel_mask_apply(
+        &self,
+        channel_mask_cntl: u8,
+        channel_mask: u16,
+        current_channel_mask: u128,
+    ) -> Result<u128, ()> {
+        match self {
+            AnyBand::Eu868(b) => {
+                b.channel_mask_apply(channel_mask_cntl, channel_mask, current_channel_mask)
+            }
+            AnyBand::Us915(b) => {
+                b.channel_mask_apply(channel_mask_cntl, channel_mask, current_channel_mask)
+            }
+            AnyBand::As923(b) => {
+                b.channel_mask_apply(channel_mask_cntl, channel_mask, current_channel_mask)
+            }
+        }
+    }
+
+    type UpstreamChannels = AnyChannels;
+    type DownstreamChannels = AnyChannels;
+
+    fn upstream_channels(&self) -> &Self::UpstreamChannels {
+        match self {
+            AnyBand::Eu868(_) => &EU868_CHANNELS_ANY,
+            AnyBand::Us915(_) => &US915_UPSTREAM_ANY,
+            AnyBand::As923(_) => &AS923_CHANNELS_ANY,
+        }
+    }
+
+    fn downstream_channels(&self) -> &Self::DownstreamChannels {
+        match self {
+            AnyBand::Eu868(_) => &EU868_CHANNELS_ANY,
+            AnyBand::Us915(_) => &US915_DOWNSTREAM_ANY,
+            AnyBand::As923(_) => &AS923_CHANNELS_ANY,
+        }
+    }
+
+    fn data_rates(&self) -> &[Modulation] {
+        match self {
+            AnyBand::Eu868(b) => b.data_rates(),
+            AnyBand::Us915(b) => b.data_rates(),
+            AnyBand::As923(b) => b.data_rates(),
+        }
+    }
+
+    fn backoff_data_rate(&self, dr_current: DataRate) -> Option<DataRate> {
+        match self {
+            AnyBand::Eu868(b) => b.backoff_data_rate(dr_current),
+            AnyBand::Us915(b) => b.backoff_data_rate(dr_current),
+            AnyBand::As923(b) => b.backoff_data_rate(dr_current),
+        }
+    }
+
+    fn cflist_type(&self) -> CflistType {
+        match self {
+            AnyBand::Eu868(b) => b.cflist_type(),
+            AnyBand::Us915(b) => b.cflist_type(),
+            AnyBand::As923(b) => b.cflist_type(),
+        }
+    }
+
+    fn maximum_payload_size(&self, data_rate: u8) -> Option<u8> {
+        match self {
+            AnyBand::Eu868(b) => b.maximum_payload_size(data_rate),
+            AnyBand::Us915(b) => b.maximum_payload_size(data_rate),
+            AnyBand::As923(b) => b.maximum_payload_size(data_rate),
+        }
+    }
+
+    fn maximum_payload_size_absent_repeaters(&self, data_rate: u8) -> Option<u8> {
+        match self {
+            AnyBand::Eu868(b) => b.maximum_payload_size_absent_repeaters(data_rate),
+            AnyBand::Us915(b) => b.maximum_payload_size_absent_repeaters(data_rate),
+            AnyBand::As923(b) => b.maximum_payload_size_absent_repeaters(data_rate),
+        }
+    }
+
+    fn beacon_settings(&self) -> &BeaconSettings {
+        match self {
+            AnyBand::Eu868(b) => b.beacon_settings(),
+            AnyBand::Us915(b) => b.beacon_settings(),
+            AnyBand::As923(b) => b.beacon_settings(),
+        }
+    }
+
+    fn ping_slot_settings(&self) -> &PingSlotSettings {
+        match self {
+            AnyBand::Eu868(b) => b.ping_slot_settings(),
+            AnyBand::Us915(b) => b.ping_slot_settings(),
+            AnyBand::As923(b) => b.ping_slot_settings(),
+        }
+    }
+
+    fn beacon_frequency(&self, beacon_time: u32) -> Frequency {
+        match self {
+            AnyBand::Eu868(b) => b.beacon_frequency(beacon_time),
+            AnyBand::Us915(b) => b.beacon_frequency(beacon_time),
+            AnyBand::As923(b) => b.beacon_frequency(beacon_time),
+        }
+    }
+
+    fn ping_slot_frequency(&self, dev_addr: DevAddr, beacon_time: u32) -> Frequency {
+        match self {
+            AnyBand::Eu868(b) => b.ping_slot_frequency(dev_addr, beacon_time),
+            AnyBand::Us915(b) => b.ping_slot_frequency(dev_addr, beacon_time),
+            AnyBand::As923(b) => b.ping_slot_frequency(dev_addr, beacon_time),
+        }
+    }
+
+    fn rx1_recv_channel(&self, transmit_channel: u8) -> u8 {
+        match self {
+            AnyBand::Eu868(b) => b.rx1_recv_channel(transmit_channel),
+            AnyBand::Us915(b) => b.rx1_recv_channel(transmit_channel),
+            AnyBand::As923(b) => b.rx1_recv_channel(transmit_channel),
+        }
+    }
+
+    fn rx1_window_data_rate(
+        &self,
+        upstream_datarate: DataRate,
+        rx1_dr_offset: u8,
+    ) -> Option<DataRate> {
+        match self {
+            AnyBand::Eu868(b) => b.rx1_window_data_rate(upstream_datarate, rx1_dr_offset),
+            AnyBand::Us915(b) => b.rx1_window_data_rate(upstream_datarate, rx1_dr_offset),
+            AnyBand::As923(b) => b.rx1_window_data_rate(upstream_datarate, rx1_dr_offset),
+        }
+    }
+
+    fn rx2_window_details(&self) -> (Frequency, DataRate) {
+        match self {
+            AnyBand::Eu868(b) => b.rx2_window_details(),
+            AnyBand::Us915(b) => b.rx2_window_details(),
+            AnyBand::As923(b) => b.rx2_window_details(),
+        }
+    }
+
+    fn frequency_range(&self) -> (Frequency, Frequency) {
+        match self {
+            AnyBand::Eu868(b) => b.frequency_range(),
+            AnyBand::Us915(b) => b.frequency_range(),
+            AnyBand::As923(b) => b.frequency_range(),
+        }
+    }
+
+    fn supports_tx_param_setup(&self) -> bool {
+        match self {
+            AnyBand::Eu868(b) => b.supports_tx_param_setup(),
+            AnyBand::Us915(b) => b.supports_tx_param_setup(),
+            AnyBand::As923(b) => b.supports_tx_param_setup(),
+        }
+    }
+
+    fn uplink_dwell_time_default(&self) -> Duration {
+        match self {
+            AnyBand::Eu868(b) => b.uplink_dwell_time_default(),
+            AnyBand::Us915(b) => b.uplink_dwell_time_default(),
+            AnyBand::As923(b) => b.uplink_dwell_time_default(),
+        }
+    }
+
+    fn sub_bands(&self) -> &[SubBand] {
+        match self {
+            AnyBand::Eu868(b) => b.sub_bands(),
+            AnyBand::Us915(b) => b.sub_bands(),
+            AnyBand::As923(b) => b.sub_bands(),
+        }
+    }
+
+    fn join_request_channel(&self, attempt: u32) -> (u8, DataRate) {
+        match self {
+            AnyBand::Eu868(b) => b.join_request_channel(attempt),
+            AnyBand::Us915(b) => b.join_request_channel(attempt),
+            AnyBand::As923(b) => b.join_request_channel(attempt),
+        }
+    }
+
+    fn max_eirp_default_dbm(&self) -> i8 {
+        match self {
+            AnyBand::Eu868(b) => b.max_eirp_default_dbm(),
+            AnyBand::Us915(b) => b.max_eirp_default_dbm(),
+            AnyBand::As923(b) => b.max_eirp_default_dbm(),
+        }
+    }
+
+    fn tx_power_eirp_offset(&self, tx_power: u8) -> Option<i8> {
+        match self {
+            AnyBand::Eu868(b) => b.tx_power_eirp_offset(tx_power),
+            AnyBand::Us915(b) => b.tx_power_eirp_offset(tx_power),
+            AnyBand::As923(b) => b.tx_power_eirp_offset(tx_power),
+        }
+    }
+}
+
+/// Maximum number of sub-bands a band may define, see [`Band::sub_bands`]
+pub const SUB_BANDS_MAX: usize = 8;
+
+/// A regulatory sub-band, in which transmissions share a duty cycle limit
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct SubBand {
+    /// Lowest frequency of the sub-band
+    pub min: Frequency,
+    /// Highest frequency of the sub-band (exclusive)
+    pub max: Frequency,
+    /// The sub-band may be used for `1 / duty_cycle` of the time, eg: 100 for 1%
+    pub duty_cycle: u16,
+}
+
+impl SubBand {
+    pub fn contains(&self, frequency: Frequency) -> bool {
+        self.min.khz <= frequency.khz && frequency.khz < self.max.khz
+    }
+}
+
+/// Tracks the duty cycle of each of a band's sub-bands (see [`Band::sub_bands`]) to tell when a
+/// frequency may next be used
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub struct SubBandDutyCycle<C: Clock> {
+    available: [Option<Instant<C>>; SUB_BANDS_MAX],
+    disabled: bool,
+}
+
+impl<C: Clock> Clone for SubBandDutyCycle<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for SubBandDutyCycle<C> {}
+
+impl<C: Clock> Default for SubBandDutyCycle<C> {
+    fn default() -> Self {
+        Self {
+            available: [None; SUB_BANDS_MAX],
+            disabled: false,
+        }
+    }
+}
+
+impl<C: Clock> SubBandDutyCycle<C> {
+    /// Never restrict any sub-band, for when the limits don't apply or are enforced elsewhere
+    pub fn disabled() -> Self {
+        Self {
+            disabled: true,
+            ..Self::default()
+        }
+    }
+
+    /// Forget all previous transmissions
+    pub fn clear(&mut self) {
+        self.available = [None; SUB_BANDS_MAX];
+    }
+
+    /// Record a transmission on `frequency` which ended at `end` and lasted `time_on_air`. Its
+    /// sub-band may not be used again for `time_on_air * (duty_cycle - 1)`.
+    pub fn transmitted(
+        &mut self,
+        band: &impl Band,
+        frequency: Frequency,
+        end: Instant<C>,
+        time_on_air: Duration,
+    ) {
+        if self.disabled {
+            return;
+        }
+        let Some((index, sub_band)) = find_sub_band(band, frequency) else {
+            return;
+        };
+        let off_factor = u32::from(sub_band.duty_cycle.saturating_sub(1));
+        if let Some(available) = self.available.get_mut(index) {
+            *available = Some(
+                time_on_air
+                    .checked_mul(off_factor)
+                    .and_then(|off| instant_add(end, off))
+                    .unwrap_or(end),
+            );
+        }
+    }
+
+    /// Earliest time `frequency` may be used, `None` if it isn't restricted
+    pub fn available_at(&self, band: &impl Band, frequency: Frequency) -> Option<Instant<C>> {
+        let (index, _) = find_sub_band(band, frequency)?;
+        self.available.get(index).copied().flatten()
+    }
+}
+
+fn find_sub_band(band: &impl Band, frequency: Frequency) -> Option<(usize, SubBand)> {
+    // sub-bands past the end of `SubBandDutyCycle::available` would never be restricted
+    debug_assert!(
+        band.sub_bands().len() <= SUB_BANDS_MAX,
+        "band has more than SUB_BANDS_MAX sub-bands"
+    );
+    band.sub_bands()
+        .iter()
+        .copied()
+        .enumerate()
+        .find(|(_, sub_band)| sub_band.contains(frequency))
 }
 
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
@@ -237,7 +679,19 @@ impl<'a> IntoIterator for &'a ChannelPlan {
 impl get_move::Get for ChannelPlan {
     type Output = ChannelDetails;
     fn get_move(&self, index: usize) -> Option<Self::Output> {
-        todo!()
+        if index >= self.count {
+            return None;
+        }
+
+        Some(ChannelDetails {
+            frequency: Frequency::from_khz(
+                self.first_channel.khz + self.channel_step.khz * index as u32,
+            ),
+            bandwidth: self.bandwidth,
+            data_rate_min: self.data_rate_min,
+            data_rate_max: self.data_rate_max,
+            coding_rate: self.coding_rate,
+        })
     }
 
     fn len(&self) -> usize {
@@ -258,6 +712,30 @@ pub struct ChannelDetails {
     pub coding_rate: Option<CodingRate>,
 }
 
+/// Maximum number of channels in a dynamic channel plan
+pub const DYNAMIC_CHANNELS_MAX: usize = 16;
+
+/// A channel defined by the Network Server via `NewChannelReq` or `DlChannelReq`
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DynamicChannel {
+    /// Uplink channel details, `None` if not defined by the Network Server. Default channels of
+    /// the band are never stored here.
+    pub uplink: Option<ChannelDetails>,
+
+    /// RX1 frequency for downlinks following an uplink on this channel, set by `DlChannelReq`.
+    /// `None` means the uplink frequency is used.
+    pub downlink_frequency: Option<Frequency>,
+}
+
+/// Per end-device channel table for bands with dynamic channel plans (see
+/// [`Band::has_dynamic_channels`]), indexed by `ChIndex`
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DynamicChannels {
+    pub channels: [DynamicChannel; DYNAMIC_CHANNELS_MAX],
+}
+
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub enum Modulation {
@@ -276,6 +754,120 @@ pub enum Modulation {
     Rfu,
 }
 
+/// Preamble length of LoRaWAN LoRa uplinks, in symbols
+pub const UPLINK_PREAMBLE_LORA: u16 = 8;
+
+/// Preamble length of LoRaWAN FSK uplinks, in bytes
+pub const UPLINK_PREAMBLE_FSK: u16 = 5;
+
+/// Duration of one LR-FHSS bit (488.28125 bit/s)
+const LR_FHSS_BIT_TIME_NS: u64 = 2_048_000;
+/// Bits in each LR-FHSS header, the header being repeated 2 or 3 times depending on coding rate
+const LR_FHSS_HEADER_BITS: u64 = 114;
+
+impl Modulation {
+    /// Duration of one LoRa symbol, `None` for other modulations
+    pub fn symbol_time(&self) -> Option<Duration> {
+        match *self {
+            Modulation::Lora { sf, bw } if (5..=12).contains(&sf) && bw.khz != 0 => Some(
+                Duration::from_nanos((1_000_000_000 << sf) / (u64::from(bw.khz) * 1000)),
+            ),
+            Modulation::Lora { .. }
+            | Modulation::Fsk { .. }
+            | Modulation::LrFhss { .. }
+            | Modulation::Rfu => None,
+        }
+    }
+
+    /// Time needed to transmit a PHY payload of `payload_len` bytes
+    ///
+    /// - LoRa: `preamble` is in symbols and `coding_rate` must be one of the 4/x rates. Low data
+    ///   rate optimization is used when a symbol lasts 16 ms or more (SF11 and SF12 at 125 kHz).
+    /// - FSK: `preamble` is in bytes and is followed by a 3 byte sync word, a length byte if
+    ///   `explicit_header`, and a 2 byte CRC if `crc`. `coding_rate` is ignored.
+    /// - LR-FHSS: the coding rate and CRC are part of the modulation, so `preamble`,
+    ///   `coding_rate`, `explicit_header` and `crc` are ignored.
+    ///
+    /// `None` for [`Modulation::Rfu`] or an unsupported coding rate.
+    pub fn time_on_air(
+        &self,
+        payload_len: usize,
+        preamble: u16,
+        coding_rate: CodingRate,
+        explicit_header: bool,
+        crc: bool,
+    ) -> Option<Duration> {
+        let payload_len = payload_len as u64;
+        match *self {
+            Modulation::Lora { sf, bw } => {
+                let cr: u64 = match coding_rate {
+                    CodingRate::Cr4_5 => 1,
+                    CodingRate::Cr4_6 => 2,
+                    CodingRate::Cr4_7 => 3,
+                    CodingRate::Cr4_8 => 4,
+                    CodingRate::Cr1_3 | CodingRate::Cr2_3 => return None,
+                };
+                let sf = u64::from(sf);
+                let bw_hz = u64::from(bw.khz) * 1000;
+                if !(5..=12).contains(&sf) || bw_hz == 0 {
+                    return None;
+                }
+                let symbol_ns = (1_000_000_000 << sf) / bw_hz;
+                let low_data_rate = symbol_ns >= 16_000_000;
+
+                // "SX1276/77/78/79 datasheet", 4.1.1.7 "Time on air"
+                let bits = 8 * payload_len as i64 - 4 * sf as i64 + 28 + if crc { 16 } else { 0 }
+                    - if explicit_header { 0 } else { 20 };
+                let bits_per_block = 4 * (sf - if low_data_rate { 2 } else { 0 });
+                let blocks = (bits.max(0) as u64).div_ceil(bits_per_block);
+                let payload_symbols = 8 + blocks * (cr + 4);
+
+                // the preamble has an extra 4.25 symbols, so count in quarter symbols
+                let quarter_symbols = 4 * u64::from(preamble) + 17 + 4 * payload_symbols;
+                Some(Duration::from_nanos(quarter_symbols * symbol_ns / 4))
+            }
+            Modulation::Fsk { rate } => {
+                if rate == 0 {
+                    return None;
+                }
+                let bytes = u64::from(preamble)
+                    + 3
+                    + u64::from(explicit_header)
+                    + payload_len
+                    + if crc { 2 } else { 0 };
+                Some(Duration::from_micros(bytes * 8 * 1000 / u64::from(rate)))
+            }
+            Modulation::LrFhss { coding_rate, .. } => {
+                // payload, 16 bit CRC and 6 tail bits
+                let bits = (payload_len + 2) * 8 + 6;
+                let (headers, bits) = match coding_rate {
+                    CodingRate::Cr1_3 => (3, bits * 3),
+                    CodingRate::Cr2_3 => (2, bits * 3 / 2),
+                    CodingRate::Cr4_5
+                    | CodingRate::Cr4_6
+                    | CodingRate::Cr4_7
+                    | CodingRate::Cr4_8 => return None,
+                };
+                Some(Duration::from_nanos(
+                    (headers * LR_FHSS_HEADER_BITS + bits) * LR_FHSS_BIT_TIME_NS,
+                ))
+            }
+            Modulation::Rfu => None,
+        }
+    }
+
+    /// Time needed to transmit a LoRaWAN uplink of `payload_len` bytes (the whole PHY payload)
+    pub fn uplink_time_on_air(&self, payload_len: usize) -> Option<Duration> {
+        let preamble = match self {
+            Modulation::Fsk { .. } => UPLINK_PREAMBLE_FSK,
+            Modulation::Lora { .. } | Modulation::LrFhss { .. } | Modulation::Rfu => {
+                UPLINK_PREAMBLE_LORA
+            }
+        };
+        self.time_on_air(payload_len, preamble, CodingRate::Cr4_5, true, true)
+    }
+}
+
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub struct BeaconSettings {
@@ -285,18 +877,52 @@ pub struct BeaconSettings {
     pub channels: ChannelSpec,
 }
 
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct PingSlotSettings {
+    pub dr: DataRate,
+    pub channels: ChannelSpec,
+}
+
 #[cfg_attr(features = "defmt", derive(defmt::Debug))]
 #[derive(Debug, Clone, Copy)]
 pub enum ChannelSpec {
-    /// Pick one of the downstream channels based on `channel_for_beacon()`
-    AllDownstream,
-    /// Use this exact one frequency as the beacon default broadcast frequency
-    ///
-    // TODO: pingSlot?
+    /// Hop between `count` channels, `step` apart from `first`, based on `channel_for_beacon()`
+    /// (beacons) or `channel_for_ping_slot()` (ping slots)
+    Hopping {
+        first: Frequency,
+        step: Frequency,
+        count: u8,
+    },
+    /// Use this exact one frequency as the default broadcast frequency
     One(Frequency),
 }
 
-/// Used when `BeaconSettings::channels` is set to `AllDownstream`
-pub fn channel_for_beacon(beacon_time: u32, beacon_period: u32) -> u8 {
-    ((beacon_time / beacon_period) % 8) as u8
+impl ChannelSpec {
+    /// Frequency to use, `channel` picking the channel index when hopping between `count`
+    /// channels
+    pub fn frequency(&self, channel: impl FnOnce(u8) -> u8) -> Frequency {
+        match *self {
+            ChannelSpec::Hopping { first, step, count } => Frequency {
+                khz: first.khz + u32::from(channel(count)) * step.khz,
+            },
+            ChannelSpec::One(frequency) => frequency,
+        }
+    }
+}
+
+/// Beacon channel index, out of the `nb_channel` of `ChannelSpec::Hopping`
+pub fn channel_for_beacon(beacon_time: u32, beacon_period: u32, nb_channel: u8) -> u8 {
+    ((beacon_time / beacon_period) % u32::from(nb_channel.max(1))) as u8
+}
+
+/// Ping slot channel index, out of the `nb_channel` of `ChannelSpec::Hopping`. `beacon_time` is
+/// the time of the beacon starting the beacon period the ping slot is in.
+pub fn channel_for_ping_slot(
+    dev_addr: DevAddr,
+    beacon_time: u32,
+    beacon_period: u32,
+    nb_channel: u8,
+) -> u8 {
+    (dev_addr.addr.wrapping_add(beacon_time / beacon_period) % u32::from(nb_channel.max(1))) as u8
 }
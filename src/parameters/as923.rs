@@ -0,0 +1,228 @@
+use super::*;
+
+/// AS923 MHz Band, frequency group AS923-1 (no frequency offset)
+///
+/// As defined by RP002-1.0.3, 2.8
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct As923;
+
+impl Band for As923 {
+    fn channel_mask_apply(
+        &self,
+        channel_mask_cntl: u8,
+        channel_mask: u16,
+        current_channel_mask: u128,
+    ) -> Result<u128, ()> {
+        match channel_mask_cntl {
+            0 => {
+                let mask = current_channel_mask & !(u16::MAX as u128);
+                let mask = mask | channel_mask as u128;
+                Ok(mask)
+            }
+            // RFU
+            1..=5 => Err(()),
+            6 => Ok(u128::MAX),
+            7..=255 => Err(()),
+        }
+    }
+
+    type UpstreamChannels = [ChannelDetails; 2];
+
+    fn upstream_channels(&self) -> &Self::UpstreamChannels {
+        &AS923_CHANNELS
+    }
+
+    type DownstreamChannels = [ChannelDetails; 2];
+
+    fn downstream_channels(&self) -> &Self::DownstreamChannels {
+        &AS923_CHANNELS
+    }
+
+    fn data_rates(&self) -> &[Modulation] {
+        &AS923_DATARATES[..]
+    }
+
+    fn backoff_data_rate(&self, dr_current: DataRate) -> Option<DataRate> {
+        Some(
+            match dr_current.into() {
+                0 => 0,
+                1 => 0,
+                2 => 1,
+                3 => 2,
+                4 => 3,
+                5 => 4,
+                6 => 5,
+                7 => 6,
+                // table ends here
+                _ => return None,
+            }
+            .try_into()
+            .unwrap(),
+        )
+    }
+
+    fn cflist_type(&self) -> CflistType {
+        CflistType::Specific
+    }
+
+    // dwell time limits depend on the country, so the Network Server lifts them with
+    // `TxParamSetupReq` where they don't apply
+    fn supports_tx_param_setup(&self) -> bool {
+        true
+    }
+
+    fn uplink_dwell_time_default(&self) -> Duration {
+        DWELL_TIME_LIMIT
+    }
+
+    // sizes without a dwell time limit, the limit itself is applied based on the time on air
+    fn maximum_payload_size(&self, data_rate: u8) -> Option<u8> {
+        Some(match data_rate {
+            0 => 59,
+            1 => 59,
+            2 => 123,
+            3 => 123,
+            4 => 230,
+            5 => 230,
+            6 => 230,
+            7 => 230,
+            _ => return None,
+        })
+    }
+
+    fn maximum_payload_size_absent_repeaters(&self, data_rate: u8) -> Option<u8> {
+        Some(match data_rate {
+            0 => 59,
+            1 => 59,
+            2 => 123,
+            3 => 123,
+            4 => 250,
+            5 => 250,
+            6 => 250,
+            7 => 250,
+            _ => return None,
+        })
+    }
+
+    fn beacon_settings(&self) -> &BeaconSettings {
+        const BEACON_SETTINGS: BeaconSettings = BeaconSettings {
+            cr: CodingRate::Cr4_5,
+            dr: DataRate::_3,
+            polarity: Polarity::Normal,
+            channels: ChannelSpec::One(Frequency::from_khz(923_400)),
+        };
+        &BEACON_SETTINGS
+    }
+
+    fn ping_slot_settings(&self) -> &PingSlotSettings {
+        const PING_SLOT_SETTINGS: PingSlotSettings = PingSlotSettings {
+            dr: DataRate::_3,
+            channels: ChannelSpec::One(Frequency::from_khz(923_400)),
+        };
+        &PING_SLOT_SETTINGS
+    }
+
+    // RX1DROffset 6 and 7 raise the data rate by 1 and 2. The data rate is capped at DR5, and
+    // isn't lowered below DR0 as this assumes downlinks aren't dwell time limited.
+    fn rx1_window_data_rate(
+        &self,
+        upstream_datarate: DataRate,
+        rx1_dr_offset: u8,
+    ) -> Option<DataRate> {
+        let upstream: u8 = upstream_datarate.into();
+        if upstream > 7 {
+            return None;
+        }
+        let data_rate = match rx1_dr_offset {
+            0..=5 => upstream.saturating_sub(rx1_dr_offset),
+            6 => upstream + 1,
+            7 => upstream + 2,
+            8..=255 => return None,
+        };
+        data_rate.min(5).try_into().ok()
+    }
+
+    /// "By default, the RX1 receive window uses the same channel as the preceding uplink"
+    fn rx1_recv_channel(&self, transmit_channel: u8) -> u8 {
+        transmit_channel
+    }
+
+    // rx2 fixed frequency and datarate
+    // 923.2/DR2
+    fn rx2_window_details(&self) -> (Frequency, DataRate) {
+        (Frequency::from_khz(923_200), DataRate::_2)
+    }
+
+    fn frequency_range(&self) -> (Frequency, Frequency) {
+        (Frequency::from_khz(915_000), Frequency::from_khz(928_000))
+    }
+
+    // Join-Requests alternate between the 2 default channels at DR2, the lowest data rate usable
+    // with the default dwell time limit
+    fn join_request_channel(&self, attempt: u32) -> (u8, DataRate) {
+        ((attempt % 2) as u8, DataRate::_2)
+    }
+
+    fn max_eirp_default_dbm(&self) -> i8 {
+        16
+    }
+
+    // TX power table: MaxEIRP minus 2dB per step
+    fn tx_power_eirp_offset(&self, tx_power: u8) -> Option<i8> {
+        match tx_power {
+            0..=7 => Some(-2 * tx_power as i8),
+            // RFU
+            8..=255 => None,
+        }
+    }
+}
+
+const AS923_DATARATES: [Modulation; 8] = [
+    Modulation::Lora {
+        sf: 12,
+        bw: Frequency::from_khz(125),
+    },
+    Modulation::Lora {
+        sf: 11,
+        bw: Frequency::from_khz(125),
+    },
+    Modulation::Lora {
+        sf: 10,
+        bw: Frequency::from_khz(125),
+    },
+    Modulation::Lora {
+        sf: 9,
+        bw: Frequency::from_khz(125),
+    },
+    Modulation::Lora {
+        sf: 8,
+        bw: Frequency::from_khz(125),
+    },
+    Modulation::Lora {
+        sf: 7,
+        bw: Frequency::from_khz(125),
+    },
+    Modulation::Lora {
+        sf: 7,
+        bw: Frequency::from_khz(250),
+    },
+    Modulation::Fsk { rate: 50 },
+];
+
+pub(super) const AS923_CHANNELS: [ChannelDetails; 2] = [
+    ChannelDetails {
+        bandwidth: Frequency::from_khz(125),
+        frequency: Frequency::from_khz(923_200),
+        data_rate_min: DataRate::_0,
+        data_rate_max: DataRate::_5,
+        coding_rate: None,
+    },
+    ChannelDetails {
+        bandwidth: Frequency::from_khz(125),
+        frequency: Frequency::from_khz(923_400),
+        data_rate_min: DataRate::_0,
+        data_rate_max: DataRate::_5,
+        coding_rate: None,
+    },
+];
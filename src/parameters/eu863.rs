@@ -16,7 +16,7 @@ impl Band for Eu868 {
     ) -> Result<u128, ()> {
         match channel_mask_cntl {
             0 => {
-                let mask = current_channel_mask & u16::MAX as u128;
+                let mask = current_channel_mask & !(u16::MAX as u128);
                 let mask = mask | channel_mask as u128;
                 Ok(mask)
             }
@@ -73,7 +73,31 @@ impl Band for Eu868 {
         CflistType::Specific
     }
 
-    fn maximum_payload_size(data_rate: u8) -> Option<u8> {
+    // ETSI EN 300 220 sub-bands, as named by ERC Recommendation 70-03
+    fn sub_bands(&self) -> &[SubBand] {
+        const fn sub_band(min: u32, max: u32, duty_cycle: u16) -> SubBand {
+            SubBand {
+                min: Frequency::from_khz(min),
+                max: Frequency::from_khz(max),
+                duty_cycle,
+            }
+        }
+        const SUB_BANDS: [SubBand; 5] = [
+            // g: 1%
+            sub_band(863_000, 868_000, 100),
+            // g1: 1%
+            sub_band(868_000, 868_600, 100),
+            // g2: 0.1%
+            sub_band(868_700, 869_200, 1000),
+            // g3: 10%
+            sub_band(869_400, 869_650, 10),
+            // g4: 1%
+            sub_band(869_700, 870_000, 100),
+        ];
+        &SUB_BANDS
+    }
+
+    fn maximum_payload_size(&self, data_rate: u8) -> Option<u8> {
         Some(match data_rate {
             0 => 59,
             1 => 59,
@@ -91,7 +115,7 @@ impl Band for Eu868 {
         })
     }
 
-    fn maximum_payload_size_absent_repeaters(data_rate: u8) -> Option<u8> {
+    fn maximum_payload_size_absent_repeaters(&self, data_rate: u8) -> Option<u8> {
         Some(match data_rate {
             0 => 59,
             1 => 59,
@@ -115,13 +139,25 @@ impl Band for Eu868 {
             cr: CodingRate::Cr4_5,
             dr: DataRate::_3,
             polarity: Polarity::Normal,
-            channels: ChannelSpec::One(Frequency::from_khz(434_665)),
+            channels: ChannelSpec::One(Frequency::from_khz(869_525)),
         };
         &BEACON_SETTINGS
     }
 
+    fn ping_slot_settings(&self) -> &PingSlotSettings {
+        const PING_SLOT_SETTINGS: PingSlotSettings = PingSlotSettings {
+            dr: DataRate::_3,
+            channels: ChannelSpec::One(Frequency::from_khz(869_525)),
+        };
+        &PING_SLOT_SETTINGS
+    }
+
     /// Table 14: EU863-870 downlink RX1 data rate mapping
-    fn rx1_window_data_rate(upstream_datarate: DataRate, rx1_dr_offset: u8) -> Option<DataRate> {
+    fn rx1_window_data_rate(
+        &self,
+        upstream_datarate: DataRate,
+        rx1_dr_offset: u8,
+    ) -> Option<DataRate> {
         const DR_MAP: [[u8; 6]; 12] = [
             [0, 0, 0, 0, 0, 0],
             [1, 0, 0, 0, 0, 0],
@@ -145,7 +181,7 @@ impl Band for Eu868 {
     }
 
     /// "By default, the RX1 receive window uses the same channel as the preceding uplink"
-    fn rx1_recv_channel(transmit_channel: u8) -> u8 {
+    fn rx1_recv_channel(&self, transmit_channel: u8) -> u8 {
         transmit_channel
     }
 
@@ -154,6 +190,31 @@ impl Band for Eu868 {
     fn rx2_window_details(&self) -> (Frequency, DataRate) {
         (Frequency::from_khz(869_525), DataRate::_0)
     }
+
+    fn frequency_range(&self) -> (Frequency, Frequency) {
+        (Frequency::from_khz(863_000), Frequency::from_khz(870_000))
+    }
+
+    // Join-Requests are only sent on the 3 default channels, stepping the data rate down from DR5
+    // each time all of them have been tried
+    fn join_request_channel(&self, attempt: u32) -> (u8, DataRate) {
+        let channel = (attempt % 3) as u8;
+        let data_rate = 5 - ((attempt / 3) % 6) as u8;
+        (channel, data_rate.try_into().unwrap())
+    }
+
+    fn max_eirp_default_dbm(&self) -> i8 {
+        16
+    }
+
+    // Table 10: EU863-870 TX power table
+    fn tx_power_eirp_offset(&self, tx_power: u8) -> Option<i8> {
+        match tx_power {
+            0..=7 => Some(-2 * tx_power as i8),
+            // RFU
+            8..=255 => None,
+        }
+    }
 }
 
 const EU863_DATARATES: [Modulation; 15] = [
@@ -207,7 +268,7 @@ const EU863_DATARATES: [Modulation; 15] = [
     Modulation::Rfu,
 ];
 
-const EU863_CHANNELS: [ChannelDetails; 3] = [
+pub(super) const EU863_CHANNELS: [ChannelDetails; 3] = [
     ChannelDetails {
         bandwidth: Frequency::from_khz(125),
         frequency: Frequency::from_khz(868_100),
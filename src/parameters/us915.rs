@@ -16,21 +16,22 @@ impl Band for Us915 {
     ) -> Result<u128, ()> {
         match channel_mask_cntl {
             0..=4 => {
+                // replace one bank of 16 channels
                 let shift = channel_mask_cntl * 16;
-                let mask = current_channel_mask & (u16::MAX as u128) << shift;
+                let mask = current_channel_mask & !((u16::MAX as u128) << shift);
                 let mask = mask | (channel_mask as u128) << shift;
                 Ok(mask)
             }
             5 => {
-                // TODO: there's probably a nice way to simplify this
+                // each of the low 8 bits enables/disables a sub-band of 8 125kHz channels and the
+                // 500kHz channel that shares the sub-band
                 let mut mask = current_channel_mask;
-                for i in 0..7 {
-                    let shift = i * 16;
-                    let m = (u16::MAX as u128) << shift;
-                    if i & channel_mask != 0 {
+                for i in 0..8 {
+                    let m = ((u8::MAX as u128) << (i * 8)) | (1 << (64 + i));
+                    if channel_mask & (1 << i) != 0 {
                         mask |= m;
                     } else {
-                        mask &= m;
+                        mask &= !m;
                     }
                 }
 
@@ -85,7 +86,13 @@ impl Band for Us915 {
         CflistType::Mask
     }
 
-    fn maximum_payload_size(data_rate: u8) -> Option<u8> {
+    // FCC rules limit the dwell time of uplinks, which the data rates and maximum payload sizes
+    // already account for
+    fn uplink_dwell_time_default(&self) -> Duration {
+        DWELL_TIME_LIMIT
+    }
+
+    fn maximum_payload_size(&self, data_rate: u8) -> Option<u8> {
         Some(match data_rate {
             0 => 19,
             1 => 61,
@@ -105,7 +112,7 @@ impl Band for Us915 {
         })
     }
 
-    fn maximum_payload_size_absent_repeaters(data_rate: u8) -> Option<u8> {
+    fn maximum_payload_size_absent_repeaters(&self, data_rate: u8) -> Option<u8> {
         Some(match data_rate {
             0 => 19,
             1 => 61,
@@ -125,21 +132,42 @@ impl Band for Us915 {
         })
     }
 
+    // beacons and ping slots hop between the 8 downstream channels
     fn beacon_settings(&self) -> &BeaconSettings {
         const BEACON_SETTINGS: BeaconSettings = BeaconSettings {
-            dr: DataRate::_4,
+            dr: DataRate::_8,
             cr: CodingRate::Cr4_5,
             polarity: Polarity::Normal,
-            channels: ChannelSpec::AllDownstream,
+            channels: ChannelSpec::Hopping {
+                first: Frequency::from_khz(923_300),
+                step: Frequency::from_khz(600),
+                count: 8,
+            },
         };
         &BEACON_SETTINGS
     }
 
-    fn rx1_recv_channel(transmit_channel: u8) -> u8 {
+    fn ping_slot_settings(&self) -> &PingSlotSettings {
+        const PING_SLOT_SETTINGS: PingSlotSettings = PingSlotSettings {
+            dr: DataRate::_8,
+            channels: ChannelSpec::Hopping {
+                first: Frequency::from_khz(923_300),
+                step: Frequency::from_khz(600),
+                count: 8,
+            },
+        };
+        &PING_SLOT_SETTINGS
+    }
+
+    fn rx1_recv_channel(&self, transmit_channel: u8) -> u8 {
         transmit_channel % 8
     }
 
-    fn rx1_window_data_rate(upstream_datarate: DataRate, rx1_dr_offset: u8) -> Option<DataRate> {
+    fn rx1_window_data_rate(
+        &self,
+        upstream_datarate: DataRate,
+        rx1_dr_offset: u8,
+    ) -> Option<DataRate> {
         const DR_MAP: [[u8; 4]; 7] = [
             [10, 9, 8, 8],
             [11, 10, 9, 8],
@@ -160,6 +188,37 @@ impl Band for Us915 {
     fn rx2_window_details(&self) -> (Frequency, DataRate) {
         (Frequency::from_khz(923_300), DataRate::_0)
     }
+
+    fn frequency_range(&self) -> (Frequency, Frequency) {
+        (Frequency::from_khz(902_000), Frequency::from_khz(928_000))
+    }
+
+    // Join-Requests alternate between a 125kHz channel at DR0 and a 500kHz channel at DR4. The
+    // 125kHz channel is taken from a different sub-band each time so that a gateway listening to
+    // any single sub-band is found within 16 attempts.
+    fn join_request_channel(&self, attempt: u32) -> (u8, DataRate) {
+        let round = attempt / 2;
+        if attempt.is_multiple_of(2) {
+            let sub_band = round % 8;
+            let channel = (round / 8) % 8;
+            ((sub_band * 8 + channel) as u8, DataRate::_0)
+        } else {
+            ((64 + round % 8) as u8, DataRate::_4)
+        }
+    }
+
+    fn max_eirp_default_dbm(&self) -> i8 {
+        30
+    }
+
+    // Table 26: US902-928 TX power table
+    fn tx_power_eirp_offset(&self, tx_power: u8) -> Option<i8> {
+        match tx_power {
+            0..=14 => Some(-2 * tx_power as i8),
+            // RFU
+            15..=255 => None,
+        }
+    }
 }
 
 /*
@@ -171,44 +230,45 @@ impl Us915 {
 }
 */
 
-const US915_CHANNELS: Channels<Chain<ChannelPlan, ChannelPlan>, ChannelPlan> = Channels {
-    upstream: get_move::chain(
-        ChannelPlan {
-            first_channel: Frequency::from_khz(902_300),
-            channel_step: Frequency::from_khz(200),
-            count: 64,
+pub(super) const US915_CHANNELS: Channels<Chain<ChannelPlan, ChannelPlan>, ChannelPlan> =
+    Channels {
+        upstream: get_move::chain(
+            ChannelPlan {
+                first_channel: Frequency::from_khz(902_300),
+                channel_step: Frequency::from_khz(200),
+                count: 64,
 
-            bandwidth: Frequency::from_khz(125),
+                bandwidth: Frequency::from_khz(125),
 
-            data_rate_min: DataRate::_0,
-            data_rate_max: DataRate::_3,
-            coding_rate: Some(CodingRate::Cr4_5),
-        },
-        ChannelPlan {
-            first_channel: Frequency::from_khz(903_000),
-            channel_step: Frequency::from_khz(1_600),
-            count: 8,
+                data_rate_min: DataRate::_0,
+                data_rate_max: DataRate::_3,
+                coding_rate: Some(CodingRate::Cr4_5),
+            },
+            ChannelPlan {
+                first_channel: Frequency::from_khz(903_000),
+                channel_step: Frequency::from_khz(1_600),
+                count: 8,
 
-            // FIXME: 500kHZ @ Dr4 or 1.5233MHZ @ LR-FHSS
-            bandwidth: Frequency::from_khz(500),
-            data_rate_max: DataRate::_4,
-            data_rate_min: DataRate::_4,
-            coding_rate: None,
-        },
-    ),
+                // FIXME: 500kHZ @ Dr4 or 1.5233MHZ @ LR-FHSS
+                bandwidth: Frequency::from_khz(500),
+                data_rate_max: DataRate::_4,
+                data_rate_min: DataRate::_4,
+                coding_rate: None,
+            },
+        ),
 
-    downstream: ChannelPlan {
-        first_channel: Frequency::from_khz(923_300),
-        channel_step: Frequency::from_khz(600),
-        count: 8,
+        downstream: ChannelPlan {
+            first_channel: Frequency::from_khz(923_300),
+            channel_step: Frequency::from_khz(600),
+            count: 8,
 
-        bandwidth: Frequency::from_khz(600),
+            bandwidth: Frequency::from_khz(600),
 
-        data_rate_max: DataRate::_13,
-        data_rate_min: DataRate::_8,
-        coding_rate: None,
-    },
-};
+            data_rate_max: DataRate::_13,
+            data_rate_min: DataRate::_8,
+            coding_rate: None,
+        },
+    };
 
 const US915_DATARATES: &[Modulation] = &[
     Modulation::Lora {
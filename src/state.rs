@@ -0,0 +1,926 @@
+//! Sans-IO Class A state machine
+//!
+//! The application owns the radio and the timers. It queues an uplink with
+//! [`EndDevice::send_uplink_unconfirmed`] or [`EndDevice::send_uplink_confirmed`], then
+//! repeatedly asks [`EndDevice::next_action`] what the radio should do and reports what happened
+//! with [`EndDevice::handle_event`]:
+//!
+//! ```norust
+//! Idle -> TxPending -> Transmitting -> Rx1 -> Rx2 -> Idle
+//!            ^                          |      |      ^
+//!            |                          +------|------+ (downlink received in RX1)
+//!            +---------------------------------+ (NbTrans not reached)
+//! ```
+//!
+//! Each uplink is transmitted up to NbTrans ([`EndDevice::num_transmits`]) times, on a different
+//! channel each time. Confirmed uplinks are retransmitted until acknowledged, waiting
+//! `retransmit_timeout_fixed` plus up to `retransmit_timeout_random` after RX2 before each retry.
+//! Unconfirmed uplinks are repeated until any downlink is received.
+//!
+//! Once [`EndDevice::start_class_b`] is called, beacon windows ([`Action::ReceiveBeacon`]) and
+//! then ping slots ([`Action::ReceivePingSlot`]) are requested between Class A exchanges, see
+//! [`class_b`].
+//!
+//! In Class C ([`EndDevice::start_class_c`]) or during a multicast Class C session, the device
+//! listens continuously ([`Action::ReceiveContinuous`]) instead of sleeping, see [`class_c`].
+//!
+//! [`class_c`]: crate::class_c
+
+use embedded_time::{Clock, Instant};
+
+use crate::mac_frame::{self, FrameType};
+use crate::{class_b, instant_add, mac, parameters, BatteryLevel, EndDevice, MessageRecvMeta};
+use crate::{
+    Beacon, DataRate, DeviceClass, Frequency, NoChannelAvailable, Rng, RxWindow, TxDetails,
+};
+use parameters::Band;
+
+/// Largest PHYPayload (`MHDR | MACPayload | MIC`) of any data rate
+pub const MAX_PHY_PAYLOAD_LEN: usize = 255;
+
+/// Where the Class A state machine is in sending an uplink and receiving the downlink that may
+/// follow it
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub enum ClassAState<C: Clock> {
+    /// No uplink in progress
+    Idle,
+    /// [`EndDevice::uplink`] is waiting to be transmitted, no earlier than `not_before`
+    TxPending { not_before: Option<Instant<C>> },
+    /// Waiting for the radio to report [`RadioEvent::TxDone`]
+    Transmitting,
+    /// Listening in RX1, and then RX2 if nothing is received
+    Rx1 { rx1: RxWindow<C>, rx2: RxWindow<C> },
+    /// Listening in RX2
+    Rx2 { rx2: RxWindow<C> },
+}
+
+// NOTE: manual impls as derive would require `C: Clone`/`C: Copy`
+impl<C: Clock> Clone for ClassAState<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Clock> Copy for ClassAState<C> {}
+
+/// An encoded uplink frame and how to transmit it
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct Uplink {
+    pub confirmed: bool,
+    /// Number of times the frame has been transmitted
+    pub transmissions: u8,
+    pub details: TxDetails,
+    pub frame: [u8; MAX_PHY_PAYLOAD_LEN],
+}
+
+impl Default for Uplink {
+    fn default() -> Self {
+        Self {
+            confirmed: false,
+            transmissions: 0,
+            details: TxDetails {
+                channel: 0,
+                frequency: crate::Frequency::from_khz(0),
+                data_rate: DataRate::_0,
+                tx_power_dbm: 0,
+                len: 0,
+            },
+            frame: [0; MAX_PHY_PAYLOAD_LEN],
+        }
+    }
+}
+
+impl Uplink {
+    pub fn frame(&self) -> &[u8] {
+        &self.frame[..self.details.len]
+    }
+}
+
+/// An application uplink waiting for the uplink which carries the MAC command answers in its
+/// `FRMPayload` (FPort 0) to complete
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy)]
+pub struct QueuedUplink {
+    pub confirmed: bool,
+    pub fport: u8,
+    len: usize,
+    payload: [u8; MAX_PHY_PAYLOAD_LEN],
+}
+
+impl QueuedUplink {
+    fn new(confirmed: bool, fport: u8, payload: &[u8]) -> Self {
+        let mut queued = Self {
+            confirmed,
+            fport,
+            len: payload.len(),
+            payload: [0; MAX_PHY_PAYLOAD_LEN],
+        };
+        queued.payload[..payload.len()].copy_from_slice(payload);
+        queued
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+}
+
+/// Something the radio reports to the state machine
+#[derive(Debug)]
+pub enum RadioEvent<'a, C: Clock> {
+    /// The transmission requested by [`Action::Transmit`] ended at this time
+    TxDone(Instant<C>),
+    /// A frame was received in the window requested by [`Action::Receive`],
+    /// [`Action::ReceiveContinuous`], [`Action::ReceiveBeacon`] or [`Action::ReceivePingSlot`].
+    /// It is decrypted in place.
+    ///
+    /// For beacons, `time` must be when the reception ended.
+    Rx(MessageRecvMeta<C>, &'a mut [u8]),
+    /// The window requested by [`Action::Receive`], [`Action::ReceiveBeacon`] or
+    /// [`Action::ReceivePingSlot`] closed at this time without receiving a frame, or the window
+    /// requested by [`Action::ReceiveContinuous`] reached its `until` time
+    Timeout(Instant<C>),
+}
+
+/// What the radio should do next
+#[derive(Debug)]
+pub enum Action<'a, C: Clock> {
+    /// Transmit `frame`, then report [`RadioEvent::TxDone`]
+    Transmit { details: TxDetails, frame: &'a [u8] },
+    /// Open a receive window, then report [`RadioEvent::Rx`] or [`RadioEvent::Timeout`]
+    Receive(RxWindow<C>),
+    /// Keep a receive window open until `until`, or until the application queues an uplink if
+    /// `None`, then call [`EndDevice::next_action`] again. Report [`RadioEvent::Rx`] if a frame is
+    /// received.
+    ReceiveContinuous {
+        window: RxWindow<C>,
+        until: Option<Instant<C>>,
+    },
+    /// Open a beacon window, with the coding rate and polarity of the band's
+    /// [`parameters::Band::beacon_settings`], then report [`RadioEvent::Rx`] or
+    /// [`RadioEvent::Timeout`]
+    ReceiveBeacon(class_b::Window<C>),
+    /// Open a Class B ping slot window, then report [`RadioEvent::Rx`] or [`RadioEvent::Timeout`]
+    ReceivePingSlot(class_b::Window<C>),
+    /// Nothing to do until this time, or until the application queues an uplink if `None`
+    Sleep(Option<Instant<C>>),
+}
+
+/// Something the application is told about
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A downlink for this device was received
+    Downlink {
+        /// The Network Server acknowledged the last confirmed uplink
+        ack: bool,
+        /// The Network Server has more downlinks queued, and the device should send an uplink
+        /// soon to receive them
+        frame_pending: bool,
+        /// `None` if the downlink only carried MAC commands
+        fport: Option<u8>,
+        /// Decrypted application payload
+        payload: &'a [u8],
+    },
+    /// A downlink for the multicast group [`EndDevice::multicast_groups`]`[group]` was received
+    MulticastDownlink {
+        /// McGroupID
+        group: u8,
+        fport: u8,
+        /// Decrypted application payload
+        payload: &'a [u8],
+    },
+    /// The last confirmed uplink was transmitted NbTrans times without being acknowledged
+    AckTimeout,
+    /// The first beacon since [`EndDevice::start_class_b`] was received, the device now operates
+    /// in Class B
+    BeaconLocked(Beacon),
+    /// A beacon was received while operating in Class B
+    Beacon(Beacon),
+    /// Class B: an expected beacon wasn't received. Ping slots continue from the timing of the
+    /// last beacon received, for up to [`class_b::BEACONLESS_OPERATION`].
+    BeaconLost,
+    /// Class B: a beacon was received after [`Event::BeaconLost`]
+    BeaconReacquired(Beacon),
+    /// Class B: no beacon was received for [`class_b::BEACONLESS_OPERATION`], the device is back
+    /// in Class A
+    ClassBTimedOut,
+}
+
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UplinkError {
+    /// The device hasn't joined or been activated by personalization
+    NotActivated,
+    /// No band is selected, or the band isn't implemented
+    NoBand,
+    /// Another uplink is still in progress, or queued behind the MAC command answers
+    Busy,
+    /// FPort must be in `1..=223`
+    InvalidPort,
+    /// No enabled channel supports the current data rate
+    NoChannel,
+    /// The payload doesn't fit in an uplink at the current data rate along with the pending MAC
+    /// command answers, see [`EndDevice::max_app_payload_len`]
+    PayloadTooLong {
+        /// Longest payload which would have been accepted
+        max: usize,
+    },
+    /// Even an empty uplink at the current data rate exceeds the dwell time limit
+    DwellTimeExceeded,
+}
+
+/// Reasons a received frame is ignored
+#[cfg_attr(features = "defmt", derive(defmt::Debug))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownlinkError {
+    NotActivated,
+    /// Not a data downlink with a supported major version
+    NotDataDownlink,
+    Malformed,
+    /// Sent to a different DevAddr, and to none of the multicast groups if received in a Class B
+    /// or C window
+    NotForUs,
+    /// The frame counter has already been used
+    Replayed,
+    /// The frame counter is above the multicast group's `max_frame_count`
+    FrameCountExhausted,
+    MicMismatch,
+}
+
+impl<C, P, B> EndDevice<C, P, B>
+where
+    C: Clock,
+    P: mac::ProprietaryHandler,
+    B: BatteryLevel,
+{
+    /// Queue an unconfirmed uplink of `payload` on `fport`
+    ///
+    /// If the pending MAC command answers don't fit in `FOpts`, they are sent first in an uplink
+    /// of their own, and `payload` follows once that uplink completes.
+    pub fn send_uplink_unconfirmed(
+        &mut self,
+        fport: u8,
+        payload: &[u8],
+    ) -> Result<(), UplinkError> {
+        self.send_uplink(false, Some(fport), payload)
+    }
+
+    /// Queue a confirmed uplink of `payload` on `fport`, which the Network Server acknowledges
+    ///
+    /// See [`EndDevice::send_uplink_unconfirmed`] for when the MAC command answers don't fit in
+    /// `FOpts`.
+    pub fn send_uplink_confirmed(&mut self, fport: u8, payload: &[u8]) -> Result<(), UplinkError> {
+        self.send_uplink(true, Some(fport), payload)
+    }
+
+    /// Queue an uplink, without FPort and FRMPayload if `fport` is `None`
+    ///
+    /// MAC command answers which don't fit in `FOpts` are sent first in an uplink of their own
+    /// (FPort 0), the application payload being sent once that uplink completes. If they don't
+    /// even fit in an uplink at the current data rate, the ones that don't fit are dropped.
+    pub(crate) fn send_uplink(
+        &mut self,
+        confirmed: bool,
+        fport: Option<u8>,
+        payload: &[u8],
+    ) -> Result<(), UplinkError> {
+        if self.queued_uplink.is_some() {
+            return Err(UplinkError::Busy);
+        }
+        self.send_uplink_in(confirmed, fport, payload, false)
+    }
+
+    /// Send the uplink queued by [`EndDevice::send_uplink`] behind the MAC command answers. It is
+    /// dropped if it can no longer be sent.
+    fn send_queued_uplink(&mut self) {
+        if let Some(queued) = self.queued_uplink.take() {
+            let _ =
+                self.send_uplink_in(queued.confirmed, Some(queued.fport), queued.payload(), true);
+        }
+    }
+
+    /// [`EndDevice::send_uplink`]. A `queued` payload is sent after the MAC command answers even
+    /// if the answers fit in `FOpts`, but not beside the payload.
+    fn send_uplink_in(
+        &mut self,
+        confirmed: bool,
+        fport: Option<u8>,
+        payload: &[u8],
+        queued: bool,
+    ) -> Result<(), UplinkError> {
+        if !matches!(self.class_a, ClassAState::Idle) {
+            return Err(UplinkError::Busy);
+        }
+        let activation = self.activation.ok_or(UplinkError::NotActivated)?;
+        let band = self.band().ok_or(UplinkError::NoBand)?;
+        if fport.is_some_and(|fport| !(1..=223).contains(&fport)) {
+            return Err(UplinkError::InvalidPort);
+        }
+        let backoff = self.adr_backoff(&band);
+        let data_rate = backoff.map_or(self.data_rate, |b| b.data_rate);
+        // the channel is picked when transmitting, as it depends on the duty cycle
+        if !self.channels_support_data_rate(&band, self.uplink_channel_mask, data_rate) {
+            return Err(UplinkError::NoChannel);
+        }
+
+        let max_payload = self
+            .maximum_payload_size(&band, data_rate)
+            .ok_or(UplinkError::DwellTimeExceeded)?;
+        // FHDR without FOpts, and FPort
+        let max_frm_payload = usize::from(max_payload)
+            .checked_sub(8)
+            .ok_or(UplinkError::DwellTimeExceeded)?;
+
+        // the answers go in FOpts, unless they need an uplink of their own
+        let fopts_len = self
+            .fopts_mac_answers_len(max_payload)
+            .filter(|len| !queued || payload.len() + len <= max_frm_payload);
+        // as many whole answers as fit, in order
+        let mut answers = [0u8; MAX_PHY_PAYLOAD_LEN];
+        let mut answers_len = 0;
+        for answer in self.uplink_mac_answers() {
+            let limit = fopts_len.unwrap_or(max_frm_payload);
+            match answer.encode(&mut answers[answers_len..limit]) {
+                Ok(len) => answers_len += len,
+                Err(_) => break,
+            }
+        }
+
+        let mut queue = None;
+        let (confirmed, fport, payload, fopts_len, max) = match fopts_len {
+            Some(fopts_len) => {
+                let max = max_frm_payload - fopts_len;
+                if payload.len() > max {
+                    return Err(UplinkError::PayloadTooLong { max });
+                }
+                (confirmed, fport, payload, fopts_len, max)
+            }
+            None => {
+                if let Some(fport) = fport {
+                    if payload.len() > max_frm_payload {
+                        return Err(UplinkError::PayloadTooLong {
+                            max: max_frm_payload,
+                        });
+                    }
+                    queue = Some(QueuedUplink::new(confirmed, fport, payload));
+                }
+                (false, Some(0), &answers[..answers_len], 0, max_frm_payload)
+            }
+        };
+        let mut fopts = [0u8; 15];
+        fopts[..fopts_len].copy_from_slice(&answers[..fopts_len]);
+
+        let fctrl = mac_frame::UplinkFrameControl::new()
+            .with_adr(self.adr)
+            .with_adr_ack_req(backoff.is_some_and(|b| b.adr_ack_req))
+            .with_class_b(matches!(self.class, DeviceClass::B))
+            .with_ack(self.ack_pending)
+            .with_frame_opts_len(fopts_len as u8);
+        let fhdr = mac_frame::FrameHeaderBuf {
+            dev_addr: activation.dev_addr,
+            fctrl: fctrl.into_bytes()[0],
+            frame_count: self.frame_count_uplink,
+            fopts,
+        };
+        let ftype = if confirmed {
+            FrameType::ConfirmedDataUplink
+        } else {
+            FrameType::UnconfirmedDataUplink
+        };
+        let len = mac_frame::encode_data_frame(
+            &mut self.uplink.frame,
+            ftype,
+            &fhdr,
+            fport,
+            payload,
+            &activation.network_session_key,
+            &activation.application_session_key,
+        )
+        .map_err(|_| UplinkError::PayloadTooLong { max })?;
+
+        if let Some(backoff) = backoff {
+            self.data_rate = backoff.data_rate;
+            self.tx_power_index = backoff.tx_power;
+            self.adr_ack_cnt = self.adr_ack_cnt.saturating_add(1);
+        }
+        self.uplink.confirmed = confirmed;
+        self.uplink.transmissions = 0;
+        self.uplink.details = TxDetails {
+            channel: 0,
+            frequency: Frequency { khz: 0 },
+            data_rate,
+            tx_power_dbm: self.tx_power_dbm().unwrap_or(band.max_eirp_default_dbm()),
+            len,
+        };
+        self.frame_count_uplink = self.frame_count_uplink.wrapping_add(1);
+        self.queued_uplink = queue;
+        self.uplink_mac_answers_sent();
+        self.ack_pending = false;
+        self.class_a = ClassAState::TxPending { not_before: None };
+        Ok(())
+    }
+
+    /// What the radio should do next. Call again after the action completes, an event is handled,
+    /// or an uplink is queued.
+    ///
+    /// `rng` picks the channel of each uplink transmission.
+    pub fn next_action(&mut self, now: Instant<C>, rng: &mut impl Rng) -> Action<'_, C> {
+        match self.class_b.receiving {
+            Some(class_b::Receiving::Beacon(window)) => return Action::ReceiveBeacon(window),
+            Some(class_b::Receiving::PingSlot(window)) => return Action::ReceivePingSlot(window),
+            None => {}
+        }
+        self.class_c.receiving = false;
+        if matches!(self.class_a, ClassAState::Idle) {
+            self.send_queued_uplink();
+        }
+        if matches!(self.class_a, ClassAState::Idle) {
+            self.queue_class_c_ack(now);
+        }
+        let beacon = self.next_beacon_window(now).map(|(_, window)| window);
+
+        match self.class_a {
+            ClassAState::Idle => self.wait_for_downlinks(now, beacon, None),
+            ClassAState::Transmitting => Action::Sleep(None),
+            ClassAState::TxPending {
+                not_before: Some(not_before),
+            } if now < not_before => self.wait_for_downlinks(now, beacon, Some(not_before)),
+            // the uplink waits for the beacon rather than risk missing it
+            ClassAState::TxPending { .. }
+                if beacon.is_some_and(|window| self.uplink_overlaps_beacon(now, window.start)) =>
+            {
+                self.wait_for_downlinks(now, beacon, None)
+            }
+            ClassAState::TxPending { .. } => {
+                let Some(band) = self.band() else {
+                    self.class_a = ClassAState::Idle;
+                    return Action::Sleep(None);
+                };
+                // retransmissions use a different channel when possible
+                let exclude =
+                    (self.uplink.transmissions > 0).then_some(self.uplink.details.channel);
+                let selected = self
+                    .select_uplink_channel(&band, now, self.uplink.details.data_rate, exclude, rng)
+                    .map(|channel| (channel, self.uplink_channel(&band, channel as usize)));
+                match selected {
+                    Ok((channel, Some(details))) => {
+                        self.class_a = ClassAState::Transmitting;
+                        self.uplink.transmissions = self.uplink.transmissions.saturating_add(1);
+                        self.uplink.details.channel = channel;
+                        self.uplink.details.frequency = details.frequency;
+                        Action::Transmit {
+                            details: self.uplink.details,
+                            frame: self.uplink.frame(),
+                        }
+                    }
+                    Err(NoChannelAvailable {
+                        earliest: Some(earliest),
+                    }) => self.wait_for_downlinks(now, beacon, Some(earliest)),
+                    // the channel plan no longer allows the uplink's data rate
+                    Ok((_, None)) | Err(NoChannelAvailable { earliest: None }) => {
+                        self.class_a = ClassAState::Idle;
+                        Action::Sleep(None)
+                    }
+                }
+            }
+            ClassAState::Rx1 { rx1, .. } => self.receive_class_a(now, rx1),
+            ClassAState::Rx2 { rx2 } => self.receive_class_a(now, rx2),
+        }
+    }
+
+    /// Open the Class A window `rx`, listening continuously until it starts if there is a
+    /// continuous window
+    fn receive_class_a(&mut self, now: Instant<C>, rx: RxWindow<C>) -> Action<'_, C> {
+        match self.continuous_window(now) {
+            Some(window) if now < rx.start => {
+                self.class_c.receiving = true;
+                Action::ReceiveContinuous {
+                    window,
+                    until: Some(rx.start),
+                }
+            }
+            _ => Action::Receive(rx),
+        }
+    }
+
+    /// Listen continuously if there is a continuous window, otherwise wait for Class B windows,
+    /// until `wake`
+    fn wait_for_downlinks(
+        &mut self,
+        now: Instant<C>,
+        beacon: Option<class_b::Window<C>>,
+        wake: Option<Instant<C>>,
+    ) -> Action<'_, C> {
+        let wake = wake
+            .into_iter()
+            .chain(self.continuous_window_change(now))
+            .min();
+        if let Some(window) = self.continuous_window(now) {
+            self.class_c.receiving = true;
+            return Action::ReceiveContinuous {
+                window,
+                until: wake,
+            };
+        }
+        self.wait_for_class_b(now, beacon, wake)
+    }
+
+    /// Open the `beacon` window or the next ping slot if one is due, otherwise sleep until the
+    /// first of them or `wake`
+    fn wait_for_class_b(
+        &mut self,
+        now: Instant<C>,
+        beacon: Option<class_b::Window<C>>,
+        wake: Option<Instant<C>>,
+    ) -> Action<'_, C> {
+        if let Some(window) = beacon.filter(|window| window.start <= now) {
+            self.class_b.receiving = Some(class_b::Receiving::Beacon(window));
+            return Action::ReceiveBeacon(window);
+        }
+        let ping_slot = self.next_ping_slot_window(now);
+        if let Some(window) = ping_slot.filter(|window| window.start <= now) {
+            self.class_b.receiving = Some(class_b::Receiving::PingSlot(window));
+            return Action::ReceivePingSlot(window);
+        }
+
+        let earliest = [beacon, ping_slot]
+            .into_iter()
+            .flatten()
+            .map(|window| window.start)
+            .chain(wake)
+            .min();
+        Action::Sleep(earliest)
+    }
+
+    /// Report something the radio did. Returns an event for the application if there is one.
+    ///
+    /// `rng` randomizes the delay before retransmitting a confirmed uplink.
+    pub fn handle_event<'a>(
+        &mut self,
+        event: RadioEvent<'a, C>,
+        rng: &mut impl Rng,
+    ) -> Option<Event<'a>> {
+        let event = match (self.class_b.receiving.take(), event) {
+            (Some(class_b::Receiving::Beacon(window)), RadioEvent::Rx(meta, bytes)) => {
+                self.class_b.last_window = Some(window.start);
+                return self.beacon_received(meta, bytes);
+            }
+            (Some(class_b::Receiving::Beacon(window)), RadioEvent::Timeout(now)) => {
+                self.class_b.last_window = Some(window.start);
+                return self.beacon_missed(now);
+            }
+            (Some(class_b::Receiving::PingSlot(window)), RadioEvent::Rx(meta, bytes)) => {
+                self.class_b.last_window = Some(window.start);
+                return self.process_downlink_in(meta, bytes, false).ok();
+            }
+            (Some(class_b::Receiving::PingSlot(window)), RadioEvent::Timeout(_)) => {
+                self.class_b.last_window = Some(window.start);
+                return None;
+            }
+            (receiving, event) => {
+                self.class_b.receiving = receiving;
+                event
+            }
+        };
+        let event = match (core::mem::take(&mut self.class_c.receiving), event) {
+            (true, RadioEvent::Rx(meta, bytes)) => {
+                return self.class_c_downlink_received(meta, bytes);
+            }
+            (true, RadioEvent::Timeout(_)) => return None,
+            (receiving, event) => {
+                self.class_c.receiving = receiving;
+                event
+            }
+        };
+
+        match (self.class_a, event) {
+            (ClassAState::Transmitting, RadioEvent::TxDone(tx_end)) => {
+                self.previous_transmit_time = Some(tx_end);
+                let details = self.uplink.details;
+                if let Some(time_on_air) = self
+                    .band()
+                    .and_then(|band| band.uplink_time_on_air(details.data_rate, details.len))
+                {
+                    self.uplink_transmitted(details.frequency, tx_end, time_on_air);
+                }
+                self.class_a = match self.receive_windows(tx_end) {
+                    Some((rx1, rx2)) => ClassAState::Rx1 { rx1, rx2 },
+                    None => ClassAState::Idle,
+                };
+                None
+            }
+            (ClassAState::Rx1 { rx2, .. }, RadioEvent::Rx(meta, bytes)) => {
+                match self.process_downlink(meta, bytes) {
+                    Ok(event) => {
+                        self.receive_windows_closed(meta.time, Some(&event), rng);
+                        Some(event)
+                    }
+                    // RX2 is still opened if RX1 didn't receive a frame for this device
+                    Err(_) => {
+                        self.class_a = ClassAState::Rx2 { rx2 };
+                        None
+                    }
+                }
+            }
+            (ClassAState::Rx1 { rx2, .. }, RadioEvent::Timeout(_)) => {
+                self.class_a = ClassAState::Rx2 { rx2 };
+                None
+            }
+            (ClassAState::Rx2 { .. }, RadioEvent::Rx(meta, bytes)) => {
+                match self.process_downlink(meta, bytes) {
+                    Ok(event) => {
+                        self.receive_windows_closed(meta.time, Some(&event), rng);
+                        Some(event)
+                    }
+                    Err(_) => self.receive_windows_closed(meta.time, None, rng),
+                }
+            }
+            (ClassAState::Rx2 { .. }, RadioEvent::Timeout(now)) => {
+                self.receive_windows_closed(now, None, rng)
+            }
+            // not expected in this state
+            (
+                ClassAState::Idle
+                | ClassAState::TxPending { .. }
+                | ClassAState::Transmitting
+                | ClassAState::Rx1 { .. }
+                | ClassAState::Rx2 { .. },
+                _,
+            ) => None,
+        }
+    }
+
+    /// Decide whether to transmit the uplink again after its receive windows closed at `now`,
+    /// `downlink` being the downlink received in them, if any
+    fn receive_windows_closed(
+        &mut self,
+        now: Instant<C>,
+        downlink: Option<&Event<'_>>,
+        rng: &mut impl Rng,
+    ) -> Option<Event<'static>> {
+        let ack = matches!(downlink, Some(Event::Downlink { ack: true, .. }));
+        let done = if self.uplink.confirmed {
+            ack
+        } else {
+            downlink.is_some()
+        };
+
+        if done || self.uplink.transmissions >= self.num_transmits {
+            self.class_a = ClassAState::Idle;
+            // if a downlink was received it is reported instead, with `ack: false`
+            return (self.uplink.confirmed && downlink.is_none()).then_some(Event::AckTimeout);
+        }
+
+        let not_before = if self.uplink.confirmed {
+            // scale the random value to [0, retransmit_timeout_random)
+            let random = (self.parameters.retransmit_timeout_random.as_micros() as u64
+                * rng.next_u32() as u64)
+                >> 32;
+            instant_add(
+                now,
+                self.parameters.retransmit_timeout_fixed
+                    + core::time::Duration::from_micros(random),
+            )
+        } else {
+            None
+        };
+
+        self.class_a = ClassAState::TxPending { not_before };
+        None
+    }
+
+    /// RX1 and RX2 windows following the uplink in [`EndDevice::uplink`], which ended at `tx_end`
+    fn receive_windows(&self, tx_end: Instant<C>) -> Option<(RxWindow<C>, RxWindow<C>)> {
+        let band = self.band()?;
+        let details = self.uplink.details;
+        let (rx1_frequency, rx1_data_rate) = self.rx1_window_details(
+            &band,
+            details.channel,
+            details.data_rate,
+            self.parameters.rx1_dr_offset as u8,
+        )?;
+        let (rx2_frequency, rx2_data_rate) = self.rx2_window_details()?;
+
+        Some((
+            RxWindow {
+                start: instant_add(tx_end, self.receive_delay1())?,
+                frequency: rx1_frequency,
+                data_rate: rx1_data_rate,
+            },
+            RxWindow {
+                start: instant_add(tx_end, self.receive_delay2())?,
+                frequency: rx2_frequency,
+                data_rate: rx2_data_rate,
+            },
+        ))
+    }
+
+    /// Verify, decrypt (in place) and process a data downlink received in a Class A window,
+    /// including any MAC commands it carries
+    pub fn process_downlink<'a>(
+        &mut self,
+        meta: MessageRecvMeta<C>,
+        bytes: &'a mut [u8],
+    ) -> Result<Event<'a>, DownlinkError> {
+        self.process_downlink_in(meta, bytes, true)
+    }
+
+    /// [`EndDevice::process_downlink`] for a downlink received in a Class A window if `class_a`,
+    /// otherwise in a Class B or C one
+    pub(crate) fn process_downlink_in<'a>(
+        &mut self,
+        meta: MessageRecvMeta<C>,
+        bytes: &'a mut [u8],
+        class_a: bool,
+    ) -> Result<Event<'a>, DownlinkError> {
+        let activation = self.activation.ok_or(DownlinkError::NotActivated)?;
+
+        let phy =
+            mac_frame::PhyPayload::from_bytes(&bytes[..]).map_err(|_| DownlinkError::Malformed)?;
+        let mhdr = phy.mac_header();
+        let confirmed = match mhdr.ftype() {
+            FrameType::UnconfirmedDataDownlink => false,
+            FrameType::ConfirmedDataDownlink => true,
+            FrameType::JoinRequest
+            | FrameType::JoinAccept
+            | FrameType::UnconfirmedDataUplink
+            | FrameType::ConfirmedDataUplink
+            | FrameType::Rfu
+            | FrameType::Proprietary => return Err(DownlinkError::NotDataDownlink),
+        };
+        if mhdr.major() != 0 {
+            return Err(DownlinkError::NotDataDownlink);
+        }
+
+        let payload = mac_frame::MacPayload::from_bytes(phy.payload_bytes())
+            .map_err(|_| DownlinkError::Malformed)?;
+        let dev_addr = payload.dev_addr().addr;
+        if dev_addr != activation.dev_addr.addr {
+            // multicast downlinks are only sent in Class B and C windows
+            let group = self
+                .multicast_groups
+                .iter()
+                .position(|group| group.is_some_and(|group| group.address.addr == dev_addr))
+                .filter(|_| !class_a)
+                .ok_or(DownlinkError::NotForUs)?;
+            return self.process_multicast_downlink(group, confirmed, bytes);
+        }
+
+        let frame_count = full_frame_count(
+            self.frame_count_downlink,
+            payload.frame_count(),
+            self.parameters.max_fcnt_gap,
+        )
+        .ok_or(DownlinkError::Replayed)?;
+
+        let mic_start = bytes.len() - 4;
+        let mic = mac_frame::data_frame_mic(
+            &activation.network_session_key,
+            mac_frame::Direction::Downlink,
+            activation.dev_addr,
+            frame_count,
+            &bytes[..mic_start],
+        );
+        if mic[..] != bytes[mic_start..] {
+            return Err(DownlinkError::MicMismatch);
+        }
+
+        let fctrl = mac_frame::DownlinkFrameControl::from_bytes([payload.fctrl()]);
+        let fport = payload.fport();
+        let mut fopts = [0u8; 15];
+        let fopts_len = payload.fopts().len();
+        fopts[..fopts_len].copy_from_slice(payload.fopts());
+        // MAC commands may not be in both FOpts and FRMPayload
+        if fport == Some(0) && fopts_len != 0 {
+            return Err(DownlinkError::Malformed);
+        }
+        let frm_payload_start = 1 + payload.fhdr_bytes().len() + 1;
+
+        self.frame_count_downlink = frame_count.wrapping_add(1);
+        self.adr_ack_cnt = 0;
+        self.ack_pending = confirmed;
+        if class_a {
+            self.class_a_downlink_received();
+        }
+
+        let bytes: &'a mut [u8] = bytes;
+        let frm_payload = bytes
+            .get_mut(frm_payload_start..mic_start)
+            .unwrap_or_default();
+        if let Some(fport) = fport {
+            let key = match mac_frame::frame_port_key(fport) {
+                mac_frame::Key::NwkSKey => &activation.network_session_key,
+                mac_frame::Key::AppSKey => &activation.application_session_key,
+            };
+            mac_frame::frm_payload_crypt(
+                key,
+                mac_frame::Direction::Downlink,
+                activation.dev_addr,
+                frame_count,
+                frm_payload,
+            );
+        }
+
+        // commands after one which can't be decoded are dropped
+        let _ = self.process_mac_commands(meta, &fopts[..fopts_len]);
+        let frm_payload: &'a [u8] = frm_payload;
+        let (fport, payload) = match fport {
+            Some(0) => {
+                let _ = self.process_mac_commands(meta, frm_payload);
+                (None, &[][..])
+            }
+            fport => (fport, frm_payload),
+        };
+
+        Ok(Event::Downlink {
+            ack: fctrl.ack(),
+            frame_pending: fctrl.frame_pending(),
+            fport,
+            payload,
+        })
+    }
+
+    /// Verify and decrypt (in place) a downlink for [`EndDevice::multicast_groups`]`[id]`
+    fn process_multicast_downlink<'a>(
+        &mut self,
+        id: usize,
+        confirmed: bool,
+        bytes: &'a mut [u8],
+    ) -> Result<Event<'a>, DownlinkError> {
+        let Some(group) = self.multicast_groups.get_mut(id).and_then(Option::as_mut) else {
+            return Err(DownlinkError::NotForUs);
+        };
+
+        let phy =
+            mac_frame::PhyPayload::from_bytes(&bytes[..]).map_err(|_| DownlinkError::Malformed)?;
+        let payload = mac_frame::MacPayload::from_bytes(phy.payload_bytes())
+            .map_err(|_| DownlinkError::Malformed)?;
+        // multicast downlinks can't be acknowledged, and don't carry MAC commands
+        let fport = match payload.fport() {
+            Some(fport) if fport != 0 && !confirmed && payload.fopts().is_empty() => fport,
+            _ => return Err(DownlinkError::Malformed),
+        };
+
+        let frame_count = full_frame_count(
+            group.downlink_frame_counter,
+            payload.frame_count(),
+            self.parameters.max_fcnt_gap,
+        )
+        .ok_or(DownlinkError::Replayed)?;
+        if frame_count > group.max_frame_count {
+            return Err(DownlinkError::FrameCountExhausted);
+        }
+
+        let mic_start = bytes.len() - 4;
+        let mic = mac_frame::data_frame_mic(
+            &group.network_session_key,
+            mac_frame::Direction::Downlink,
+            group.address,
+            frame_count,
+            &bytes[..mic_start],
+        );
+        if mic[..] != bytes[mic_start..] {
+            return Err(DownlinkError::MicMismatch);
+        }
+        let frm_payload_start = 1 + payload.fhdr_bytes().len() + 1;
+
+        group.downlink_frame_counter = frame_count.wrapping_add(1);
+
+        let bytes: &'a mut [u8] = bytes;
+        let frm_payload = bytes
+            .get_mut(frm_payload_start..mic_start)
+            .unwrap_or_default();
+        mac_frame::frm_payload_crypt(
+            &group.application_session_key,
+            mac_frame::Direction::Downlink,
+            group.address,
+            frame_count,
+            frm_payload,
+        );
+
+        Ok(Event::MulticastDownlink {
+            group: id as u8,
+            fport,
+            payload: frm_payload,
+        })
+    }
+}
+
+/// The full downlink frame counter with the low 16 bits `low`, assuming it is the smallest one
+/// which isn't below `expected`. `None` if the counter was used within the last `max_gap` frames
+/// or would overflow.
+fn full_frame_count(expected: u32, low: u16, max_gap: u16) -> Option<u32> {
+    // only the low 16 bits of FCnt are sent
+    let frame_count = (expected & !0xFFFF) | u32::from(low);
+    if frame_count < expected {
+        if expected - frame_count <= u32::from(max_gap) {
+            // recently used, rather than a counter from the next 64K epoch
+            return None;
+        }
+        frame_count.checked_add(0x1_0000)
+    } else {
+        Some(frame_count)
+    }
+}
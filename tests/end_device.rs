@@ -0,0 +1,2295 @@
+use core::time::Duration;
+use embedded_time::{fraction::Fraction, Clock, Instant};
+use lorawan::{mac, EndDevice, MessageRecvMeta};
+
+#[derive(Debug, Clone, Copy)]
+struct TestClock;
+
+impl Clock for TestClock {
+    type T = u64;
+    const SCALING_FACTOR: Fraction = Fraction::new(1, 1_000_000);
+
+    fn try_now(&self) -> Result<Instant<Self>, embedded_time::clock::Error> {
+        unimplemented!()
+    }
+}
+
+/// Returns the same value every time
+struct TestRng(u32);
+
+impl lorawan::Rng for TestRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0
+    }
+}
+
+fn recv_meta(us: u64) -> MessageRecvMeta<TestClock> {
+    MessageRecvMeta {
+        power_db: 0,
+        snr_db: -7,
+        time: Instant::new(us),
+    }
+}
+
+#[test]
+fn adr_param_setup_req() {
+    let mut ed = EndDevice::<TestClock>::default();
+
+    ed.process_mac_request(
+        recv_meta(0),
+        mac::ReqFromNetworkServer::AdrParamSetup(
+            mac::AdrParamSetupReq::new()
+                .with_limit_exp(10)
+                .with_delay_exp(2),
+        ),
+    )
+    .unwrap();
+
+    assert_eq!(ed.parameters.adr_ack_limit, 1024);
+    assert_eq!(ed.parameters.adr_ack_delay, 4);
+    assert!(matches!(
+        ed.pending_mac_answers.iter().next(),
+        Some(mac::AnsFromEndDevice::AdrParamSetup)
+    ));
+}
+
+#[test]
+fn device_mode_ind_repeats_until_conf() {
+    let mut ed = EndDevice::<TestClock>::default();
+
+    assert_eq!(
+        ed.request_device_mode(lorawan::DeviceClass::B),
+        Err(lorawan::UnsupportedDeviceMode)
+    );
+    assert_eq!(ed.pending_indications().count(), 0);
+    ed.request_device_mode(lorawan::DeviceClass::C).unwrap();
+    assert_eq!(ed.pending_indications().count(), 1);
+    assert_eq!(ed.pending_indications().count(), 1);
+    assert_eq!(ed.class, lorawan::DeviceClass::A);
+
+    ed.process_mac_request(
+        recv_meta(0),
+        mac::ReqFromNetworkServer::DeviceMode(mac::DeviceModeConf::new().with_class(0x02)),
+    )
+    .unwrap();
+
+    assert_eq!(ed.pending_indications().count(), 0);
+    assert_eq!(ed.class, lorawan::DeviceClass::C);
+}
+
+#[derive(Debug, Clone, Default)]
+struct Vendor {
+    seen: u8,
+}
+
+impl mac::ProprietaryHandler for Vendor {
+    fn request_len(&self, cid: u8) -> Option<usize> {
+        match cid {
+            0x80 => Some(2),
+            _ => None,
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &mac::ProprietaryCommand,
+    ) -> Option<mac::ProprietaryCommand> {
+        self.seen += 1;
+        mac::ProprietaryCommand::new(request.cid, &[request.payload()[0] ^ request.payload()[1]])
+    }
+}
+
+#[test]
+fn proprietary_commands() {
+    let mut ed = EndDevice::<TestClock, Vendor>::default();
+
+    // proprietary 0x80, followed by ADRParamSetupReq
+    ed.process_mac_commands(recv_meta(0), &[0x80, 0x0F, 0xF1, 0x0C, 0x00])
+        .unwrap();
+    assert_eq!(ed.proprietary.seen, 1);
+
+    let mut buf = [0u8; 15];
+    let len = mac::encode_answers(ed.pending_mac_answers.iter(), &mut buf).unwrap();
+    assert_eq!(&buf[..len], &[0x80, 0xFE, 0x0C]);
+
+    // unknown proprietary commands abort decoding of the remaining commands
+    assert_eq!(
+        ed.process_mac_commands(recv_meta(0), &[0x81, 0x0C, 0x00]),
+        Err(mac::MacCommandParseError::UnknownCid { cid: 0x81 })
+    );
+}
+
+#[test]
+fn sticky_answers_repeat_until_downlink() {
+    let mut ed = EndDevice::<TestClock>::default();
+
+    ed.send_mac_answer(mac::AnsFromEndDevice::RxTimingSetup)
+        .unwrap();
+    ed.send_mac_answer(mac::AnsFromEndDevice::DutyCycle)
+        .unwrap();
+    assert_eq!(ed.uplink_mac_answers().count(), 2);
+
+    ed.uplink_mac_answers_sent();
+    assert!(matches!(
+        ed.uplink_mac_answers().collect::<Vec<_>>()[..],
+        [mac::AnsFromEndDevice::RxTimingSetup]
+    ));
+
+    // survives a reset
+    let mut storage = lorawan::EndDeviceStorage::default();
+    ed.persist(&mut storage);
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.restore(&storage);
+    assert_eq!(ed.uplink_mac_answers().count(), 1);
+
+    ed.class_a_downlink_received();
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+}
+
+fn link_adr(data_rate: u8, ch_mask: u16, ctrl: u8) -> [u8; 5] {
+    let b = mac::LinkAdrReq::new()
+        .with_data_rate(data_rate)
+        .with_tx_power(0xF)
+        .with_ch_mask(ch_mask)
+        .with_channel_mask_ctrl(ctrl)
+        .with_nb_trans(2)
+        .into_bytes();
+    [0x03, b[0], b[1], b[2], b[3]]
+}
+
+fn link_adr_answers(ed: &EndDevice<TestClock>) -> Vec<(bool, bool, bool)> {
+    ed.pending_mac_answers
+        .iter()
+        .map(|a| match a {
+            mac::AnsFromEndDevice::LinkAdr(a) => {
+                (a.channel_mask_ack(), a.data_rate_ack(), a.power_ack())
+            }
+            _ => panic!("unexpected answer {:?}", a),
+        })
+        .collect()
+}
+
+#[test]
+fn link_adr_block() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::US915));
+
+    // disable everything, then enable the first 8 125kHz channels at DR2
+    let mut fopts = Vec::new();
+    fopts.extend(link_adr(0xF, 0, 7));
+    fopts.extend(link_adr(2, 0x00FF, 0));
+    ed.process_mac_commands(recv_meta(0), &fopts).unwrap();
+
+    assert_eq!(link_adr_answers(&ed), vec![(true, true, true); 2]);
+    assert_eq!(ed.uplink_channel_mask, 0xFF);
+    assert_eq!(u8::from(ed.data_rate), 2);
+    assert_eq!(ed.num_transmits, 2);
+}
+
+#[test]
+fn link_adr_block_rejected() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::US915));
+
+    // only the first 500kHz channel, which can't be used at DR0
+    ed.process_mac_commands(recv_meta(0), &link_adr(0, 0x0001, 7))
+        .unwrap();
+    assert_eq!(link_adr_answers(&ed), vec![(false, false, true)]);
+    assert_eq!(ed.uplink_channel_mask, u128::MAX);
+    ed.uplink_mac_answers_sent();
+
+    // all channels disabled
+    ed.process_mac_commands(recv_meta(0), &link_adr(0, 0, 7))
+        .unwrap();
+    assert_eq!(link_adr_answers(&ed), vec![(false, false, true)]);
+    assert_eq!(ed.uplink_channel_mask, u128::MAX);
+    ed.uplink_mac_answers_sent();
+
+    // channels past the 72 defined by US915
+    ed.process_mac_commands(recv_meta(0), &link_adr(0, 0x0100, 4))
+        .unwrap();
+    assert_eq!(link_adr_answers(&ed), vec![(false, true, true)]);
+}
+
+#[test]
+fn link_adr_block_too_long() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::US915));
+
+    // one more request than can be answered, each of which would be accepted alone
+    let cmds: Vec<u8> = core::iter::repeat_n(link_adr(0, 0x00FF, 0), mac::PENDING_ANSWERS_MAX + 1)
+        .flatten()
+        .collect();
+    ed.process_mac_commands(recv_meta(0), &cmds).unwrap();
+    assert_eq!(
+        link_adr_answers(&ed),
+        vec![(false, false, false); mac::PENDING_ANSWERS_MAX]
+    );
+    assert_eq!(ed.uplink_channel_mask, u128::MAX);
+}
+
+#[test]
+fn link_adr_tx_power() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    assert_eq!(ed.tx_power_dbm(), Some(16));
+
+    let req = |tx_power| {
+        let b = mac::LinkAdrReq::new()
+            .with_data_rate(0xF)
+            .with_tx_power(tx_power)
+            .with_ch_mask(0x0007)
+            .into_bytes();
+        [0x03, b[0], b[1], b[2], b[3]]
+    };
+
+    ed.process_mac_commands(recv_meta(0), &req(3)).unwrap();
+    assert_eq!(link_adr_answers(&ed), vec![(true, true, true)]);
+    assert_eq!(ed.tx_power_dbm(), Some(10));
+    ed.uplink_mac_answers_sent();
+
+    // RFU in EU868
+    ed.process_mac_commands(recv_meta(0), &req(9)).unwrap();
+    assert_eq!(link_adr_answers(&ed), vec![(true, true, false)]);
+    assert_eq!(ed.tx_power_dbm(), Some(10));
+    ed.uplink_mac_answers_sent();
+
+    // MaxEIRP from TxParamSetupReq
+    ed.set_band_id(Some(lorawan::BandId::US915));
+    assert_eq!(ed.tx_power_dbm(), Some(30));
+    assert!(ed
+        .process_mac_request(
+            recv_meta(0),
+            mac::ReqFromNetworkServer::TxParamSetup(mac::TxParamSetupReq::new().with_max_eirp(5)),
+        )
+        .is_err());
+    assert_eq!(
+        mac::TxParamSetupReq::new().with_max_eirp(5).max_eirp_dbm(),
+        16
+    );
+}
+
+fn new_channel(index: u8, freq_100hz: u32, min: u8, max: u8) -> mac::ReqFromNetworkServer {
+    mac::ReqFromNetworkServer::NewChannel(
+        mac::NewChannelReq::new()
+            .with_channel_index(index)
+            .with_frequency(freq_100hz)
+            .with_min_data_rate(min)
+            .with_max_data_rate(max),
+    )
+}
+
+fn new_channel_answer(ed: &mut EndDevice<TestClock>) -> (bool, bool) {
+    let r = match ed.pending_mac_answers.iter().last() {
+        Some(mac::AnsFromEndDevice::NewChannel(a)) => {
+            (a.channel_frequency_ok(), a.data_rate_range_ok())
+        }
+        a => panic!("unexpected answer {:?}", a),
+    };
+    ed.uplink_mac_answers_sent();
+    r
+}
+
+#[test]
+fn new_channel_req() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    let band = ed.band().unwrap();
+
+    ed.process_mac_request(recv_meta(0), new_channel(3, 8_671_000, 0, 5))
+        .unwrap();
+    assert_eq!(new_channel_answer(&mut ed), (true, true));
+    assert_eq!(ed.uplink_channel(&band, 3).unwrap().frequency.khz, 867_100);
+
+    // default channels can't be changed
+    ed.process_mac_request(recv_meta(0), new_channel(0, 8_671_000, 0, 5))
+        .unwrap();
+    assert_eq!(new_channel_answer(&mut ed), (false, false));
+
+    // outside the band, and an inverted data rate range
+    ed.process_mac_request(recv_meta(0), new_channel(4, 9_151_000, 5, 0))
+        .unwrap();
+    assert_eq!(new_channel_answer(&mut ed), (false, false));
+    assert!(ed.uplink_channel(&band, 4).is_none());
+
+    // frequency 0 removes the channel
+    ed.process_mac_request(recv_meta(0), new_channel(3, 0, 0, 0))
+        .unwrap();
+    assert_eq!(new_channel_answer(&mut ed), (true, true));
+    assert!(ed.uplink_channel(&band, 3).is_none());
+
+    // DlChannelReq only applies to defined channels
+    ed.process_mac_request(
+        recv_meta(0),
+        mac::ReqFromNetworkServer::DlChannel(
+            mac::DlChannelReq::new()
+                .with_channel_index(3)
+                .with_frequency(8_681_000),
+        ),
+    )
+    .unwrap();
+    assert!(matches!(
+        ed.sticky_mac_answers.iter().next(),
+        Some(mac::AnsFromEndDevice::DlChannel(a)) if a.channel_frequency_ok() && !a.uplink_frequency_exists()
+    ));
+}
+
+#[test]
+fn dl_channel_req_answers_each_request() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+
+    // two DlChannelReq in one FOpts, the second for an undefined channel
+    let mut fopts = [0u8; 10];
+    for (i, channel_index) in [1u8, 5].into_iter().enumerate() {
+        fopts[i * 5] = 0x0A;
+        fopts[i * 5 + 1..i * 5 + 5].copy_from_slice(
+            &mac::DlChannelReq::new()
+                .with_channel_index(channel_index)
+                .with_frequency(8_681_000)
+                .into_bytes(),
+        );
+    }
+    ed.class_a_downlink_received();
+    ed.process_mac_commands(recv_meta(0), &fopts).unwrap();
+
+    let answers: Vec<_> = ed
+        .uplink_mac_answers()
+        .map(|a| match a {
+            mac::AnsFromEndDevice::DlChannel(a) => a.uplink_frequency_exists(),
+            a => panic!("unexpected answer {:?}", a),
+        })
+        .collect();
+    assert_eq!(answers, [true, false]);
+
+    // both are repeated until the next Class A downlink
+    ed.uplink_mac_answers_sent();
+    assert_eq!(ed.uplink_mac_answers().count(), 2);
+    ed.class_a_downlink_received();
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+}
+
+#[test]
+fn new_channel_req_fixed_plan() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::US915));
+
+    assert!(ed
+        .process_mac_request(recv_meta(0), new_channel(3, 9_030_000, 0, 3))
+        .is_err());
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+}
+
+#[test]
+fn rx_timing_setup_req() {
+    let mut ed = EndDevice::<TestClock>::default();
+
+    for (delay, expected) in [(0, 1), (1, 1), (5, 5), (15, 15)] {
+        ed.process_mac_request(
+            recv_meta(0),
+            mac::ReqFromNetworkServer::RxTimingSetup(
+                mac::RxTimingSetupReq::new().with_delay_seconds(delay),
+            ),
+        )
+        .unwrap();
+        assert_eq!(ed.receive_delay1().as_secs(), expected);
+        assert_eq!(ed.receive_delay2().as_secs(), expected + 1);
+    }
+}
+
+#[test]
+fn rx_param_setup_req() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+
+    let req = |freq| {
+        mac::ReqFromNetworkServer::RxParamSetup(
+            mac::RxParamSetupReq::new()
+                .with_rx1_data_rate_offset(2)
+                .with_rx2_data_rate(3)
+                .with_frequency(freq),
+        )
+    };
+    let answer = |ed: &EndDevice<TestClock>| match ed.sticky_mac_answers.iter().next() {
+        Some(mac::AnsFromEndDevice::RxParamSetup(a)) => (
+            a.rx1_data_rate_offset_ack(),
+            a.rx2_data_rate_ack(),
+            a.channel_ack(),
+        ),
+        a => panic!("unexpected answer {:?}", a),
+    };
+
+    // out of band frequency, nothing is applied
+    ed.process_mac_request(recv_meta(0), req(9_233_000))
+        .unwrap();
+    assert_eq!(answer(&ed), (true, true, false));
+    assert_eq!(ed.rx2_window_details().unwrap().0.khz, 869_525);
+
+    // the next request arrives in another Class A downlink
+    ed.class_a_downlink_received();
+    ed.process_mac_request(recv_meta(0), req(8_695_250))
+        .unwrap();
+    assert_eq!(answer(&ed), (true, true, true));
+    assert_eq!(ed.sticky_mac_answers.len(), 1);
+    let (freq, dr) = ed.rx2_window_details().unwrap();
+    assert_eq!((freq.khz, u8::from(dr)), (869_525, 3));
+    assert_eq!(ed.parameters.rx1_dr_offset, 2);
+
+    // not supported in EU868
+    assert!(ed
+        .process_mac_request(
+            recv_meta(0),
+            mac::ReqFromNetworkServer::TxParamSetup(mac::TxParamSetupReq::new()),
+        )
+        .is_err());
+}
+
+#[derive(Debug, Clone, Default)]
+struct HalfBattery;
+
+impl lorawan::BatteryLevel for HalfBattery {
+    fn battery_level(&self) -> lorawan::Battery {
+        lorawan::Battery::Level(127)
+    }
+}
+
+#[test]
+fn dev_status_req() {
+    let mut ed = EndDevice::<TestClock, (), HalfBattery>::default();
+
+    ed.process_mac_request(recv_meta(0), mac::ReqFromNetworkServer::DevStatus)
+        .unwrap();
+    match ed.pending_mac_answers.iter().next() {
+        Some(mac::AnsFromEndDevice::DevStatus(a)) => {
+            assert_eq!(a.battery(), 127);
+            assert_eq!(a.margin(), -7);
+            assert_eq!(a.into_bytes(), [0x7F, 0x39]);
+        }
+        a => panic!("unexpected answer {:?}", a),
+    }
+
+    let a = mac::DevStatusAns::new().with_margin(-40);
+    assert_eq!(a.margin(), -32);
+    let a = mac::DevStatusAns::new().with_margin(31);
+    assert_eq!(a.margin(), 31);
+
+    // RFU | Margin
+    assert_eq!(
+        mac::DevStatusAns::new().with_margin(5).into_bytes(),
+        [0x00, 0x05]
+    );
+    assert_eq!(
+        mac::DevStatusAns::new().with_margin(-1).into_bytes(),
+        [0x00, 0x3F]
+    );
+
+    assert_eq!(u8::from(lorawan::Battery::External), 0);
+    assert_eq!(u8::from(lorawan::Battery::Unknown), 255);
+}
+
+#[test]
+fn duty_cycle_req() {
+    let mut ed = EndDevice::<TestClock>::default();
+    let frequency = lorawan::Frequency::from_khz(868_100);
+
+    // no limit by default
+    ed.uplink_transmitted(
+        frequency,
+        Instant::new(1_000_000),
+        Duration::from_millis(100),
+    );
+    assert!(ed
+        .check_uplink_allowed(Instant::new(1_000_000), frequency)
+        .is_ok());
+
+    // 1/8 aggregated duty cycle, RFU | MaxDCycle
+    let req = mac::DutyCycleReq::from_bytes([0x03]);
+    assert_eq!(req.max_duty_cycle(), 3);
+    ed.process_mac_request(recv_meta(0), mac::ReqFromNetworkServer::DutyCycle(req))
+        .unwrap();
+    ed.uplink_transmitted(
+        frequency,
+        Instant::new(1_000_000),
+        Duration::from_millis(100),
+    );
+    let delayed = ed
+        .check_uplink_allowed(Instant::new(1_000_000), frequency)
+        .unwrap_err();
+    assert_eq!(delayed.earliest, Instant::new(1_700_000));
+    assert!(ed
+        .check_uplink_allowed(Instant::new(1_699_999), frequency)
+        .is_err());
+    assert!(ed
+        .check_uplink_allowed(Instant::new(1_700_000), frequency)
+        .is_ok());
+}
+
+const APP_KEY: &str = "B6B53F4A168A7A88BDF7EA135CE9CFCA";
+
+fn otaa_credentials() -> lorawan::OtaaCredentials {
+    lorawan::OtaaCredentials {
+        join_eui: 0x0102030405060708,
+        dev_eui: 0x1122334455667788,
+        app_key: hex::decode(APP_KEY).unwrap().try_into().unwrap(),
+    }
+}
+
+#[test]
+fn otaa_join() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    let credentials = otaa_credentials();
+    let mut storage = lorawan::EndDeviceStorage::default();
+
+    let mut buf = [0u8; 64];
+    let tx = ed
+        .join_request(Instant::new(0), &mut storage, &credentials, &mut buf)
+        .unwrap();
+    assert_eq!(
+        &buf[..tx.len],
+        &hex::decode("00080706050403020188776655443322110000a63a8733").unwrap()[..]
+    );
+    assert_eq!(storage.dev_nonce, 1);
+    assert_eq!(
+        (tx.channel, tx.frequency.khz, u8::from(tx.data_rate)),
+        (0, 868_100, 5)
+    );
+
+    let [rx1, rx2] = ed.join_request_sent(Instant::new(1_000_000)).unwrap();
+    assert_eq!(rx1.start, Instant::new(6_000_000));
+    assert_eq!((rx1.frequency.khz, u8::from(rx1.data_rate)), (868_100, 5));
+    assert_eq!(rx2.start, Instant::new(7_000_000));
+    assert_eq!((rx2.frequency.khz, u8::from(rx2.data_rate)), (869_525, 0));
+
+    let join_accept =
+        hex::decode("20c1981fa5ccc42ca311ac476923b6407d16b8e9afd9120e0d0bcf364988791534").unwrap();
+
+    assert!(matches!(
+        ed.join_accept_received(&mut storage, &credentials, &mut join_accept.clone()[..8]),
+        Err(lorawan::JoinError::Decode(
+            lorawan::mac_frame::PhyPayloadDecodeError::SmallerThanMinSize { have: 8, need: 12 }
+        ))
+    ));
+
+    let mut corrupted = join_accept.clone();
+    corrupted[5] ^= 1;
+    assert!(matches!(
+        ed.join_accept_received(&mut storage, &credentials, &mut corrupted),
+        Err(lorawan::JoinError::Decrypt(
+            lorawan::mac_frame::JoinAcceptDecryptError::MicMismatch
+        ))
+    ));
+    assert!(ed.activation.is_none());
+
+    // state of a previous session which the Network Server changed
+    ed.frame_count_uplink = 10;
+    ed.data_rate = lorawan::DataRate::_4;
+    ed.tx_power_index = 3;
+    ed.num_transmits = 2;
+    ed.max_duty_cycle = 5;
+    ed.max_eirp_dbm = Some(10);
+    ed.uplink_dwell_time = Some(lorawan::DWELL_TIME_LIMIT);
+    ed.parameters.adr_ack_limit = 1;
+    ed.parameters.adr_ack_delay = 1;
+    ed.send_mac_answer(mac::AnsFromEndDevice::RxTimingSetup)
+        .unwrap();
+
+    ed.join_accept_received(&mut storage, &credentials, &mut join_accept.clone())
+        .unwrap();
+
+    let activation = ed.activation.unwrap();
+    assert_eq!(activation.dev_addr.addr, 0x26011234);
+    assert_eq!(
+        activation.network_session_key[..],
+        hex::decode("cc962a6cf7548eabd43f56540f4a4173").unwrap()[..]
+    );
+    assert_eq!(
+        activation.application_session_key[..],
+        hex::decode("2752127eceb8cc634cf80428a9367771").unwrap()[..]
+    );
+    assert_eq!(ed.frame_count_uplink, 0);
+    assert_eq!(u8::from(ed.data_rate), 0);
+    assert_eq!(ed.tx_power_index, 0);
+    assert_eq!(ed.num_transmits, 1);
+    assert_eq!(ed.max_duty_cycle, 0);
+    assert_eq!(ed.effective_max_eirp_dbm(), Some(16));
+    assert_eq!(ed.uplink_dwell_time, None);
+    assert_eq!(ed.parameters.adr_ack_limit, 64);
+    assert_eq!(ed.parameters.adr_ack_delay, 32);
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+
+    // DLSettings, RxDelay and CFList
+    assert_eq!(ed.parameters.rx1_dr_offset, 2);
+    assert_eq!(u8::from(ed.rx2_window_details().unwrap().1), 3);
+    assert_eq!(ed.receive_delay1, Duration::from_secs(5));
+    let band = ed.band().unwrap();
+    for (i, khz) in [867_100, 867_300, 867_500, 867_700, 867_900]
+        .into_iter()
+        .enumerate()
+    {
+        assert_eq!(ed.uplink_channel(&band, 3 + i).unwrap().frequency.khz, khz);
+        assert_ne!(ed.uplink_channel_mask & (1 << (3 + i)), 0);
+    }
+
+    // the Join-Request is no longer pending
+    assert!(matches!(
+        ed.join_accept_received(&mut storage, &credentials, &mut join_accept.clone()),
+        Err(lorawan::JoinError::NotJoining)
+    ));
+}
+
+#[test]
+fn otaa_join_accept_replayed() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    let credentials = otaa_credentials();
+    let mut storage = lorawan::EndDeviceStorage::default();
+    let join_accept =
+        hex::decode("20c1981fa5ccc42ca311ac476923b6407d16b8e9afd9120e0d0bcf364988791534").unwrap();
+
+    let mut buf = [0u8; 23];
+    ed.join_request(Instant::new(0), &mut storage, &credentials, &mut buf)
+        .unwrap();
+    ed.join_accept_received(&mut storage, &credentials, &mut join_accept.clone())
+        .unwrap();
+    let join_nonce = storage.join_nonce.unwrap();
+
+    // the JoinNonce survives a reset, and a replay of the same Join-Accept is rejected
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    ed.join_request(Instant::new(0), &mut storage, &credentials, &mut buf)
+        .unwrap();
+    assert!(matches!(
+        ed.join_accept_received(&mut storage, &credentials, &mut join_accept.clone()),
+        Err(lorawan::JoinError::JoinNonceReplayed)
+    ));
+    assert!(ed.activation.is_none());
+    assert!(ed.pending_join.is_some());
+    assert_eq!(storage.join_nonce, Some(join_nonce));
+
+    // as are older ones
+    storage.join_nonce = Some(join_nonce + 1);
+    assert!(matches!(
+        ed.join_accept_received(&mut storage, &credentials, &mut join_accept.clone()),
+        Err(lorawan::JoinError::JoinNonceReplayed)
+    ));
+
+    storage.join_nonce = Some(join_nonce - 1);
+    ed.join_accept_received(&mut storage, &credentials, &mut join_accept.clone())
+        .unwrap();
+    assert_eq!(storage.join_nonce, Some(join_nonce));
+}
+
+#[test]
+fn otaa_join_retries() {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::US915));
+    let credentials = otaa_credentials();
+    let mut storage = lorawan::EndDeviceStorage::default();
+
+    let mut buf = [0u8; 23];
+    let attempts: Vec<_> = (0..4)
+        .map(|_| {
+            let tx = ed
+                .join_request(Instant::new(0), &mut storage, &credentials, &mut buf)
+                .unwrap();
+            (tx.channel, u8::from(tx.data_rate))
+        })
+        .collect();
+    assert_eq!(attempts, vec![(0, 0), (64, 4), (8, 0), (65, 4)]);
+    assert_eq!(storage.dev_nonce, 4);
+    assert_eq!(ed.pending_join.unwrap().dev_nonce, 3);
+
+    assert!(matches!(
+        ed.join_request(Instant::new(0), &mut storage, &credentials, &mut [0u8; 22]),
+        Err(lorawan::JoinError::BufferTooSmall)
+    ));
+}
+
+#[test]
+fn abp_activation() {
+    let mut storage = lorawan::EndDeviceStorage {
+        frame_count_uplink: 42,
+        frame_count_downlink: 7,
+        ..Default::default()
+    };
+
+    // state of a previous session
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    ed.process_mac_request(recv_meta(0), new_channel(3, 8_671_000, 0, 5))
+        .unwrap();
+    ed.send_mac_answer(mac::AnsFromEndDevice::RxTimingSetup)
+        .unwrap();
+    ed.ack_pending = true;
+    ed.adr_ack_cnt = 10;
+    ed.uplink_channel_mask = 0b11;
+    ed.data_rate = lorawan::DataRate::_3;
+
+    ed.activate_abp(
+        lorawan::DevAddr { addr: 0x26011234 },
+        [1; 16],
+        [2; 16],
+        storage.frame_count_uplink,
+        storage.frame_count_downlink,
+    );
+    assert!(!ed.is_otaa());
+    assert_eq!((ed.frame_count_uplink, ed.frame_count_downlink), (42, 7));
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+    assert!(!ed.ack_pending);
+    assert_eq!(ed.adr_ack_cnt, 0);
+    assert_eq!(ed.uplink_channel_mask, u128::MAX);
+    assert_eq!(u8::from(ed.data_rate), 0);
+    assert!(ed.uplink_channel(&ed.band().unwrap(), 3).is_none());
+
+    // rejoins only apply to OTAA sessions
+    assert!(ed
+        .process_mac_request(
+            recv_meta(0),
+            mac::ReqFromNetworkServer::ForceRejoin(mac::ForceRejoinReq::new()),
+        )
+        .is_err());
+    assert!(ed
+        .process_mac_request(
+            recv_meta(0),
+            mac::ReqFromNetworkServer::RejoinParamSetup(mac::RejoinParamSetupReq::new()),
+        )
+        .is_err());
+    assert!(ed.force_rejoin.is_none());
+    assert_eq!(ed.pending_mac_answers.len(), 0);
+
+    ed.frame_count_uplink += 1;
+    ed.persist(&mut storage);
+    assert_eq!(storage.frame_count_uplink, 43);
+    assert_eq!(
+        storage.activation.unwrap().kind,
+        lorawan::ActivationKind::Abp
+    );
+}
+
+const NWK_SKEY: [u8; 16] = [1; 16];
+const APP_SKEY: [u8; 16] = [2; 16];
+const DEV_ADDR: u32 = 0x26011234;
+
+fn abp_device(band_id: lorawan::BandId) -> EndDevice<TestClock> {
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(band_id));
+    let storage = lorawan::EndDeviceStorage::default();
+    ed.activate_abp(
+        lorawan::DevAddr { addr: DEV_ADDR },
+        NWK_SKEY,
+        APP_SKEY,
+        storage.frame_count_uplink,
+        storage.frame_count_downlink,
+    );
+    // the sub-band duty cycle is only tested by `sub_band_duty_cycle`
+    ed.sub_band_duty_cycle = lorawan::SubBandDutyCycle::disabled();
+    ed
+}
+
+fn downlink(
+    confirmed: bool,
+    frame_count: u32,
+    fopts: &[u8],
+    fport: Option<u8>,
+    payload: &[u8],
+) -> Vec<u8> {
+    use lorawan::mac_frame::{encode_data_frame, FrameHeaderBuf, FrameType};
+
+    let mut fhdr = FrameHeaderBuf {
+        dev_addr: lorawan::DevAddr { addr: DEV_ADDR },
+        fctrl: fopts.len() as u8,
+        frame_count,
+        fopts: [0; 15],
+    };
+    fhdr.fopts[..fopts.len()].copy_from_slice(fopts);
+    let ftype = if confirmed {
+        FrameType::ConfirmedDataDownlink
+    } else {
+        FrameType::UnconfirmedDataDownlink
+    };
+    let mut buf = [0u8; 255];
+    let len =
+        encode_data_frame(&mut buf, ftype, &fhdr, fport, payload, &NWK_SKEY, &APP_SKEY).unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn class_a_uplink() {
+    use lorawan::state::{Action, Event, RadioEvent};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    assert!(matches!(
+        ed.next_action(Instant::new(0), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+    assert_eq!(
+        ed.send_uplink_unconfirmed(1, b"again"),
+        Err(lorawan::state::UplinkError::Busy)
+    );
+    // the last of the three default channels
+    match ed.next_action(Instant::new(0), &mut TestRng(u32::MAX)) {
+        Action::Transmit { details, frame } => {
+            // MHDR | DevAddr | FCtrl | FCnt | FPort | FRMPayload | MIC
+            assert_eq!(frame.len(), 1 + 7 + 1 + 5 + 4);
+            assert_eq!(frame[0], 0x40);
+            assert_eq!(&frame[1..5], &DEV_ADDR.to_le_bytes());
+            assert_eq!((details.channel, details.frequency.khz), (2, 868_500));
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+    assert_eq!(ed.frame_count_uplink, 1);
+
+    // waiting for the transmission to complete
+    assert!(matches!(
+        ed.next_action(Instant::new(0), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+    assert!(ed
+        .handle_event(RadioEvent::TxDone(Instant::new(100_000)), &mut TestRng(0))
+        .is_none());
+    assert_eq!(ed.previous_transmit_time, Some(Instant::new(100_000)));
+
+    match ed.next_action(Instant::new(100_000), &mut TestRng(0)) {
+        Action::Receive(rx1) => {
+            assert_eq!(rx1.start, Instant::new(1_100_000));
+            assert_eq!(rx1.frequency.khz, 868_500);
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+    ed.handle_event(
+        RadioEvent::Timeout(Instant::new(1_200_000)),
+        &mut TestRng(0),
+    );
+    match ed.next_action(Instant::new(1_200_000), &mut TestRng(0)) {
+        Action::Receive(rx2) => {
+            assert_eq!(rx2.start, Instant::new(2_100_000));
+            assert_eq!((rx2.frequency.khz, u8::from(rx2.data_rate)), (869_525, 0));
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+
+    // DevStatusReq in FOpts along with application data
+    let mut frame = downlink(true, 0, &[0x06], Some(2), b"hi");
+    match ed.handle_event(
+        RadioEvent::Rx(recv_meta(2_100_000), &mut frame),
+        &mut TestRng(0),
+    ) {
+        Some(Event::Downlink {
+            ack: false,
+            frame_pending: false,
+            fport: Some(2),
+            payload,
+        }) => assert_eq!(payload, b"hi"),
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert!(matches!(
+        ed.next_action(Instant::new(2_200_000), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+    assert_eq!(ed.frame_count_downlink, 1);
+    assert!(ed.ack_pending);
+    assert_eq!(ed.pending_mac_answers.len(), 1);
+
+    // the next uplink acknowledges the confirmed downlink and carries the DevStatusAns
+    ed.send_uplink_unconfirmed(1, b"").unwrap();
+    match ed.next_action(Instant::new(3_000_000), &mut TestRng(0)) {
+        Action::Transmit { details, frame } => {
+            let fctrl = lorawan::mac_frame::UplinkFrameControl::from_bytes([frame[5]]);
+            assert!(fctrl.ack());
+            assert_eq!(fctrl.frame_opts_len(), 3);
+            assert_eq!(frame[8], 0x06);
+            assert_eq!(details.channel, 0);
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+    assert!(!ed.ack_pending);
+}
+
+/// Transmit the queued uplink and let its receive windows close without a downlink, returning
+/// the decrypted FPort and FRMPayload
+fn transmit_uplink(ed: &mut EndDevice<TestClock>) -> (Option<u8>, Vec<u8>) {
+    use lorawan::mac_frame::{frm_payload_crypt, Direction, UplinkFrameControl};
+    use lorawan::state::{Action, RadioEvent};
+
+    let mut frame = match ed.next_action(Instant::new(0), &mut TestRng(0)) {
+        Action::Transmit { frame, .. } => frame.to_vec(),
+        a => panic!("unexpected action {:?}", a),
+    };
+    ed.handle_event(RadioEvent::TxDone(Instant::new(0)), &mut TestRng(0));
+    ed.handle_event(RadioEvent::Timeout(Instant::new(0)), &mut TestRng(0));
+    ed.handle_event(RadioEvent::Timeout(Instant::new(0)), &mut TestRng(0));
+
+    let fopts_len = UplinkFrameControl::from_bytes([frame[5]]).frame_opts_len() as usize;
+    let frame_count = u16::from_le_bytes([frame[6], frame[7]]);
+    let fport_index = 8 + fopts_len;
+    let mic_start = frame.len() - 4;
+    if fport_index == mic_start {
+        return (None, vec![]);
+    }
+    let fport = frame[fport_index];
+    let key = if fport == 0 { &NWK_SKEY } else { &APP_SKEY };
+    let payload = &mut frame[fport_index + 1..mic_start];
+    frm_payload_crypt(
+        key,
+        Direction::Uplink,
+        lorawan::DevAddr { addr: DEV_ADDR },
+        frame_count.into(),
+        payload,
+    );
+    (Some(fport), payload.to_vec())
+}
+
+#[test]
+fn mac_answers_too_long_for_fopts() {
+    use lorawan::state::UplinkError;
+
+    // six DevStatusAns (18 bytes) don't fit in FOpts, so they get an FPort 0 uplink and the
+    // application payload follows
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.process_mac_commands(recv_meta(0), &[0x06; 6]).unwrap();
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+    let (fport, payload) = transmit_uplink(&mut ed);
+    assert_eq!(fport, Some(0));
+    assert_eq!(payload.len(), 18);
+    assert!(payload.chunks(3).all(|a| a[0] == 0x06));
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+
+    assert_eq!(
+        ed.send_uplink_unconfirmed(1, b"again"),
+        Err(UplinkError::Busy)
+    );
+    assert_eq!(transmit_uplink(&mut ed), (Some(1), b"hello".to_vec()));
+    assert!(ed.queued_uplink.is_none());
+    assert_eq!(ed.frame_count_uplink, 2);
+
+    // four (12 bytes) fit in FOpts, but only three fit in an uplink at US915 DR0 (M = 19)
+    let mut ed = abp_device(lorawan::BandId::US915);
+    ed.process_mac_commands(recv_meta(0), &[0x06; 4]).unwrap();
+    ed.send_uplink_unconfirmed(1, b"hi").unwrap();
+    let (fport, payload) = transmit_uplink(&mut ed);
+    assert_eq!((fport, payload.len()), (Some(0), 9));
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+    assert_eq!(transmit_uplink(&mut ed), (Some(1), b"hi".to_vec()));
+}
+
+#[test]
+fn class_a_downlink_rejected() {
+    use lorawan::state::{Action, DownlinkError, RadioEvent};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.frame_count_downlink = 5;
+
+    // replayed frame counter
+    let mut frame = downlink(false, 4, &[], Some(1), b"old");
+    assert_eq!(
+        ed.process_downlink(recv_meta(0), &mut frame).unwrap_err(),
+        DownlinkError::Replayed
+    );
+    assert_eq!(ed.frame_count_downlink, 5);
+
+    // corrupted
+    let mut frame = downlink(false, 5, &[], Some(1), b"new");
+    frame[9] ^= 1;
+    assert_eq!(
+        ed.process_downlink(recv_meta(0), &mut frame).unwrap_err(),
+        DownlinkError::MicMismatch
+    );
+
+    // MAC commands in both FOpts and FRMPayload
+    let mut frame = downlink(false, 5, &[0x06], Some(0), &[0x06]);
+    assert_eq!(
+        ed.process_downlink(recv_meta(0), &mut frame).unwrap_err(),
+        DownlinkError::Malformed
+    );
+
+    // a frame for another device in RX1 doesn't stop RX2 from being opened
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+    ed.next_action(Instant::new(0), &mut TestRng(0));
+    ed.handle_event(RadioEvent::TxDone(Instant::new(0)), &mut TestRng(0));
+    let mut frame = downlink(false, 5, &[], Some(1), b"new");
+    frame[1] ^= 1;
+    assert!(ed
+        .handle_event(
+            RadioEvent::Rx(recv_meta(1_000_000), &mut frame),
+            &mut TestRng(0)
+        )
+        .is_none());
+    assert!(matches!(
+        ed.next_action(Instant::new(1_100_000), &mut TestRng(0)),
+        Action::Receive(rx2) if rx2.start == Instant::new(2_000_000)
+    ));
+
+    // gaps in the frame counter are allowed
+    let mut frame = downlink(false, 9, &[], Some(0), &[0x06]);
+    ed.process_downlink(recv_meta(0), &mut frame).unwrap();
+    assert_eq!(ed.frame_count_downlink, 10);
+
+    // the same frame again
+    let mut frame = downlink(false, 9, &[], Some(0), &[0x06]);
+    assert_eq!(
+        ed.process_downlink(recv_meta(0), &mut frame).unwrap_err(),
+        DownlinkError::Replayed
+    );
+
+    // far enough below to have wrapped into the next 64K epoch
+    ed.frame_count_downlink = 0x8000;
+    let mut frame = downlink(false, 0x1_0008, &[], Some(0), &[0x06]);
+    ed.process_downlink(recv_meta(0), &mut frame).unwrap();
+    assert_eq!(ed.frame_count_downlink, 0x1_0009);
+}
+
+#[test]
+fn confirmed_uplink_retransmission() {
+    use lorawan::state::{Action, Event, RadioEvent};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.num_transmits = 3;
+    ed.send_uplink_confirmed(1, b"hello").unwrap();
+
+    let mut now = 0;
+    let mut channels = Vec::new();
+    for attempt in 0..3 {
+        let frame = match ed.next_action(Instant::new(now), &mut TestRng(0)) {
+            Action::Transmit { details, frame } => {
+                channels.push(details.channel);
+                frame.to_vec()
+            }
+            a => panic!("unexpected action {:?}", a),
+        };
+        // retransmissions are the same frame, with the same frame counter
+        assert_eq!(frame[0], 0x80);
+        assert_eq!(&frame[6..8], &[0, 0]);
+        assert_eq!(ed.uplink.transmissions, attempt + 1);
+
+        ed.handle_event(RadioEvent::TxDone(Instant::new(now)), &mut TestRng(0));
+        ed.handle_event(
+            RadioEvent::Timeout(Instant::new(now + 1_100_000)),
+            &mut TestRng(0),
+        );
+        // half of RETRANSMIT_TIMEOUT_RANDOM
+        let event = ed.handle_event(
+            RadioEvent::Timeout(Instant::new(now + 2_100_000)),
+            &mut TestRng(1 << 31),
+        );
+        now += 2_100_000;
+        if attempt < 2 {
+            assert!(event.is_none());
+            let retry = now + 1_000_000 + 1_000_000;
+            assert!(matches!(
+                ed.next_action(Instant::new(now), &mut TestRng(0)),
+                Action::Sleep(Some(t)) if t == Instant::new(retry)
+            ));
+            now = retry;
+        } else {
+            assert!(matches!(event, Some(Event::AckTimeout)));
+        }
+    }
+    assert_ne!(channels[0], channels[1]);
+    assert_ne!(channels[1], channels[2]);
+    assert!(matches!(
+        ed.next_action(Instant::new(now), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+    assert_eq!(ed.frame_count_uplink, 1);
+
+    // an acknowledgement stops retransmissions
+    ed.send_uplink_confirmed(1, b"again").unwrap();
+    ed.next_action(Instant::new(now), &mut TestRng(0));
+    ed.handle_event(RadioEvent::TxDone(Instant::new(now)), &mut TestRng(0));
+    let fhdr = lorawan::mac_frame::FrameHeaderBuf {
+        dev_addr: lorawan::DevAddr { addr: DEV_ADDR },
+        // ACK
+        fctrl: 0x20,
+        frame_count: 0,
+        fopts: [0; 15],
+    };
+    let mut frame = [0u8; 255];
+    let len = lorawan::mac_frame::encode_data_frame(
+        &mut frame,
+        lorawan::mac_frame::FrameType::UnconfirmedDataDownlink,
+        &fhdr,
+        None,
+        b"",
+        &NWK_SKEY,
+        &APP_SKEY,
+    )
+    .unwrap();
+    let frame = &mut frame[..len];
+    match ed.handle_event(
+        RadioEvent::Rx(recv_meta(now + 1_000_000), frame),
+        &mut TestRng(0),
+    ) {
+        Some(Event::Downlink { ack: true, .. }) => {}
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert_eq!(ed.uplink.transmissions, 1);
+    assert!(matches!(
+        ed.next_action(Instant::new(now + 1_100_000), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+}
+
+#[test]
+fn unconfirmed_uplink_repetition() {
+    use lorawan::state::{Action, Event, RadioEvent};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.num_transmits = 2;
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+
+    // repeated right after RX2, with no acknowledgement timeout
+    ed.next_action(Instant::new(0), &mut TestRng(0));
+    ed.handle_event(RadioEvent::TxDone(Instant::new(0)), &mut TestRng(0));
+    ed.handle_event(
+        RadioEvent::Timeout(Instant::new(1_100_000)),
+        &mut TestRng(0),
+    );
+    assert!(ed
+        .handle_event(
+            RadioEvent::Timeout(Instant::new(2_100_000)),
+            &mut TestRng(0)
+        )
+        .is_none());
+    assert!(matches!(
+        ed.next_action(Instant::new(2_100_000), &mut TestRng(0)),
+        Action::Transmit { .. }
+    ));
+
+    // any downlink stops repetitions
+    ed.handle_event(RadioEvent::TxDone(Instant::new(2_200_000)), &mut TestRng(0));
+    let mut frame = downlink(false, 0, &[], Some(1), b"hi");
+    assert!(matches!(
+        ed.handle_event(
+            RadioEvent::Rx(recv_meta(3_200_000), &mut frame),
+            &mut TestRng(0)
+        ),
+        Some(Event::Downlink { ack: false, .. })
+    ));
+    assert!(matches!(
+        ed.next_action(Instant::new(3_300_000), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+
+    // the last repetition ends the uplink without an event
+    ed.send_uplink_unconfirmed(1, b"again").unwrap();
+    for t in [4_000_000, 7_000_000] {
+        assert!(matches!(
+            ed.next_action(Instant::new(t), &mut TestRng(0)),
+            Action::Transmit { .. }
+        ));
+        ed.handle_event(RadioEvent::TxDone(Instant::new(t)), &mut TestRng(0));
+        ed.handle_event(
+            RadioEvent::Timeout(Instant::new(t + 1_100_000)),
+            &mut TestRng(0),
+        );
+        assert!(ed
+            .handle_event(
+                RadioEvent::Timeout(Instant::new(t + 2_100_000)),
+                &mut TestRng(0)
+            )
+            .is_none());
+    }
+    assert!(matches!(
+        ed.next_action(Instant::new(9_200_000), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+}
+
+#[test]
+fn adr_backoff() {
+    use lorawan::{data_rate_backoff, AnyBand, Band, BandId, DataRate, Parameters};
+
+    let band = AnyBand::from_id(BandId::Eu868).unwrap();
+    let params = Parameters {
+        adr_ack_limit: 4,
+        adr_ack_delay: 2,
+        ..Parameters::default()
+    };
+
+    let b = data_rate_backoff(&params, &band, 3, DataRate::_5, 3, true);
+    assert!(!b.adr_ack_req);
+    let b = data_rate_backoff(&params, &band, 4, DataRate::_5, 3, true);
+    assert!(b.adr_ack_req);
+    assert_eq!((u8::from(b.data_rate), b.tx_power), (5, 3));
+    // TX power first, then the data rate
+    let b = data_rate_backoff(&params, &band, 6, DataRate::_5, 3, true);
+    assert_eq!((u8::from(b.data_rate), b.tx_power), (5, 0));
+    let b = data_rate_backoff(&params, &band, 7, DataRate::_5, 0, true);
+    assert_eq!(u8::from(b.data_rate), 5);
+    let b = data_rate_backoff(&params, &band, 8, DataRate::_5, 0, true);
+    assert_eq!(u8::from(b.data_rate), 4);
+    assert!(b.adr_ack_req);
+    // then the default channels
+    let b = data_rate_backoff(&params, &band, 8, DataRate::_0, 0, false);
+    assert!(b.channel_mask_reset);
+    assert!(!b.adr_ack_req);
+    let b = data_rate_backoff(&params, &band, 10, DataRate::_0, 0, true);
+    assert!(!b.channel_mask_reset);
+    assert!(!b.adr_ack_req);
+
+    // uplinks from an EndDevice
+    let mut ed = abp_device(BandId::Eu868);
+    ed.parameters = params;
+    ed.data_rate = DataRate::_5;
+    ed.tx_power_index = 3;
+    ed.uplink_channel_mask = 0b110;
+    let send = |ed: &mut EndDevice<TestClock>| {
+        use lorawan::state::{Action, RadioEvent};
+
+        ed.send_uplink_unconfirmed(1, b"").unwrap();
+        let fctrl = match ed.next_action(Instant::new(0), &mut TestRng(0)) {
+            Action::Transmit { frame, .. } => {
+                lorawan::mac_frame::UplinkFrameControl::from_bytes([frame[5]])
+            }
+            a => panic!("unexpected action {:?}", a),
+        };
+        ed.handle_event(RadioEvent::TxDone(Instant::new(0)), &mut TestRng(0));
+        ed.handle_event(RadioEvent::Timeout(Instant::new(0)), &mut TestRng(0));
+        ed.handle_event(RadioEvent::Timeout(Instant::new(0)), &mut TestRng(0));
+        assert_eq!(fctrl.adr(), ed.adr);
+        fctrl.adr_ack_req()
+    };
+    for _ in 0..4 {
+        assert!(!send(&mut ed));
+    }
+    assert!(send(&mut ed));
+    assert_eq!(ed.adr_ack_cnt, 5);
+    assert!(send(&mut ed));
+    assert_eq!((u8::from(ed.data_rate), ed.tx_power_index), (5, 3));
+    assert!(send(&mut ed));
+    assert_eq!((u8::from(ed.data_rate), ed.tx_power_index), (5, 0));
+    for _ in 0..11 {
+        send(&mut ed);
+    }
+    // the lowest data rate of the backoff table
+    assert_eq!(
+        band.backoff_data_rate(ed.data_rate).map(u8::from),
+        Some(u8::from(ed.data_rate))
+    );
+    assert_eq!(ed.uplink_channel_mask, 0b111);
+    assert!(!send(&mut ed));
+
+    // any downlink resets the backoff
+    let mut frame = downlink(false, 0, &[], Some(1), b"");
+    ed.process_downlink(recv_meta(0), &mut frame).unwrap();
+    assert_eq!(ed.adr_ack_cnt, 0);
+
+    // no backoff without ADR
+    ed.adr = false;
+    for _ in 0..20 {
+        assert!(!send(&mut ed));
+    }
+    assert_eq!(ed.adr_ack_cnt, 0);
+}
+
+#[test]
+fn uplink_channel_selection() {
+    use lorawan::{AnyBand, BandId, DataRate};
+
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(BandId::US915));
+    let band = AnyBand::from_id(BandId::US915).unwrap();
+    // sub-band 2
+    ed.uplink_channel_mask = (0xFF << 8) | (1 << 65);
+    let now = Instant::new(0);
+
+    // spread over the 8 enabled 125 kHz channels
+    let picks: Vec<u8> = [0, 1 << 29, 1 << 31, u32::MAX]
+        .into_iter()
+        .map(|r| {
+            ed.select_uplink_channel(&band, now, DataRate::_0, None, &mut TestRng(r))
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(picks, [8, 9, 12, 15]);
+
+    // the excluded channel is skipped unless it's the only usable one
+    assert_eq!(
+        ed.select_uplink_channel(&band, now, DataRate::_0, Some(8), &mut TestRng(0))
+            .unwrap(),
+        9
+    );
+    assert_eq!(
+        ed.select_uplink_channel(&band, now, DataRate::_4, Some(65), &mut TestRng(0))
+            .unwrap(),
+        65
+    );
+
+    // no enabled channel supports the data rate
+    ed.uplink_channel_mask = 0xFF << 8;
+    let err = ed
+        .select_uplink_channel(&band, now, DataRate::_4, None, &mut TestRng(0))
+        .unwrap_err();
+    assert!(err.earliest.is_none());
+
+    // all channels are unavailable due to the duty cycle
+    ed.duty_cycle_available = Some(Instant::new(5_000_000));
+    let err = ed
+        .select_uplink_channel(&band, now, DataRate::_0, None, &mut TestRng(0))
+        .unwrap_err();
+    assert_eq!(err.earliest, Some(Instant::new(5_000_000)));
+    assert_eq!(
+        ed.select_uplink_channel(
+            &band,
+            Instant::new(5_000_000),
+            DataRate::_0,
+            None,
+            &mut TestRng(0)
+        )
+        .unwrap(),
+        8
+    );
+}
+
+#[test]
+fn duty_cycle_follows_transmissions() {
+    use lorawan::state::{Action, RadioEvent};
+    use lorawan::{AnyBand, Band};
+
+    let band = AnyBand::from_id(lorawan::BandId::Eu868).unwrap();
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    // 50%
+    ed.max_duty_cycle = 1;
+
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+    let details = match ed.next_action(Instant::new(0), &mut TestRng(0)) {
+        Action::Transmit { details, .. } => details,
+        a => panic!("unexpected action {:?}", a),
+    };
+    let time_on_air = band
+        .uplink_time_on_air(details.data_rate, details.len)
+        .unwrap();
+    let tx_end = time_on_air.as_micros() as u64;
+    ed.handle_event(RadioEvent::TxDone(Instant::new(tx_end)), &mut TestRng(0));
+    assert_eq!(ed.duty_cycle_available, Some(Instant::new(2 * tx_end)));
+}
+
+#[test]
+fn sub_band_duty_cycle() {
+    use lorawan::{Band, BandId, DataRate, Frequency};
+
+    // every sub-band of the implemented bands is tracked
+    for band_id in [BandId::Eu868, BandId::US915, BandId::AS923] {
+        let band = lorawan::AnyBand::from_id(band_id).unwrap();
+        assert!(band.sub_bands().len() <= lorawan::SUB_BANDS_MAX);
+    }
+
+    let mut ed = EndDevice::<TestClock>::default();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    let band = ed.band().unwrap();
+    let g1 = Frequency::from_khz(868_100);
+    let g3 = Frequency::from_khz(869_525);
+
+    // 1% in g1, other sub-bands aren't affected
+    ed.uplink_transmitted(g1, Instant::new(1_000_000), Duration::from_millis(100));
+    let delayed = ed
+        .check_uplink_allowed(Instant::new(1_000_000), Frequency::from_khz(868_500))
+        .unwrap_err();
+    assert_eq!(delayed.earliest, Instant::new(10_900_000));
+    assert!(ed
+        .check_uplink_allowed(Instant::new(10_900_000), g1)
+        .is_ok());
+    assert!(ed.check_uplink_allowed(Instant::new(1_000_000), g3).is_ok());
+
+    // 10% in g3
+    ed.uplink_transmitted(g3, Instant::new(2_000_000), Duration::from_millis(100));
+    assert_eq!(
+        ed.check_uplink_allowed(Instant::new(2_000_000), g3)
+            .unwrap_err()
+            .earliest,
+        Instant::new(2_900_000)
+    );
+
+    // channel selection moves to a channel in another sub-band
+    ed.process_mac_request(recv_meta(0), new_channel(3, 8_671_000, 0, 5))
+        .unwrap();
+    assert_eq!(
+        ed.select_uplink_channel(
+            &band,
+            Instant::new(3_000_000),
+            DataRate::_0,
+            None,
+            &mut TestRng(0)
+        )
+        .unwrap(),
+        3
+    );
+    ed.uplink_transmitted(
+        Frequency::from_khz(867_100),
+        Instant::new(3_000_000),
+        Duration::from_millis(50),
+    );
+    let err = ed
+        .select_uplink_channel(
+            &band,
+            Instant::new(3_000_000),
+            DataRate::_0,
+            None,
+            &mut TestRng(0),
+        )
+        .unwrap_err();
+    assert_eq!(err.earliest, Some(Instant::new(7_950_000)));
+
+    // disabled tracking stays disabled across band changes
+    ed.sub_band_duty_cycle = lorawan::SubBandDutyCycle::disabled();
+    ed.set_band_id(Some(lorawan::BandId::Eu868));
+    ed.uplink_transmitted(g1, Instant::new(8_000_000), Duration::from_millis(100));
+    assert!(ed.check_uplink_allowed(Instant::new(8_000_000), g1).is_ok());
+}
+
+#[test]
+fn dwell_time() {
+    use lorawan::state::UplinkError;
+    use lorawan::{BandId, DataRate, DWELL_TIME_LIMIT};
+
+    // always limited in US915, which its payload sizes already account for
+    let mut ed = abp_device(BandId::US915);
+    let band = ed.band().unwrap();
+    assert_eq!(ed.uplink_dwell_time_limit(&band), Some(DWELL_TIME_LIMIT));
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_0), Some(19));
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_1), Some(61));
+    assert_eq!(
+        ed.send_uplink_unconfirmed(1, &[0; 12]),
+        Err(UplinkError::PayloadTooLong { max: 11 })
+    );
+    ed.send_uplink_unconfirmed(1, &[0; 11]).unwrap();
+
+    // limited in EU868 only for the test, as if set by TxParamSetupReq
+    let mut ed = abp_device(BandId::Eu868);
+    let band = ed.band().unwrap();
+    assert_eq!(ed.uplink_dwell_time_limit(&band), None);
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_0), Some(59));
+    ed.uplink_dwell_time = Some(DWELL_TIME_LIMIT);
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_0), None);
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_1), None);
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_2), Some(19));
+    assert_eq!(
+        ed.send_uplink_unconfirmed(1, b"hello"),
+        Err(UplinkError::DwellTimeExceeded)
+    );
+
+    // data rates exceeding the dwell time are rejected
+    let req = |data_rate| {
+        let b = mac::LinkAdrReq::new()
+            .with_data_rate(data_rate)
+            .with_tx_power(0xF)
+            .with_ch_mask(0x0007)
+            .into_bytes();
+        [0x03, b[0], b[1], b[2], b[3]]
+    };
+    ed.process_mac_commands(recv_meta(0), &req(1)).unwrap();
+    assert_eq!(link_adr_answers(&ed), vec![(true, false, true)]);
+    assert_eq!(u8::from(ed.data_rate), 0);
+    ed.uplink_mac_answers_sent();
+    ed.process_mac_commands(recv_meta(0), &req(2)).unwrap();
+    assert_eq!(link_adr_answers(&ed), vec![(true, true, true)]);
+    ed.uplink_mac_answers_sent();
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+
+    // the ADR backoff doesn't go below them either
+    let mut ed = abp_device(BandId::Eu868);
+    ed.uplink_dwell_time = Some(DWELL_TIME_LIMIT);
+    ed.data_rate = DataRate::_2;
+    ed.adr_ack_cnt = u32::from(ed.parameters.adr_ack_limit + ed.parameters.adr_ack_delay);
+    ed.send_uplink_unconfirmed(1, b"").unwrap();
+    assert_eq!(u8::from(ed.data_rate), 2);
+}
+
+#[test]
+fn tx_param_setup_req() {
+    use lorawan::{BandId, DataRate, DWELL_TIME_LIMIT};
+
+    // AS923 starts out with the uplink dwell time limited
+    let mut ed = abp_device(BandId::AS923);
+    let band = ed.band().unwrap();
+    assert_eq!(ed.uplink_dwell_time_limit(&band), Some(DWELL_TIME_LIMIT));
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_0), None);
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_2), Some(19));
+    assert_eq!(ed.effective_max_eirp_dbm(), Some(16));
+
+    let req = |max_eirp, uplink, downlink| {
+        mac::ReqFromNetworkServer::TxParamSetup(
+            mac::TxParamSetupReq::new()
+                .with_max_eirp(max_eirp)
+                .with_uplink_dwell_time(uplink)
+                .with_downlink_dwell_time(downlink),
+        )
+    };
+
+    // lift the uplink limit, limit downlinks and raise MaxEIRP to 24 dBm
+    ed.process_mac_request(recv_meta(0), req(9, false, true))
+        .unwrap();
+    assert!(matches!(
+        ed.uplink_mac_answers().collect::<Vec<_>>()[..],
+        [mac::AnsFromEndDevice::TxParamSetup]
+    ));
+    assert_eq!(ed.uplink_dwell_time_limit(&band), None);
+    assert_eq!(ed.maximum_payload_size(&band, DataRate::_0), Some(59));
+    assert_eq!(ed.parameters.downlink_dwell_time, DWELL_TIME_LIMIT);
+    assert_eq!(ed.max_eirp_dbm, Some(24));
+    assert_eq!(ed.effective_max_eirp_dbm(), Some(24));
+    ed.uplink_mac_answers_sent();
+
+    // and back
+    ed.process_mac_request(recv_meta(0), req(5, true, false))
+        .unwrap();
+    assert_eq!(ed.uplink_dwell_time_limit(&band), Some(DWELL_TIME_LIMIT));
+    assert!(ed.parameters.downlink_dwell_time.is_zero());
+    assert_eq!(ed.effective_max_eirp_dbm(), Some(16));
+
+    // ignored without dwell time limits
+    let mut ed = abp_device(BandId::Eu868);
+    assert!(ed
+        .process_mac_request(recv_meta(0), req(9, true, true))
+        .is_err());
+    assert_eq!(ed.uplink_mac_answers().count(), 0);
+    assert_eq!(ed.uplink_dwell_time, None);
+}
+
+#[test]
+fn max_app_payload_len() {
+    use lorawan::state::UplinkError;
+    use lorawan::DataRate;
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    assert!(EndDevice::<TestClock>::default()
+        .max_app_payload_len()
+        .is_none());
+
+    // N for DR0 and DR5
+    assert_eq!(ed.max_app_payload_len(), Some(51));
+    ed.data_rate = DataRate::_5;
+    assert_eq!(ed.max_app_payload_len(), Some(222));
+    ed.data_rate = DataRate::_0;
+
+    // a DevStatusAns in FOpts
+    ed.process_mac_commands(recv_meta(0), &[0x06]).unwrap();
+    assert_eq!(ed.max_app_payload_len(), Some(48));
+    assert_eq!(
+        ed.send_uplink_unconfirmed(1, &[0; 49]),
+        Err(UplinkError::PayloadTooLong { max: 48 })
+    );
+    assert_eq!(ed.pending_mac_answers.len(), 1);
+    ed.send_uplink_unconfirmed(1, &[0; 48]).unwrap();
+
+    // six DevStatusAns (18 bytes) don't fit in FOpts, and go in an uplink of their own
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.data_rate = DataRate::_5;
+    ed.process_mac_commands(recv_meta(0), &[0x06; 6]).unwrap();
+    assert_eq!(ed.max_app_payload_len(), Some(222));
+    assert_eq!(
+        ed.send_uplink_unconfirmed(1, &[0; 223]),
+        Err(UplinkError::PayloadTooLong { max: 222 })
+    );
+    ed.send_uplink_unconfirmed(1, &[0; 222]).unwrap();
+    assert_eq!(ed.queued_uplink.unwrap().payload().len(), 222);
+}
+
+/// EU868 beacon from the LoRaWAN 1.0.4 specification, 13.4 Beacon Encoding Examples
+const EU868_BEACON: [u8; 17] = [
+    0x00, 0x00, 0x00, 0x00, 0x02, 0xCC, 0xA2, 0x7E, 0x00, 0x01, 0x20, 0x00, 0x00, 0x81, 0x03, 0xDE,
+    0x55,
+];
+const EU868_BEACON_TIME: u64 = 0xCC02_0000;
+
+#[test]
+fn device_time_ans() {
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+
+    // DeviceTimeAns: 1_000_000_000 s and 128/256 s
+    let mut ans = vec![0x0D];
+    ans.extend_from_slice(&1_000_000_000u32.to_le_bytes());
+    ans.push(128);
+    ed.process_mac_commands(recv_meta(2_000_000), &ans).unwrap();
+    assert!(ed.device_time.is_none());
+
+    // refers to the end of the uplink
+    ed.previous_transmit_time = Some(Instant::new(1_000_000));
+    ed.process_mac_commands(recv_meta(2_000_000), &ans).unwrap();
+    let time = ed.device_time.unwrap();
+    assert_eq!(time.local, Instant::new(1_000_000));
+    assert_eq!(time.gps, Duration::from_millis(1_000_000_000_500));
+    assert_eq!(time.accuracy, lorawan::class_b::DEVICE_TIME_ACCURACY);
+}
+
+#[test]
+fn beacon_freq_req() {
+    use lorawan::class_b::{GpsTime, DEVICE_TIME_ACCURACY};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.start_class_b(GpsTime {
+        local: Instant::new(0),
+        gps: Duration::from_secs(EU868_BEACON_TIME - 28),
+        accuracy: DEVICE_TIME_ACCURACY,
+    })
+    .unwrap();
+    let frequency = |ed: &EndDevice<TestClock>| {
+        let (_, window) = ed.next_beacon_window(Instant::new(0)).unwrap();
+        window.frequency.khz
+    };
+    let answer = |ed: &mut EndDevice<TestClock>| {
+        let answers: Vec<_> = ed.uplink_mac_answers().collect();
+        ed.uplink_mac_answers_sent();
+        match answers[..] {
+            [mac::AnsFromEndDevice::BeaconFreq(a)] => a.beacon_frequency_ok(),
+            _ => panic!("unexpected answers {:?}", answers),
+        }
+    };
+    assert_eq!(frequency(&ed), 869_525);
+
+    // BeaconFreqReq, 868.1 MHz
+    ed.process_mac_commands(recv_meta(0), &[0x13, 0x28, 0x76, 0x84])
+        .unwrap();
+    assert!(answer(&mut ed));
+    assert_eq!(frequency(&ed), 868_100);
+
+    // outside of the band
+    ed.process_mac_commands(recv_meta(0), &[0x13, 0x40, 0x0D, 0x03])
+        .unwrap();
+    assert!(!answer(&mut ed));
+    assert_eq!(frequency(&ed), 868_100);
+
+    // back to the default
+    ed.process_mac_commands(recv_meta(0), &[0x13, 0, 0, 0])
+        .unwrap();
+    assert!(answer(&mut ed));
+    assert_eq!(frequency(&ed), 869_525);
+}
+
+#[test]
+fn class_b_beacon_tracking() {
+    use lorawan::class_b::{ClassBError, GpsTime, DEVICE_TIME_ACCURACY};
+    use lorawan::state::{Action, Event, RadioEvent};
+    use lorawan::{BandId, DataRate, DeviceClass};
+
+    let time = GpsTime {
+        local: Instant::new(1_000_000),
+        gps: Duration::from_secs(EU868_BEACON_TIME - 28),
+        accuracy: DEVICE_TIME_ACCURACY,
+    };
+    assert_eq!(
+        EndDevice::<TestClock>::default().start_class_b(time),
+        Err(ClassBError::NotActivated)
+    );
+    let mut ed = abp_device(BandId::Eu868);
+    ed.start_class_b(time).unwrap();
+
+    // 28.0015 s away, widened by the time's accuracy and 20 ppm of that
+    let widening = Duration::from_nanos(100_560_030);
+    let window = match ed.next_action(Instant::new(1_000_000), &mut TestRng(0)) {
+        Action::Sleep(Some(start)) => ed.next_action(start, &mut TestRng(0)),
+        a => panic!("unexpected action {:?}", a),
+    };
+    let window = match window {
+        Action::ReceiveBeacon(window) => window,
+        a => panic!("unexpected action {:?}", a),
+    };
+    assert_eq!(window.start, Instant::new(1_000_000 + 27_900_939));
+    // and the 10 symbol preamble at SF9
+    assert_eq!(window.timeout, 2 * widening + Duration::from_micros(40_960));
+    assert_eq!(window.frequency.khz, 869_525);
+    assert_eq!(u8::from(window.data_rate), u8::from(DataRate::_3));
+
+    // received 152.576 ms after it started
+    let mut beacon = EU868_BEACON;
+    let rx_end = 1_000_000 + 28_001_500 + 152_576;
+    match ed.handle_event(
+        RadioEvent::Rx(recv_meta(rx_end), &mut beacon),
+        &mut TestRng(0),
+    ) {
+        Some(Event::BeaconLocked(beacon)) => assert_eq!(u64::from(beacon.time), EU868_BEACON_TIME),
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert!(matches!(ed.class, DeviceClass::B));
+
+    // the next beacon is widened only by the clock drift over a beacon period
+    let next_start = rx_end + 127_844_867;
+    let (beacon_time, window) = ed.next_beacon_window(Instant::new(rx_end)).unwrap();
+    assert_eq!(u64::from(beacon_time), EU868_BEACON_TIME + 128);
+    assert_eq!(window.start, Instant::new(next_start));
+
+    // uplinks which would still be in their receive windows at the beacon guard wait for the beacon
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+    assert!(matches!(
+        ed.next_action(Instant::new(next_start - 4_000_000), &mut TestRng(0)),
+        Action::Sleep(Some(start)) if start == Instant::new(next_start)
+    ));
+    match ed.next_action(Instant::new(next_start), &mut TestRng(0)) {
+        Action::ReceiveBeacon(window) => {
+            assert_eq!(window.timeout, Duration::from_nanos(46_073_896))
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+
+    // a missed beacon widens the following window further
+    let timeout_end = next_start + 46_074;
+    assert!(matches!(
+        ed.handle_event(
+            RadioEvent::Timeout(Instant::new(timeout_end)),
+            &mut TestRng(0)
+        ),
+        Some(Event::BeaconLost)
+    ));
+    assert!(matches!(
+        ed.next_action(Instant::new(timeout_end), &mut TestRng(0)),
+        Action::Transmit { .. }
+    ));
+    assert!(ed
+        .handle_event(
+            RadioEvent::TxDone(Instant::new(timeout_end + 100_000)),
+            &mut TestRng(0)
+        )
+        .is_none());
+    ed.handle_event(
+        RadioEvent::Timeout(Instant::new(timeout_end + 1_200_000)),
+        &mut TestRng(0),
+    );
+    ed.handle_event(
+        RadioEvent::Timeout(Instant::new(timeout_end + 2_200_000)),
+        &mut TestRng(0),
+    );
+    let (_, window) = ed
+        .next_beacon_window(Instant::new(timeout_end + 2_200_000))
+        .unwrap();
+    assert_eq!(window.start, Instant::new(rx_end + 255_842_307));
+
+    ed.stop_class_b();
+    assert!(matches!(ed.class, DeviceClass::A));
+    assert!(matches!(
+        ed.next_action(Instant::new(timeout_end + 2_200_000), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+}
+
+/// An EU868 device locked on [`EU868_BEACON`], which was received at the returned time (µs)
+fn class_b_device() -> (EndDevice<TestClock>, u64) {
+    use lorawan::class_b::{GpsTime, DEVICE_TIME_ACCURACY};
+    use lorawan::state::{Action, Event, RadioEvent};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.start_class_b(GpsTime {
+        local: Instant::new(0),
+        gps: Duration::from_secs(EU868_BEACON_TIME - 10),
+        accuracy: DEVICE_TIME_ACCURACY,
+    })
+    .unwrap();
+    let (_, window) = ed.next_beacon_window(Instant::new(0)).unwrap();
+    assert!(matches!(
+        ed.next_action(window.start, &mut TestRng(0)),
+        Action::ReceiveBeacon(_)
+    ));
+    let rx_end = 10_001_500 + 152_576;
+    let mut beacon = EU868_BEACON;
+    assert!(matches!(
+        ed.handle_event(
+            RadioEvent::Rx(recv_meta(rx_end), &mut beacon),
+            &mut TestRng(0)
+        ),
+        Some(Event::BeaconLocked(_))
+    ));
+    (ed, rx_end)
+}
+
+#[test]
+fn class_b_ping_slots() {
+    use lorawan::class_b::{ping_offset, ping_period};
+    use lorawan::state::{Action, Event, RadioEvent};
+    use lorawan::{DataRate, DevAddr};
+
+    // Rand starts with 06 AF for the first beacon, C1 18 for the next
+    let dev_addr = DevAddr { addr: DEV_ADDR };
+    assert_eq!(ping_period(7), 4096);
+    assert_eq!(ping_period(0), 32);
+    assert_eq!(ping_offset(EU868_BEACON_TIME as u32, dev_addr, 4096), 3846);
+    assert_eq!(ping_offset(EU868_BEACON_TIME as u32, dev_addr, 32), 6);
+    assert_eq!(
+        ping_offset(EU868_BEACON_TIME as u32 + 128, dev_addr, 4096),
+        2241
+    );
+
+    let (mut ed, rx_end) = class_b_device();
+
+    // a single ping slot per beacon period, 2.12 s + 3846 * 30 ms after it starts, widened by
+    // 20 ppm of the time since the beacon
+    let start = rx_end + 117_343_577;
+    assert!(matches!(
+        ed.next_action(Instant::new(rx_end), &mut TestRng(0)),
+        Action::Sleep(Some(s)) if s == Instant::new(start)
+    ));
+    match ed.next_action(Instant::new(start), &mut TestRng(0)) {
+        Action::ReceivePingSlot(window) => {
+            assert_eq!(window.start, Instant::new(start));
+            // and the 8 symbol preamble at SF9
+            assert_eq!(window.timeout, Duration::from_nanos(37_461_836));
+            assert_eq!(window.frequency.khz, 869_525);
+            assert_eq!(u8::from(window.data_rate), u8::from(DataRate::_3));
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+
+    // sticky answers are only cleared by Class A downlinks
+    ed.sticky_mac_answers
+        .push(mac::AnsFromEndDevice::RxTimingSetup)
+        .unwrap();
+    let mut frame = downlink(false, 0, &[], Some(1), b"ping");
+    match ed.handle_event(
+        RadioEvent::Rx(recv_meta(start + 50_000), &mut frame),
+        &mut TestRng(0),
+    ) {
+        Some(Event::Downlink { fport, payload, .. }) => {
+            assert_eq!(fport, Some(1));
+            assert_eq!(payload, b"ping");
+        }
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert_eq!(ed.sticky_mac_answers.len(), 1);
+
+    // the next one is after the beacon, at a different offset
+    let window = ed
+        .next_ping_slot_window(Instant::new(start + 50_000))
+        .unwrap();
+    let (_, beacon) = ed.next_beacon_window(Instant::new(start)).unwrap();
+    assert!(window.start > beacon.start);
+
+    // a ping slot every second, 32 slots apart
+    let (mut ed, rx_end) = class_b_device();
+    ed.parameters.ping_slot_periodicity = 0;
+    let window = ed.next_ping_slot_window(Instant::new(rx_end)).unwrap();
+    assert_eq!(window.start, Instant::new(rx_end + 2_145_881));
+    let window = ed
+        .next_ping_slot_window(Instant::new(rx_end + 2_200_000))
+        .unwrap();
+    assert_eq!(window.start, Instant::new(rx_end + 3_105_861));
+}
+
+#[test]
+fn ping_slot_channel_req() {
+    let (mut ed, rx_end) = class_b_device();
+    let window = |ed: &EndDevice<TestClock>| {
+        let window = ed.next_ping_slot_window(Instant::new(rx_end)).unwrap();
+        (window.frequency.khz, u8::from(window.data_rate))
+    };
+    let answer = |ed: &mut EndDevice<TestClock>| {
+        let answers: Vec<_> = ed.uplink_mac_answers().collect();
+        ed.uplink_mac_answers_sent();
+        match answers[..] {
+            [mac::AnsFromEndDevice::PingSlotChannel(a)] => {
+                (a.channel_frequency_ok(), a.data_rate_ok())
+            }
+            _ => panic!("unexpected answers {:?}", answers),
+        }
+    };
+    assert_eq!(window(&ed), (869_525, 3));
+
+    // PingSlotChannelReq, 868.1 MHz at DR5
+    ed.process_mac_commands(recv_meta(0), &[0x11, 0x28, 0x76, 0x84, 0x05])
+        .unwrap();
+    assert_eq!(answer(&mut ed), (true, true));
+    assert_eq!(window(&ed), (868_100, 5));
+
+    // an RFU data rate, nothing is changed
+    ed.process_mac_commands(recv_meta(0), &[0x11, 0, 0, 0, 0x0F])
+        .unwrap();
+    assert_eq!(answer(&mut ed), (true, false));
+    assert_eq!(window(&ed), (868_100, 5));
+
+    // back to the default frequency
+    ed.process_mac_commands(recv_meta(0), &[0x11, 0, 0, 0, 0x03])
+        .unwrap();
+    assert_eq!(answer(&mut ed), (true, true));
+    assert_eq!(window(&ed), (869_525, 3));
+}
+
+/// [`EU868_BEACON`] sent at another `time`
+fn eu868_beacon(time: u32) -> [u8; 17] {
+    let mut beacon = EU868_BEACON;
+    beacon[2..6].copy_from_slice(&time.to_le_bytes());
+    let crc = crc_0x8810::CRC_16_LORA.checksum(&beacon[..6]);
+    beacon[6..8].copy_from_slice(&crc.to_le_bytes());
+    beacon
+}
+
+#[test]
+fn class_b_beacon_loss() {
+    use lorawan::state::{Action, Event, RadioEvent};
+    use lorawan::DeviceClass;
+
+    assert_eq!(eu868_beacon(EU868_BEACON_TIME as u32), EU868_BEACON);
+
+    // uplinks tell the Network Server the device is in Class B
+    let (mut ed, rx_end) = class_b_device();
+    ed.send_uplink_unconfirmed(1, b"hello").unwrap();
+    match ed.next_action(Instant::new(rx_end), &mut TestRng(0)) {
+        Action::Transmit { frame, .. } => assert_eq!(frame[5] & 0x10, 0x10),
+        a => panic!("unexpected action {:?}", a),
+    }
+    ed.handle_event(RadioEvent::TxDone(Instant::new(rx_end)), &mut TestRng(0));
+    ed.handle_event(RadioEvent::Timeout(Instant::new(rx_end)), &mut TestRng(0));
+    ed.handle_event(RadioEvent::Timeout(Instant::new(rx_end)), &mut TestRng(0));
+
+    // miss a beacon, then receive the following one
+    let miss_beacon = |ed: &mut EndDevice<TestClock>| {
+        let (_, window) = ed.next_beacon_window(Instant::new(0))?;
+        assert!(matches!(
+            ed.next_action(window.start, &mut TestRng(0)),
+            Action::ReceiveBeacon(_)
+        ));
+        let end = embedded_time::duration::Microseconds(window.timeout.as_micros() as u32);
+        ed.handle_event(
+            RadioEvent::Timeout(window.start.checked_add(end).unwrap()),
+            &mut TestRng(0),
+        )
+    };
+    assert!(matches!(miss_beacon(&mut ed), Some(Event::BeaconLost)));
+    // ping slots continue
+    assert!(ed.next_ping_slot_window(Instant::new(rx_end)).is_some());
+    let (beacon_time, window) = ed.next_beacon_window(Instant::new(0)).unwrap();
+    assert_eq!(u64::from(beacon_time), EU868_BEACON_TIME + 256);
+    assert!(matches!(
+        ed.next_action(window.start, &mut TestRng(0)),
+        Action::ReceiveBeacon(_)
+    ));
+    let mut beacon = eu868_beacon(beacon_time);
+    match ed.handle_event(
+        RadioEvent::Rx(recv_meta(rx_end + 256_000_000), &mut beacon),
+        &mut TestRng(0),
+    ) {
+        Some(Event::BeaconReacquired(beacon)) => assert_eq!(beacon.time, beacon_time),
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert!(matches!(miss_beacon(&mut ed), Some(Event::BeaconLost)));
+
+    // without beacons for 120 minutes (56 beacon periods), the device falls back to Class A
+    for _ in 0..54 {
+        assert!(miss_beacon(&mut ed).is_none());
+    }
+    assert!(matches!(miss_beacon(&mut ed), Some(Event::ClassBTimedOut)));
+    assert!(matches!(ed.class, DeviceClass::A));
+    assert!(ed.next_beacon_window(Instant::new(0)).is_none());
+    assert!(ed.next_ping_slot_window(Instant::new(0)).is_none());
+}
+
+#[test]
+fn class_c_continuous_reception() {
+    use lorawan::state::{Action, Event, RadioEvent};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    ed.start_class_c().unwrap();
+    assert!(matches!(ed.class, lorawan::DeviceClass::C));
+    match ed.next_action(Instant::new(0), &mut TestRng(0)) {
+        Action::ReceiveContinuous {
+            window,
+            until: None,
+        } => {
+            assert_eq!(window.start, Instant::new(0));
+            assert_eq!(
+                (window.frequency.khz, u8::from(window.data_rate)),
+                (869_525, 0)
+            );
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+
+    // a confirmed downlink is acknowledged right away by an empty uplink
+    let mut frame = downlink(true, 0, &[], Some(2), b"on");
+    match ed.handle_event(
+        RadioEvent::Rx(recv_meta(500_000), &mut frame),
+        &mut TestRng(0),
+    ) {
+        Some(Event::Downlink {
+            fport: Some(2),
+            payload,
+            ..
+        }) => assert_eq!(payload, b"on"),
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert_eq!(ed.class_c.ack_deadline, Some(Instant::new(8_500_000)));
+    match ed.next_action(Instant::new(600_000), &mut TestRng(0)) {
+        Action::Transmit { frame, .. } => {
+            // MHDR | DevAddr | FCtrl | FCnt | MIC
+            assert_eq!(frame.len(), 1 + 7 + 4);
+            assert_eq!(frame[0], 0x40);
+            let fctrl = lorawan::mac_frame::UplinkFrameControl::from_bytes([frame[5]]);
+            assert!(fctrl.ack());
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+    assert!(!ed.ack_pending);
+
+    // listening continuously before RX1 and between RX1 and RX2
+    ed.handle_event(RadioEvent::TxDone(Instant::new(700_000)), &mut TestRng(0));
+    match ed.next_action(Instant::new(700_000), &mut TestRng(0)) {
+        Action::ReceiveContinuous { window, until } => {
+            assert_eq!(window.frequency.khz, 869_525);
+            assert_eq!(until, Some(Instant::new(1_700_000)));
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+    assert!(ed
+        .handle_event(
+            RadioEvent::Timeout(Instant::new(1_700_000)),
+            &mut TestRng(0)
+        )
+        .is_none());
+    match ed.next_action(Instant::new(1_700_000), &mut TestRng(0)) {
+        Action::Receive(rx1) => assert_eq!(rx1.start, Instant::new(1_700_000)),
+        a => panic!("unexpected action {:?}", a),
+    }
+    ed.handle_event(
+        RadioEvent::Timeout(Instant::new(1_800_000)),
+        &mut TestRng(0),
+    );
+    match ed.next_action(Instant::new(1_800_000), &mut TestRng(0)) {
+        Action::ReceiveContinuous { until, .. } => {
+            assert_eq!(until, Some(Instant::new(2_700_000)))
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+    match ed.next_action(Instant::new(2_700_000), &mut TestRng(0)) {
+        Action::Receive(rx2) => assert_eq!(rx2.start, Instant::new(2_700_000)),
+        a => panic!("unexpected action {:?}", a),
+    }
+    ed.handle_event(
+        RadioEvent::Timeout(Instant::new(2_800_000)),
+        &mut TestRng(0),
+    );
+    assert!(matches!(
+        ed.next_action(Instant::new(2_800_000), &mut TestRng(0)),
+        Action::ReceiveContinuous { until: None, .. }
+    ));
+
+    // too late to acknowledge, the next uplink does
+    let mut frame = downlink(true, 1, &[], Some(2), b"off");
+    ed.handle_event(
+        RadioEvent::Rx(recv_meta(3_000_000), &mut frame),
+        &mut TestRng(0),
+    )
+    .unwrap();
+    assert!(matches!(
+        ed.next_action(Instant::new(11_000_000), &mut TestRng(0)),
+        Action::ReceiveContinuous { until: None, .. }
+    ));
+    assert!(ed.ack_pending);
+
+    ed.stop_class_c();
+    assert!(matches!(ed.class, lorawan::DeviceClass::A));
+    assert!(matches!(
+        ed.next_action(Instant::new(11_000_000), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+}
+
+#[test]
+fn class_c_multicast_session() {
+    use lorawan::state::{Action, Event, RadioEvent};
+
+    // a Class A device listens continuously during the session only
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    let mut group = multicast_group();
+    group.class_c = Some(lorawan::class_c::Session {
+        start: Instant::new(10_000_000),
+        end: Instant::new(20_000_000),
+        frequency: lorawan::Frequency::from_khz(869_525),
+        data_rate: lorawan::DataRate::_3,
+    });
+    ed.set_multicast_group(0, Some(group)).unwrap();
+    assert!(matches!(
+        ed.next_action(Instant::new(0), &mut TestRng(0)),
+        Action::Sleep(Some(t)) if t == Instant::new(10_000_000)
+    ));
+    match ed.next_action(Instant::new(10_000_000), &mut TestRng(0)) {
+        Action::ReceiveContinuous { window, until } => {
+            assert_eq!(
+                (window.frequency.khz, u8::from(window.data_rate)),
+                (869_525, 3)
+            );
+            assert_eq!(until, Some(Instant::new(20_000_000)));
+        }
+        a => panic!("unexpected action {:?}", a),
+    }
+    let mut frame = downlink(false, 0, &[], Some(3), b"fw");
+    assert!(matches!(
+        ed.handle_event(
+            RadioEvent::Rx(recv_meta(15_000_000), &mut frame),
+            &mut TestRng(0)
+        ),
+        Some(Event::Downlink { fport: Some(3), .. })
+    ));
+    assert!(matches!(
+        ed.next_action(Instant::new(20_000_000), &mut TestRng(0)),
+        Action::Sleep(None)
+    ));
+}
+
+const MC_ADDR: u32 = 0x01AB_CDEF;
+const MC_NWK_SKEY: [u8; 16] = [3; 16];
+const MC_APP_SKEY: [u8; 16] = [4; 16];
+
+/// A group accepting frame counters `10..=12`, without Class B or C parameters
+fn multicast_group() -> lorawan::MulticastGroup<TestClock> {
+    lorawan::MulticastGroup {
+        address: lorawan::DevAddr { addr: MC_ADDR },
+        network_session_key: MC_NWK_SKEY,
+        application_session_key: MC_APP_SKEY,
+        downlink_frame_counter: 10,
+        max_frame_count: 12,
+        class_b: None,
+        class_c: None,
+    }
+}
+
+fn multicast_downlink(
+    confirmed: bool,
+    frame_count: u32,
+    fport: u8,
+    payload: &[u8],
+    nwk_skey: &[u8; 16],
+) -> Vec<u8> {
+    use lorawan::mac_frame::{encode_data_frame, FrameHeaderBuf, FrameType};
+
+    let fhdr = FrameHeaderBuf {
+        dev_addr: lorawan::DevAddr { addr: MC_ADDR },
+        fctrl: 0,
+        frame_count,
+        fopts: [0; 15],
+    };
+    let ftype = if confirmed {
+        FrameType::ConfirmedDataDownlink
+    } else {
+        FrameType::UnconfirmedDataDownlink
+    };
+    let mut buf = [0u8; 255];
+    let len = encode_data_frame(
+        &mut buf,
+        ftype,
+        &fhdr,
+        Some(fport),
+        payload,
+        nwk_skey,
+        &MC_APP_SKEY,
+    )
+    .unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn multicast_group_downlinks() {
+    use lorawan::state::{Action, DownlinkError, Event, RadioEvent};
+
+    let mut ed = abp_device(lorawan::BandId::Eu868);
+    let mut group = multicast_group();
+    group.class_c = Some(lorawan::class_c::Session {
+        start: Instant::new(0),
+        end: Instant::new(100_000_000),
+        frequency: lorawan::Frequency::from_khz(869_525),
+        data_rate: lorawan::DataRate::_0,
+    });
+    assert_eq!(
+        ed.set_multicast_group(lorawan::MULTICAST_GROUPS_MAX as u8, Some(group)),
+        Err(lorawan::InvalidMulticastGroupId)
+    );
+    ed.set_multicast_group(2, Some(group)).unwrap();
+
+    let rx = |ed: &mut EndDevice<TestClock>, frame: &mut [u8]| {
+        assert!(matches!(
+            ed.next_action(Instant::new(1_000_000), &mut TestRng(0)),
+            Action::ReceiveContinuous { .. }
+        ));
+        match ed.handle_event(RadioEvent::Rx(recv_meta(1_000_000), frame), &mut TestRng(0)) {
+            Some(Event::MulticastDownlink {
+                group,
+                fport,
+                payload,
+            }) => Some((group, fport, payload.to_vec())),
+            None => None,
+            e => panic!("unexpected event {:?}", e),
+        }
+    };
+
+    let mut frame = multicast_downlink(false, 10, 200, b"update", &MC_NWK_SKEY);
+    assert_eq!(rx(&mut ed, &mut frame), Some((2, 200, b"update".to_vec())));
+    assert_eq!(ed.multicast_groups[2].unwrap().downlink_frame_counter, 11);
+    // replayed
+    let mut frame = multicast_downlink(false, 10, 200, b"update", &MC_NWK_SKEY);
+    assert_eq!(rx(&mut ed, &mut frame), None);
+    // above maxMcFCnt
+    let mut frame = multicast_downlink(false, 13, 200, b"update", &MC_NWK_SKEY);
+    assert_eq!(rx(&mut ed, &mut frame), None);
+    // wrong McNwkSKey
+    let mut frame = multicast_downlink(false, 12, 200, b"update", &NWK_SKEY);
+    assert_eq!(rx(&mut ed, &mut frame), None);
+    // multicast downlinks are never confirmed
+    let mut frame = multicast_downlink(true, 12, 200, b"update", &MC_NWK_SKEY);
+    assert_eq!(rx(&mut ed, &mut frame), None);
+    let mut frame = multicast_downlink(false, 12, 200, b"last", &MC_NWK_SKEY);
+    assert_eq!(rx(&mut ed, &mut frame), Some((2, 200, b"last".to_vec())));
+    assert_eq!(ed.frame_count_downlink, 0);
+
+    // unicast downlinks are still received in the session
+    let mut frame = downlink(false, 0, &[], Some(1), b"uni");
+    ed.next_action(Instant::new(1_000_000), &mut TestRng(0));
+    assert!(matches!(
+        ed.handle_event(
+            RadioEvent::Rx(recv_meta(1_000_000), &mut frame),
+            &mut TestRng(0)
+        ),
+        Some(Event::Downlink { fport: Some(1), .. })
+    ));
+
+    // but multicast downlinks aren't in Class A windows
+    let mut frame = multicast_downlink(false, 20, 200, b"update", &MC_NWK_SKEY);
+    ed.multicast_groups[2].as_mut().unwrap().max_frame_count = 100;
+    assert_eq!(
+        ed.process_downlink(recv_meta(1_000_000), &mut frame).err(),
+        Some(DownlinkError::NotForUs)
+    );
+}
+
+/// Start of the first ping slot of [`MC_ADDR`] after [`class_b_device`] locks on, with a ping slot
+/// every second: 2.12 s + 29 * 30 ms after the beacon period starts, widened by 20 ppm
+const MC_PING_SLOT: u64 = 2_835_867;
+
+#[test]
+fn multicast_group_ping_slots() {
+    use lorawan::class_b::PingSlots;
+    use lorawan::state::{Action, Event, RadioEvent};
+
+    let (mut ed, rx_end) = class_b_device();
+    let unicast = ed.next_ping_slot_window(Instant::new(rx_end)).unwrap();
+    assert_eq!(unicast.start, Instant::new(rx_end + 117_343_577));
+
+    // a ping slot every second, on a fixed frequency
+    let mut group = multicast_group();
+    group.class_b = Some(PingSlots {
+        periodicity: 0,
+        frequency: Some(lorawan::Frequency::from_khz(869_000)),
+        data_rate: lorawan::DataRate::_3,
+    });
+    ed.set_multicast_group(0, Some(group)).unwrap();
+    let window = ed.next_ping_slot_window(Instant::new(rx_end)).unwrap();
+    assert_eq!(window.start, Instant::new(rx_end + MC_PING_SLOT));
+    assert_eq!(
+        (window.frequency.khz, u8::from(window.data_rate)),
+        (869_000, 3)
+    );
+
+    assert!(matches!(
+        ed.next_action(window.start, &mut TestRng(0)),
+        Action::ReceivePingSlot(_)
+    ));
+    let mut frame = multicast_downlink(false, 10, 5, b"ping", &MC_NWK_SKEY);
+    match ed.handle_event(
+        RadioEvent::Rx(recv_meta(rx_end + MC_PING_SLOT + 100_000), &mut frame),
+        &mut TestRng(0),
+    ) {
+        Some(Event::MulticastDownlink {
+            group: 0,
+            fport: 5,
+            payload,
+        }) => assert_eq!(payload, b"ping"),
+        e => panic!("unexpected event {:?}", e),
+    }
+}
@@ -3,10 +3,47 @@
 fn lora_aa() {
     let buf = hex::decode("40F17DBE4900020001954378762B11FF0D").unwrap();
 
-    let _pkt = lorawan::mac_frame::PhyPayload::from_bytes(&buf[..]).unwrap();
+    let pkt = lorawan::mac_frame::PhyPayload::from_bytes(&buf[..]).unwrap();
+
+    let nw_skey = hex::decode("44024241ed4ce9a68c6a8bc055233fd3").unwrap();
+    let app_skey = hex::decode("ec925802ae430ca77fd3dd73cb2cc588").unwrap();
 
-    let _nw_skey = hex::decode("44024241ed4ce9a68c6a8bc055233fd3").unwrap();
-    let _app_skey = hex::decode("ec925802ae430ca77fd3dd73cb2cc588").unwrap();
+    let payload = check_uplink(&pkt, &nw_skey, &app_skey);
+    assert_eq!(&payload, b"test");
+}
+
+/// Verify the MIC of a data uplink and return its decrypted `FRMPayload`
+fn check_uplink(
+    pkt: &lorawan::mac_frame::PhyPayload<&[u8], lorawan::mac_frame::decode_state::Encrypted>,
+    nw_skey: &[u8],
+    app_skey: &[u8],
+) -> Vec<u8> {
+    use lorawan::mac_frame::{data_frame_mic, frm_payload_crypt, Direction, Payload};
+
+    let Payload::MacPayload(mac_payload) = pkt.payload().unwrap() else {
+        panic!("not a data frame");
+    };
+    let dev_addr = mac_payload.dev_addr();
+    let frame_count = mac_payload.frame_count() as u32;
+
+    let mut msg = vec![pkt.mac_header().into_bytes()[0]];
+    msg.extend(pkt.payload_bytes());
+    let nw_skey: [u8; 16] = nw_skey.try_into().unwrap();
+    assert_eq!(
+        data_frame_mic(&nw_skey, Direction::Uplink, dev_addr, frame_count, &msg),
+        pkt.mic()
+    );
+
+    assert_eq!(mac_payload.fport(), Some(1));
+    let mut payload = mac_payload.frm_paylod_bytes().to_vec();
+    frm_payload_crypt(
+        app_skey.try_into().unwrap(),
+        Direction::Uplink,
+        dev_addr,
+        frame_count,
+        &mut payload,
+    );
+    payload
 }
 
 // https://lorawan-packet-decoder-0ta6puiniaut.runkit.sh/?data=40AE130426800000016F895D98810714E3268295&nwkskey=99D58493D1205B43EFF938F0F66C339E&appskey=0A501524F8EA5FCBF9BDB5AD7D126F75
@@ -14,10 +51,13 @@ fn lora_aa() {
 fn lora_2() {
     let buf = hex::decode("40AE130426800000016F895D98810714E3268295").unwrap();
 
-    let _pkt = lorawan::mac_frame::PhyPayload::from_bytes(&buf[..]).unwrap();
+    let pkt = lorawan::mac_frame::PhyPayload::from_bytes(&buf[..]).unwrap();
+
+    let nw_skey = hex::decode("99D58493D1205B43EFF938F0F66C339E").unwrap();
+    let app_skey = hex::decode("0A501524F8EA5FCBF9BDB5AD7D126F75").unwrap();
 
-    let _nw_skey = hex::decode("99D58493D1205B43EFF938F0F66C339E").unwrap();
-    let _app_skey = hex::decode("0A501524F8EA5FCBF9BDB5AD7D126F75").unwrap();
+    let payload = check_uplink(&pkt, &nw_skey, &app_skey);
+    assert_eq!(&payload, b"abcdefg");
 }
 
 // example from https://lorawan-packet-decoder-0ta6puiniaut.runkit.sh/
@@ -37,7 +77,10 @@ fn join_request() {
 
     assert_eq!(u32::from_be_bytes(pkt.mic()), 0x030AF2C9);
 
-    assert_eq!(u32::from_be_bytes(pkt.mic_expected(&app_key)), 0x030AF2C9);
+    assert_eq!(
+        u32::from_be_bytes(pkt.mic_expected(&app_key).unwrap()),
+        0x030AF2C9
+    );
 
     let _payload = if let lorawan::mac_frame::Payload::JoinRequest(a) = pkt.payload().unwrap() {
         a
@@ -45,3 +88,185 @@ fn join_request() {
         panic!()
     };
 }
+
+// MAC command payloads from the LoRaWAN 1.1 specification's field diagrams
+#[test]
+fn lorawan_1_1_commands() {
+    use lorawan::mac::*;
+
+    // Minor in the low 4 bits
+    assert_eq!(ResetInd::new().with_minor(1).into_bytes(), [0x01]);
+    assert_eq!(RekeyInd::new().with_minor(1).into_bytes(), [0x01]);
+    assert_eq!(ResetConf::from_bytes([0x01]).minor(), 1);
+    assert_eq!(RekeyConf::from_bytes([0x01]).minor(), 1);
+
+    // Limit_exp in the high 4 bits, Delay_exp in the low 4 bits
+    let req = AdrParamSetupReq::from_bytes([0x21]);
+    assert_eq!((req.limit_exp(), req.delay_exp()), (2, 1));
+
+    // RFU | RejoinType | DR, then RFU | Period | Max_Retries
+    let req = ForceRejoinReq::from_bytes([0x25, 0x1A]);
+    assert_eq!(
+        (
+            req.rejoin_type(),
+            req.data_rate(),
+            req.period(),
+            req.max_retries()
+        ),
+        (2, 5, 3, 2)
+    );
+
+    // MaxTimeN in the high 4 bits, MaxCountN in the low 4 bits
+    let req = RejoinParamSetupReq::from_bytes([0x53]);
+    assert_eq!((req.max_time_n(), req.max_count_n()), (5, 3));
+    assert_eq!(
+        RejoinParamSetupAns::new().with_time_ok(true).into_bytes(),
+        [0x01]
+    );
+
+    assert_eq!(
+        DeviceModeInd::for_class(lorawan::DeviceClass::C)
+            .unwrap()
+            .into_bytes(),
+        [0x02]
+    );
+}
+
+#[test]
+fn link_adr_commands() {
+    use lorawan::mac::*;
+
+    // DataRate | TXPower, ChMask, RFU | ChMaskCntl | NbTrans
+    let req = LinkAdrReq::from_bytes([0x52, 0xFF, 0x00, 0x71]);
+    assert_eq!((req.data_rate(), req.tx_power()), (5, 2));
+    assert_eq!(req.ch_mask(), 0x00FF);
+    assert_eq!((req.channel_mask_ctrl(), req.nb_trans()), (7, 1));
+
+    // RFU | Power ACK | Data rate ACK | Channel mask ACK
+    assert_eq!(
+        LinkAdrAns::new().with_channel_mask_ack(true).into_bytes(),
+        [0x01]
+    );
+    assert_eq!(LinkAdrAns::new().with_power_ack(true).into_bytes(), [0x04]);
+}
+
+#[test]
+fn channel_commands() {
+    use lorawan::mac::*;
+
+    // ChIndex, Freq (868.1MHz), MaxDR | MinDR
+    let req = NewChannelReq::from_bytes([0x03, 0x28, 0x76, 0x84, 0x50]);
+    assert_eq!(req.channel_index(), 3);
+    assert_eq!(req.frequency(), 8_681_000);
+    assert_eq!((req.min_data_rate(), req.max_data_rate()), (0, 5));
+
+    // RFU | Data rate range ok | Channel frequency ok
+    assert_eq!(
+        NewChannelAns::new()
+            .with_channel_frequency_ok(true)
+            .into_bytes(),
+        [0x01]
+    );
+    assert_eq!(
+        NewChannelAns::new()
+            .with_data_rate_range_ok(true)
+            .into_bytes(),
+        [0x02]
+    );
+
+    // RFU | Uplink frequency exists | Channel frequency ok
+    assert_eq!(
+        DlChannelAns::new()
+            .with_channel_frequency_ok(true)
+            .into_bytes(),
+        [0x01]
+    );
+    assert_eq!(
+        DlChannelAns::new()
+            .with_uplink_frequency_exists(true)
+            .into_bytes(),
+        [0x02]
+    );
+}
+
+#[test]
+fn rx_tx_setup_commands() {
+    use lorawan::mac::*;
+
+    // RFU | RX1DRoffset | RX2DataRate, Frequency (869.525MHz)
+    let req = RxParamSetupReq::from_bytes([0x23, 0xD2, 0xAD, 0x84]);
+    assert_eq!((req.rx1_data_rate_offset(), req.rx2_data_rate()), (2, 3));
+    assert_eq!(req.frequency(), 8_695_250);
+
+    // RFU | RX1DRoffset ACK | RX2 Data rate ACK | Channel ACK
+    assert_eq!(
+        RxParamSetupAns::new().with_channel_ack(true).into_bytes(),
+        [0x01]
+    );
+    assert_eq!(
+        RxParamSetupAns::new()
+            .with_rx1_data_rate_offset_ack(true)
+            .into_bytes(),
+        [0x04]
+    );
+
+    // RFU | Del
+    assert_eq!(RxTimingSetupReq::from_bytes([0x05]).delay_seconds(), 5);
+    assert_eq!(RxTimingSetupReq::from_bytes([0x0F]).delay_seconds(), 15);
+
+    // RFU | DownlinkDwellTime | UplinkDwellTime | MaxEIRP
+    let req = TxParamSetupReq::from_bytes([0x2D]);
+    assert_eq!(req.max_eirp(), 0xD);
+    assert!(!req.uplink_dwell_time());
+    assert!(req.downlink_dwell_time());
+}
+
+#[test]
+fn class_b_commands() {
+    use lorawan::mac::*;
+
+    // PingSlotInfoAns, BeaconTimingAns, PingSlotChannelReq, BeaconFreqReq, DevStatusReq
+    let fopts = [
+        0x10, 0x12, 0x34, 0x12, 0x02, 0x11, 0x28, 0x76, 0x84, 0x03, 0x13, 0x28, 0x76, 0x84, 0x06,
+    ];
+    let cmds: Vec<_> = DownlinkCommands::new(&fopts, &())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(cmds.len(), 5);
+    assert!(matches!(
+        cmds[0],
+        FromNetworkServer::Ans(AnsFromNetworkServer::PingSlotInfo)
+    ));
+    match cmds[1] {
+        FromNetworkServer::Ans(AnsFromNetworkServer::BeaconTiming(ans)) => {
+            assert_eq!((ans.delay(), ans.channel()), (0x1234, 2))
+        }
+        c => panic!("unexpected command {:?}", c),
+    }
+    match cmds[2] {
+        FromNetworkServer::Req(ReqFromNetworkServer::PingSlotChannel(req)) => {
+            assert_eq!((req.frequency(), req.data_rate()), (8_681_000, 3))
+        }
+        c => panic!("unexpected command {:?}", c),
+    }
+    match cmds[3] {
+        FromNetworkServer::Req(ReqFromNetworkServer::BeaconFreq(req)) => {
+            assert_eq!(req.frequency(), 8_681_000)
+        }
+        c => panic!("unexpected command {:?}", c),
+    }
+    assert!(matches!(
+        cmds[4],
+        FromNetworkServer::Req(ReqFromNetworkServer::DevStatus)
+    ));
+
+    // RFU | Data rate OK | Channel frequency OK
+    let mut buf = [0u8; 2];
+    let ans = AnsFromEndDevice::PingSlotChannel(PingSlotChannelAns::new().with_data_rate_ok(true));
+    assert_eq!(ans.encode(&mut buf), Ok(2));
+    assert_eq!(buf, [0x11, 0x02]);
+    // RFU | Beacon frequency OK
+    let ans = AnsFromEndDevice::BeaconFreq(BeaconFreqAns::new().with_beacon_frequency_ok(true));
+    assert_eq!(ans.encode(&mut buf), Ok(2));
+    assert_eq!(buf, [0x13, 0x01]);
+}
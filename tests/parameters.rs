@@ -0,0 +1,88 @@
+use core::time::Duration;
+use lorawan::{AnyBand, Band, BandId, CodingRate, DataRate, Frequency, Modulation};
+
+fn lora(sf: u8, bw_khz: u32) -> Modulation {
+    Modulation::Lora {
+        sf,
+        bw: Frequency::from_khz(bw_khz),
+    }
+}
+
+/// A 13 byte PHYPayload is an uplink without FOpts or FRMPayload
+#[test]
+fn time_on_air_lora() {
+    let toa = |m: Modulation| m.uplink_time_on_air(13).unwrap();
+    assert_eq!(toa(lora(7, 125)), Duration::from_micros(46_336));
+    assert_eq!(toa(lora(9, 125)), Duration::from_micros(164_864));
+    assert_eq!(toa(lora(8, 500)), Duration::from_micros(20_608));
+    // low data rate optimization
+    assert_eq!(toa(lora(11, 125)), Duration::from_micros(577_536));
+    assert_eq!(toa(lora(12, 125)), Duration::from_micros(1_155_072));
+
+    // implicit header, no CRC and a longer coding rate
+    assert_eq!(
+        lora(7, 125)
+            .time_on_air(13, 8, CodingRate::Cr4_8, false, false)
+            .unwrap(),
+        Duration::from_micros(45_312)
+    );
+    assert!(lora(7, 125)
+        .time_on_air(13, 8, CodingRate::Cr1_3, true, true)
+        .is_none());
+}
+
+#[test]
+fn time_on_air_fsk_lr_fhss() {
+    // 5 byte preamble, 3 byte sync word, length, 13 bytes and CRC at 50 kbit/s
+    assert_eq!(
+        Modulation::Fsk { rate: 50 }.uplink_time_on_air(13),
+        Some(Duration::from_micros(3_840))
+    );
+
+    // 3 headers of 114 bits, then (13 + 2) * 8 + 6 bits at coding rate 1/3, each bit 2.048 ms
+    let lr_fhss = Modulation::LrFhss {
+        coding_rate: CodingRate::Cr1_3,
+        bandwidth: Frequency::from_khz(137),
+    };
+    assert_eq!(
+        lr_fhss.uplink_time_on_air(13),
+        Some(Duration::from_micros(1_474_560))
+    );
+
+    assert!(Modulation::Rfu.uplink_time_on_air(13).is_none());
+}
+
+#[test]
+fn band_uplink_time_on_air() {
+    let band = AnyBand::from_id(BandId::Eu868).unwrap();
+    assert_eq!(
+        band.uplink_time_on_air(DataRate::_5, 13),
+        Some(Duration::from_micros(46_336))
+    );
+}
+
+#[test]
+fn beacon_and_ping_slot_frequencies() {
+    use lorawan::DevAddr;
+
+    // US915 hops between 923.3 + 0.6 * n MHz every beacon period
+    let band = AnyBand::from_id(BandId::US915).unwrap();
+    assert_eq!(band.beacon_frequency(0).khz, 923_300);
+    assert_eq!(band.beacon_frequency(3 * 128).khz, 925_100);
+    assert_eq!(band.beacon_frequency(9 * 128).khz, 923_900);
+    // ping slots are offset by the DevAddr
+    let dev_addr = DevAddr { addr: 5 };
+    assert_eq!(band.ping_slot_frequency(dev_addr, 4 * 128).khz, 923_900);
+    assert_eq!(band.ping_slot_frequency(dev_addr, 6 * 128).khz, 925_100);
+    assert_eq!(
+        band.ping_slot_frequency(DevAddr { addr: u32::MAX }, 128)
+            .khz,
+        923_300
+    );
+    assert_eq!(u8::from(band.beacon_settings().dr), 8);
+
+    // EU868 uses a single frequency
+    let band = AnyBand::from_id(BandId::Eu868).unwrap();
+    assert_eq!(band.beacon_frequency(3 * 128).khz, 869_525);
+    assert_eq!(band.ping_slot_frequency(dev_addr, 4 * 128).khz, 869_525);
+}